@@ -0,0 +1,421 @@
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::net::{SocketAddr, TcpListener, TcpStream, ToSocketAddrs};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+
+use serde::Deserialize;
+use serde_json::{json, Value};
+
+use crate::explorer;
+use crate::hash::{hash_from_hex, Hash};
+use crate::metrics::Event;
+use crate::node::{ConnectionSlot, Node, Validation};
+use crate::transaction::{is_valid_address, Transaction};
+
+const CLIENT_TIMEOUT: Duration = Duration::from_secs(5);
+/// Largest request body accepted, a submitted transaction is a few hundred bytes
+const MAX_BODY_LEN: usize = 64 * 1024;
+/// Longest request line or header line accepted, line break included
+const MAX_LINE_LEN: u64 = 8 * 1024;
+/// Most header lines accepted in one request
+const MAX_HEADERS: usize = 64;
+/// Most requests served at once, each on its own thread. Further connections are closed
+/// right away.
+const MAX_CONNECTIONS: usize = 64;
+
+/// Start the HTTP query API of `node` on `address` (port 0 picks a free port) and serve it
+/// in the background. Returns the address it listens on.
+///
+/// Every response is JSON, except for the HTML block explorer under `/explorer` (see
+/// `explorer::page`) and the metrics. One request is served per connection.
+///
+/// - `GET /tip`: the last block of the best chain
+/// - `GET /blocks/height/<height>`: block of the best chain by height, the genesis block is height 1.
+///   A pruned node only has the blocks from the recent blocks of its snapshot on.
+/// - `GET /blocks/hash/<hash>`: block by hex hash, including blocks on side branches
+/// - `GET /blocks/hash/<hash>/proofs/<transaction>`: header of the block and a Merkle proof that
+///   the transaction is in it, which a light client checks with `BlockHeader::contains_transaction`
+/// - `GET /accounts/<address>`: balance and next nonce of an address at the tip of the best
+///   chain, also on a pruned node
+/// - `GET /status`: height, snapshot height, work, next difficulty and the result of the last
+///   full validation of the best chain
+/// - `POST /validate`: validate every block of the best chain again and answer the result. The
+///   chain is held while it runs, so this is for operators rather than routine polling.
+/// - `GET /metrics`: chain height, hash rate, blocks rejected by reason, peers, mempool size and
+///   more, in the Prometheus text exposition format (see `Node::metrics`)
+/// - `POST /transactions`: submit a signed transaction to be mined in a coming block
+/// - `POST /rpc`: JSON-RPC 2.0 with the methods `get_tip`, `get_block_by_height` (`{"height"}`),
+///   `get_block_by_hash` (`{"hash"}`), `get_transaction_proof` (`{"hash", "transaction"}`), `get_account` (`{"address"}`), `get_status` and `submit_transaction` (`{"transaction"}`)
+pub fn start<A: ToSocketAddrs>(address: A, node: Arc<Node>) -> io::Result<SocketAddr> {
+    let listener = TcpListener::bind(address)?;
+    let api_address = listener.local_addr()?;
+    node.log(Event::ApiStarted {
+        address: api_address,
+    });
+
+    let connections = Arc::new(AtomicUsize::new(0));
+    thread::spawn(move || {
+        for stream in listener.incoming() {
+            let Ok(stream) = stream else { continue };
+            if connections.fetch_add(1, Ordering::SeqCst) >= MAX_CONNECTIONS {
+                connections.fetch_sub(1, Ordering::SeqCst);
+                continue;
+            }
+            let node = Arc::clone(&node);
+            let connections = Arc::clone(&connections);
+            thread::spawn(move || {
+                let _slot = ConnectionSlot(&connections);
+                if let Err(e) = handle_connection(&node, stream) {
+                    node.log(Event::ConnectionError {
+                        error: e.to_string(),
+                    });
+                }
+            });
+        }
+    });
+    Ok(api_address)
+}
+
+/// Why a query could not be answered
+#[derive(Debug)]
+enum ApiError {
+    NotFound(String),
+    BadRequest(String),
+}
+
+impl ApiError {
+    fn status(&self) -> u16 {
+        match self {
+            ApiError::NotFound(_) => 404,
+            ApiError::BadRequest(_) => 400,
+        }
+    }
+
+    fn message(&self) -> &str {
+        match self {
+            ApiError::NotFound(message) | ApiError::BadRequest(message) => message,
+        }
+    }
+}
+
+fn handle_connection(node: &Node, mut stream: TcpStream) -> io::Result<()> {
+    stream.set_read_timeout(Some(CLIENT_TIMEOUT))?;
+    let mut reader = BufReader::new(&stream);
+
+    let (method, path, content_length) = match read_head(&mut reader) {
+        Ok(head) => head,
+        Err(e) if e.kind() == io::ErrorKind::InvalidData => {
+            let body = json!({ "error": e.to_string() });
+            return write_response(&mut stream, 431, &body);
+        }
+        Err(e) => return Err(e),
+    };
+
+    if method == "GET" {
+        if let Some(target) = path.strip_prefix("/explorer") {
+            let (status, html) = node.with_chain(|chain| explorer::page(chain, target));
+            return write_body(
+                &mut stream,
+                status,
+                "text/html; charset=utf-8",
+                html.as_bytes(),
+            );
+        }
+        if path == "/metrics" {
+            return write_body(
+                &mut stream,
+                200,
+                "text/plain; version=0.0.4",
+                node.metrics().as_bytes(),
+            );
+        }
+    }
+
+    let (status, body) = if content_length > MAX_BODY_LEN {
+        (413, json!({ "error": "Request body is too large." }))
+    } else {
+        let mut body = vec![0; content_length];
+        reader.read_exact(&mut body)?;
+        match route(node, &method, &path, &body) {
+            Ok(value) => (200, value),
+            Err(e) => (e.status(), json!({ "error": e.message() })),
+        }
+    };
+    write_response(&mut stream, status, &body)
+}
+
+/// Read the request line and headers, returning the method, the path and the content length.
+/// A line longer than `MAX_LINE_LEN` or more than `MAX_HEADERS` headers is `InvalidData`.
+fn read_head(reader: &mut impl BufRead) -> io::Result<(String, String, usize)> {
+    let request_line = read_line(reader)?;
+    let mut parts = request_line.split_whitespace();
+    let method = parts.next().unwrap_or_default().to_string();
+    let path = parts.next().unwrap_or_default().to_string();
+
+    let mut content_length = 0;
+    for _ in 0..=MAX_HEADERS {
+        let line = read_line(reader)?;
+        if line.trim().is_empty() {
+            return Ok((method, path, content_length));
+        }
+        if let Some((name, value)) = line.split_once(':') {
+            if name.trim().eq_ignore_ascii_case("content-length") {
+                content_length = value.trim().parse().unwrap_or(usize::MAX);
+            }
+        }
+    }
+    Err(io::Error::new(
+        io::ErrorKind::InvalidData,
+        format!("More than {} headers", MAX_HEADERS),
+    ))
+}
+
+/// Read a line of at most `MAX_LINE_LEN` bytes, without reading past it
+fn read_line(reader: &mut impl BufRead) -> io::Result<String> {
+    let mut line = String::new();
+    reader.take(MAX_LINE_LEN).read_line(&mut line)?;
+    if line.len() as u64 == MAX_LINE_LEN && !line.ends_with('\n') {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("Line longer than {} bytes", MAX_LINE_LEN),
+        ));
+    }
+    Ok(line)
+}
+
+fn route(node: &Node, method: &str, path: &str, body: &[u8]) -> Result<Value, ApiError> {
+    let segments = path.trim_matches('/').split('/').collect::<Vec<&str>>();
+    match (method, segments.as_slice()) {
+        ("GET", ["tip"]) => tip(node),
+        ("GET", ["blocks", "height", height]) => block_by_height(node, parse_height(height)?),
+        ("GET", ["blocks", "hash", hash]) => block_by_hash(node, hash),
+        ("GET", ["blocks", "hash", hash, "proofs", transaction]) => {
+            transaction_proof(node, hash, transaction)
+        }
+        ("GET", ["accounts", address]) => account(node, address),
+        ("GET", ["status"]) => Ok(status(node)),
+        ("POST", ["validate"]) => Ok(validation_json(&node.validate_chain())),
+        ("POST", ["transactions"]) => {
+            let transaction = serde_json::from_slice(body)
+                .map_err(|e| ApiError::BadRequest(format!("Invalid transaction: {}", e)))?;
+            submit_transaction(node, transaction)
+        }
+        ("POST", ["rpc"]) => Ok(rpc(node, body)),
+        _ => Err(ApiError::NotFound(format!(
+            "No route for {} {}",
+            method, path
+        ))),
+    }
+}
+
+fn parse_height(height: &str) -> Result<u64, ApiError> {
+    height
+        .parse()
+        .map_err(|_| ApiError::BadRequest(format!("Invalid height {}", height)))
+}
+
+fn tip(node: &Node) -> Result<Value, ApiError> {
+    node.tip()
+        .map(|block| json!(block))
+        .ok_or_else(|| ApiError::NotFound("The chain is empty.".to_string()))
+}
+
+fn block_by_height(node: &Node, height: u64) -> Result<Value, ApiError> {
+    node.with_chain(|chain| chain.block_at_height(height).map(|block| json!(block)))
+        .ok_or_else(|| ApiError::NotFound(format!("No block at height {}.", height)))
+}
+
+fn parse_hash(hash: &str) -> Result<Hash, ApiError> {
+    hash_from_hex(hash).ok_or_else(|| ApiError::BadRequest(format!("Invalid hash {}", hash)))
+}
+
+fn block_by_hash(node: &Node, hash: &str) -> Result<Value, ApiError> {
+    let parsed = parse_hash(hash)?;
+    node.with_chain(|chain| chain.tree().get(&parsed).map(|block| json!(block)))
+        .ok_or_else(|| ApiError::NotFound(format!("No block with hash {}.", hash)))
+}
+
+fn transaction_proof(node: &Node, hash: &str, transaction: &str) -> Result<Value, ApiError> {
+    let parsed = parse_hash(hash)?;
+    let transaction_hash = parse_hash(transaction)?;
+    let block = node
+        .with_chain(|chain| chain.tree().get(&parsed).cloned())
+        .ok_or_else(|| ApiError::NotFound(format!("No block with hash {}.", hash)))?;
+    let proof = block.transaction_proof(&transaction_hash).ok_or_else(|| {
+        ApiError::NotFound(format!("No transaction {} in block {}.", transaction, hash))
+    })?;
+    Ok(json!({ "header": block.header(), "proof": proof }))
+}
+
+fn account(node: &Node, address: &str) -> Result<Value, ApiError> {
+    if !is_valid_address(address) {
+        return Err(ApiError::BadRequest(format!("Invalid address {}", address)));
+    }
+    Ok(node.with_chain(|chain| {
+        json!({
+            "address": address,
+            "balance": chain.ledger().balance(address),
+            "next_nonce": chain.ledger().next_nonce(address),
+        })
+    }))
+}
+
+fn status(node: &Node) -> Value {
+    let pending = node.pending_transactions().len();
+    let peers = node.peers().len();
+    let validation = validation_json(&node.validation());
+    node.with_chain(|chain| {
+        json!({
+            "height": chain.blocks.last().map_or(0, |block| block.id),
+            "snapshot_height": chain.snapshot().map(|snapshot| snapshot.height),
+            "tip": chain.blocks.last().map(|block| hex::encode(block.hash)),
+            // Work can exceed what a JSON number holds exactly
+            "total_work": chain.total_work().to_string(),
+            "next_difficulty": chain.next_difficulty(),
+            "known_blocks": chain.tree().len(),
+            "pending_transactions": pending,
+            "peers": peers,
+            "validation": validation,
+        })
+    })
+}
+
+fn validation_json(validation: &Validation) -> Value {
+    match &validation.result {
+        Ok(()) => json!({ "valid": true, "height": validation.height }),
+        Err(e) => json!({
+            "valid": false,
+            "height": validation.height,
+            "first_bad_block": e.index,
+            "error": e.error.to_string(),
+        }),
+    }
+}
+
+fn submit_transaction(node: &Node, transaction: Transaction) -> Result<Value, ApiError> {
+    let id = node
+        .submit_transaction(transaction)
+        .map_err(|e| ApiError::BadRequest(e.to_string()))?;
+    Ok(json!({ "accepted": true, "id": hex::encode(id) }))
+}
+
+#[derive(Deserialize)]
+struct RpcRequest {
+    method: String,
+    #[serde(default)]
+    params: Value,
+    #[serde(default)]
+    id: Value,
+}
+
+#[derive(Deserialize)]
+struct HeightParams {
+    height: u64,
+}
+
+#[derive(Deserialize)]
+struct HashParams {
+    hash: String,
+}
+
+#[derive(Deserialize)]
+struct ProofParams {
+    hash: String,
+    transaction: String,
+}
+
+#[derive(Deserialize)]
+struct AddressParams {
+    address: String,
+}
+
+#[derive(Deserialize)]
+struct TransactionParams {
+    transaction: Transaction,
+}
+
+/// Answer a JSON-RPC 2.0 request. Errors are reported in the response body, not the HTTP status.
+fn rpc(node: &Node, body: &[u8]) -> Value {
+    let request: RpcRequest = match serde_json::from_slice(body) {
+        Ok(request) => request,
+        Err(e) => return rpc_error(Value::Null, -32700, &format!("Parse error: {}", e)),
+    };
+
+    let result = match request.method.as_str() {
+        "get_tip" => tip(node),
+        "get_block_by_height" => {
+            params::<HeightParams>(request.params).and_then(|p| block_by_height(node, p.height))
+        }
+        "get_block_by_hash" => {
+            params::<HashParams>(request.params).and_then(|p| block_by_hash(node, &p.hash))
+        }
+        "get_transaction_proof" => params::<ProofParams>(request.params)
+            .and_then(|p| transaction_proof(node, &p.hash, &p.transaction)),
+        "get_account" => {
+            params::<AddressParams>(request.params).and_then(|p| account(node, &p.address))
+        }
+        "get_status" => Ok(status(node)),
+        "submit_transaction" => params::<TransactionParams>(request.params)
+            .and_then(|p| submit_transaction(node, p.transaction)),
+        method => {
+            let message = format!("Method {} not found", method);
+            return rpc_error(request.id, -32601, &message);
+        }
+    };
+
+    match result {
+        Ok(result) => json!({ "jsonrpc": "2.0", "result": result, "id": request.id }),
+        Err(ApiError::BadRequest(message)) => rpc_error(request.id, -32602, &message),
+        Err(ApiError::NotFound(message)) => rpc_error(request.id, -32004, &message),
+    }
+}
+
+fn params<T: for<'de> Deserialize<'de>>(params: Value) -> Result<T, ApiError> {
+    serde_json::from_value(params)
+        .map_err(|e| ApiError::BadRequest(format!("Invalid params: {}", e)))
+}
+
+fn rpc_error(id: Value, code: i64, message: &str) -> Value {
+    json!({
+        "jsonrpc": "2.0",
+        "error": { "code": code, "message": message },
+        "id": id,
+    })
+}
+
+fn write_response(stream: &mut TcpStream, status: u16, body: &Value) -> io::Result<()> {
+    write_body(
+        stream,
+        status,
+        "application/json",
+        &serde_json::to_vec(body)?,
+    )
+}
+
+fn write_body(
+    stream: &mut TcpStream,
+    status: u16,
+    content_type: &str,
+    body: &[u8],
+) -> io::Result<()> {
+    let reason = match status {
+        200 => "OK",
+        400 => "Bad Request",
+        404 => "Not Found",
+        413 => "Payload Too Large",
+        431 => "Request Header Fields Too Large",
+        _ => "Internal Server Error",
+    };
+    let head = format!(
+        "HTTP/1.1 {} {}\r\nContent-Type: {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+        status,
+        reason,
+        content_type,
+        body.len()
+    );
+    stream.write_all(head.as_bytes())?;
+    stream.write_all(body)?;
+    stream.flush()
+}
@@ -0,0 +1,211 @@
+//----------------------------------------------------------------
+//       Blockchain node: gossips blocks and transactions over TCP
+//----------------------------------------------------------------
+// Usage: node --listen <address> [--peer <address>]... [--db <path>] [--mine <miner address>]
+//             [--api <address>] [--signer <address>]... [--signer-key <path>]
+//             [--snapshot-interval <blocks>] [--snapshot <path>] [--events <path>]
+//
+// With --signer the chain is sealed by proof-of-authority: the listed signers take turns.
+// A signer node also passes --signer-key, a file holding its hex encoded secret key.
+//
+// With --snapshot-interval the node prunes old blocks, keeping a snapshot of the chain every
+// that many blocks. A new node can start from a trusted snapshot, e.g. the <db>.snapshot file
+// of another node, with --snapshot instead of downloading the whole chain.
+//
+// The node logs what it does as JSON lines to stdout, or appends them to the file given with
+// --events. With --api, its metrics are served at /metrics for Prometheus to scrape.
+use std::fs::{self, OpenOptions};
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+
+use blockchain::consensus::{Consensus, ProofOfAuthority, ProofOfWork};
+use blockchain::metrics::{Event, EventLog};
+use blockchain::snapshot::Snapshot;
+use blockchain::{api, BlockChain, BlockError, ChainConfig, Node};
+use ed25519_dalek::{SigningKey, VerifyingKey};
+
+const SYNC_INTERVAL: Duration = Duration::from_secs(10);
+/// How long a proof-of-authority signer waits for the other signers' turns
+const TURN_WAIT: Duration = Duration::from_millis(500);
+
+struct Args {
+    listen: String,
+    peers: Vec<SocketAddr>,
+    db: String,
+    miner: Option<String>,
+    api: Option<String>,
+    signers: Vec<VerifyingKey>,
+    signer_key: Option<SigningKey>,
+    snapshot_interval: u64,
+    snapshot: Option<Snapshot>,
+    events: Option<String>,
+}
+
+fn parse_args() -> Result<Args, String> {
+    let mut args = Args {
+        listen: "127.0.0.1:9000".to_string(),
+        peers: vec![],
+        db: "node.db".to_string(),
+        miner: None,
+        api: None,
+        signers: vec![],
+        signer_key: None,
+        snapshot_interval: 0,
+        snapshot: None,
+        events: None,
+    };
+
+    let mut iter = std::env::args().skip(1);
+    while let Some(flag) = iter.next() {
+        let value = iter
+            .next()
+            .ok_or_else(|| format!("Missing value for {}", flag))?;
+        match flag.as_str() {
+            "--listen" => args.listen = value,
+            "--peer" => args.peers.push(
+                value
+                    .parse()
+                    .map_err(|e| format!("Bad peer {}: {}", value, e))?,
+            ),
+            "--db" => args.db = value,
+            "--mine" => args.miner = Some(value),
+            "--api" => args.api = Some(value),
+            "--signer" => args.signers.push(parse_signer(&value)?),
+            "--signer-key" => args.signer_key = Some(read_signer_key(&value)?),
+            "--snapshot-interval" => {
+                args.snapshot_interval = value
+                    .parse()
+                    .map_err(|e| format!("Bad snapshot interval {}: {}", value, e))?
+            }
+            "--snapshot" => args.snapshot = Some(read_snapshot(&value)?),
+            "--events" => args.events = Some(value),
+            _ => return Err(format!("Unknown argument {}", flag)),
+        }
+    }
+    if args.signer_key.is_some() && args.signers.is_empty() {
+        return Err("--signer-key needs the list of --signer addresses".to_string());
+    }
+    Ok(args)
+}
+
+fn parse_signer(address: &str) -> Result<VerifyingKey, String> {
+    hex::decode(address)
+        .ok()
+        .and_then(|bytes| bytes.try_into().ok())
+        .and_then(|bytes: [u8; 32]| VerifyingKey::from_bytes(&bytes).ok())
+        .ok_or_else(|| format!("Bad signer address {}", address))
+}
+
+fn read_signer_key(path: &str) -> Result<SigningKey, String> {
+    let text = fs::read_to_string(path).map_err(|e| format!("Cannot read {}: {}", path, e))?;
+    hex::decode(text.trim())
+        .ok()
+        .and_then(|bytes| bytes.try_into().ok())
+        .map(|bytes: [u8; 32]| SigningKey::from_bytes(&bytes))
+        .ok_or_else(|| format!("{} does not hold a hex encoded secret key", path))
+}
+
+fn read_snapshot(path: &str) -> Result<Snapshot, String> {
+    let text = fs::read_to_string(path).map_err(|e| format!("Cannot read {}: {}", path, e))?;
+    serde_json::from_str(&text).map_err(|e| format!("{} is not a snapshot: {}", path, e))
+}
+
+fn consensus(args: &Args) -> Arc<dyn Consensus> {
+    if args.signers.is_empty() {
+        return Arc::new(ProofOfWork::default());
+    }
+    match &args.signer_key {
+        Some(key) => Arc::new(ProofOfAuthority::with_key(
+            args.signers.clone(),
+            key.clone(),
+        )),
+        None => Arc::new(ProofOfAuthority::new(args.signers.clone())),
+    }
+}
+
+fn main() {
+    let args = match parse_args() {
+        Ok(args) => args,
+        Err(e) => {
+            eprintln!("{}", e);
+            eprintln!(
+                "Usage: node --listen <address> [--peer <address>]... [--db <path>] [--mine <miner address>] [--api <address>] [--signer <address>]... [--signer-key <path>] [--snapshot-interval <blocks>] [--snapshot <path>] [--events <path>]"
+            );
+            std::process::exit(1);
+        }
+    };
+
+    let config = ChainConfig {
+        snapshot_interval: args.snapshot_interval,
+        ..ChainConfig::default()
+    };
+    let events = EventLog::default();
+    if let Some(path) = &args.events {
+        let file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path)
+            .expect("Could not open the event log");
+        events.set_output(Box::new(file));
+    }
+    let mut chain = BlockChain::open_with_consensus(&args.db, config, consensus(&args))
+        .expect("Could not open the block store");
+    let mut restored = None;
+    if let Some(snapshot) = args.snapshot {
+        if chain.blocks.is_empty() {
+            restored = Some(snapshot.height);
+            chain
+                .restore_snapshot(snapshot)
+                .expect("Could not restore the snapshot");
+        }
+    }
+    let node = Node::start_with_log(&args.listen, chain, events).expect("Could not start the node");
+    if let Some(height) = restored {
+        node.log(Event::SnapshotRestored { height });
+    }
+    for peer in args.peers {
+        node.add_peer(peer);
+    }
+    if let Some(address) = &args.api {
+        api::start(address, Arc::clone(&node)).expect("Could not start the API");
+    }
+
+    // Join the network's chain if there is one, otherwise start a new chain
+    node.sync();
+    if node.tip().is_none() {
+        match node.with_chain_mut(|chain| chain.generate_genesis_block()) {
+            Ok(()) => {
+                if let Some(genesis) = node.tip() {
+                    node.log(Event::GenesisGenerated {
+                        hash: hex::encode(genesis.hash),
+                    });
+                }
+            }
+            // Only the first proof-of-authority signer can seal the genesis block
+            Err(BlockError::CannotSeal) => {
+                node.log(Event::WaitingForGenesis);
+                while node.tip().is_none() {
+                    thread::sleep(TURN_WAIT);
+                    node.sync();
+                }
+            }
+            Err(e) => panic!("Could not create the genesis block. {}", e),
+        }
+    }
+
+    match args.miner {
+        Some(miner) => loop {
+            // Nothing is sealed when mining was cancelled by a new block, or when it is another
+            // proof-of-authority signer's turn
+            if node.mine_block(&miner).is_none() && !args.signers.is_empty() {
+                thread::sleep(TURN_WAIT);
+            }
+        },
+        None => loop {
+            thread::sleep(SYNC_INTERVAL);
+            node.sync();
+        },
+    }
+}
@@ -0,0 +1,194 @@
+//----------------------------------------------------------------
+//       Wallet: keeps keys in an encrypted keystore and sends transfers to a node
+//----------------------------------------------------------------
+// Usage: wallet [--keystore <path>] [--node <api address>] <command>
+//   new <name>                                   generate a keypair
+//   list                                         names and addresses of the keys
+//   address <name>                               address of a key
+//   balance <name or address>                    balance and next nonce, from the node
+//   history <name or address>                    transactions of an address
+//   send <name> <recipient> <amount> [--fee <fee>] [--nonce <nonce>]
+//
+// The keystore password is read from WALLET_PASSWORD, or from standard input.
+use std::io::{self, BufRead, Write};
+
+use blockchain::wallet::{Account, EntryKind, Keystore, NodeClient};
+use blockchain::{Transaction, WalletError};
+
+const USAGE: &str = "Usage: wallet [--keystore <path>] [--node <api address>] <command>
+Commands:
+  new <name>
+  list
+  address <name>
+  balance <name or address>
+  history <name or address>
+  send <name> <recipient> <amount> [--fee <fee>] [--nonce <nonce>]";
+
+struct Args {
+    keystore: String,
+    node: String,
+    command: Vec<String>,
+    fee: u64,
+    nonce: Option<u64>,
+}
+
+fn parse_args() -> Result<Args, String> {
+    let mut args = Args {
+        keystore: "wallet.json".to_string(),
+        node: "127.0.0.1:8080".to_string(),
+        command: vec![],
+        fee: 0,
+        nonce: None,
+    };
+
+    let mut iter = std::env::args().skip(1);
+    while let Some(arg) = iter.next() {
+        if !arg.starts_with("--") {
+            args.command.push(arg);
+            continue;
+        }
+        let value = iter
+            .next()
+            .ok_or_else(|| format!("Missing value for {}", arg))?;
+        match arg.as_str() {
+            "--keystore" => args.keystore = value,
+            "--node" => args.node = value,
+            "--fee" => args.fee = parse_number(&value)?,
+            "--nonce" => args.nonce = Some(parse_number(&value)?),
+            _ => return Err(format!("Unknown argument {}", arg)),
+        }
+    }
+    Ok(args)
+}
+
+fn parse_number(value: &str) -> Result<u64, String> {
+    value
+        .parse()
+        .map_err(|e| format!("Bad number {}: {}", value, e))
+}
+
+fn password() -> io::Result<String> {
+    if let Ok(password) = std::env::var("WALLET_PASSWORD") {
+        return Ok(password);
+    }
+    print!("Keystore password: ");
+    io::stdout().flush()?;
+    let mut password = String::new();
+    io::stdin().lock().read_line(&mut password)?;
+    Ok(password.trim_end_matches(['\r', '\n']).to_string())
+}
+
+/// Address of a key in the keystore, or `name_or_address` itself if it is not a key's name
+fn resolve_address(keystore: &Keystore, name_or_address: &str) -> String {
+    keystore
+        .find(name_or_address)
+        .map_or(name_or_address.to_string(), |key| key.address())
+}
+
+fn run(args: Args) -> Result<(), String> {
+    let password = password().map_err(|e| e.to_string())?;
+    let mut keystore =
+        Keystore::open_or_create(&args.keystore, &password).map_err(|e| e.to_string())?;
+    let node = NodeClient::new(&args.node);
+    let command = args
+        .command
+        .iter()
+        .map(String::as_str)
+        .collect::<Vec<&str>>();
+
+    match command.as_slice() {
+        ["new", name] => {
+            let key = keystore.generate(name).map_err(|e| e.to_string())?;
+            println!("{} {}", key.name, key.address());
+        }
+        ["list"] => {
+            for key in keystore.keys() {
+                println!("{} {}", key.name, key.address());
+            }
+        }
+        ["address", name] => {
+            let key = keystore.find(name).map_err(|e| e.to_string())?;
+            println!("{}", key.address());
+        }
+        ["balance", who] => {
+            let account = node
+                .account(&resolve_address(&keystore, who))
+                .map_err(|e| e.to_string())?;
+            println!("Balance: {}", account.balance);
+            println!("Next nonce: {}", account.next_nonce);
+        }
+        ["history", who] => {
+            let address = resolve_address(&keystore, who);
+            let blocks = node.blocks().map_err(|e| e.to_string())?;
+            // A pruned node no longer has the oldest blocks, their transactions are not listed
+            let account = Account::replay(&address, &blocks);
+            for entry in &account.history {
+                let transaction = &entry.transaction;
+                match entry.kind {
+                    EntryKind::Mined => {
+                        println!("Block {}: mined {}", entry.block_id, transaction.amount)
+                    }
+                    EntryKind::Received => println!(
+                        "Block {}: received {} from {}",
+                        entry.block_id, transaction.amount, transaction.sender
+                    ),
+                    EntryKind::Sent => println!(
+                        "Block {}: sent {} to {} (fee {})",
+                        entry.block_id, transaction.amount, transaction.recipient, transaction.fee
+                    ),
+                }
+            }
+            let balance = node.account(&address).map_err(|e| e.to_string())?;
+            println!("Balance: {}", balance.balance);
+        }
+        ["send", name, recipient, amount] => {
+            let amount = parse_number(amount)?;
+            let key = keystore.find(name).map_err(|e| e.to_string())?;
+            // Without --nonce, use the next nonce on the chain. Sending again before the first
+            // transfer is mined needs the following nonce.
+            let nonce = match args.nonce {
+                Some(nonce) => nonce,
+                None => {
+                    node.account(&key.address())
+                        .map_err(|e| e.to_string())?
+                        .next_nonce
+                }
+            };
+            let transaction = Transaction::with_fee(
+                &key.signing_key,
+                recipient.to_string(),
+                amount,
+                args.fee,
+                nonce,
+            );
+            match node.submit(&transaction) {
+                Ok(id) => println!(
+                    "Submitted transaction {} with nonce {}",
+                    hex::encode(id),
+                    nonce
+                ),
+                Err(WalletError::Node(message)) => {
+                    return Err(format!("The node rejected the transaction: {}", message))
+                }
+                Err(e) => return Err(e.to_string()),
+            }
+        }
+        _ => return Err(USAGE.to_string()),
+    }
+    Ok(())
+}
+
+fn main() {
+    let args = match parse_args() {
+        Ok(args) => args,
+        Err(e) => {
+            eprintln!("{}", e);
+            eprintln!("{}", USAGE);
+            std::process::exit(1);
+        }
+    };
+    if let Err(e) = run(args) {
+        eprintln!("{}", e);
+        std::process::exit(1);
+    }
+}
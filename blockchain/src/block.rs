@@ -0,0 +1,306 @@
+use chrono::Utc;
+use serde::{Deserialize, Serialize};
+
+use crate::difficulty::hash_meets_difficulty;
+use crate::hash::{self, sha3, Hash, ZERO_HASH};
+use crate::merkle::{merkle_proof, merkle_root, MerkleProof};
+use crate::miner::{CancelToken, Miner};
+use crate::transaction::Transaction;
+
+/// Version of the header encoding written by `header_bytes`
+pub const HEADER_VERSION: u32 = 1;
+
+/// Length of an encoded header:
+/// version (4) | id (8) | timestamp (8) | previous hash (32) | merkle root (32) | difficulty (4) | nonce (8)
+pub const HEADER_LEN: usize = 96;
+
+/// Canonical encoding of a block header, the input of the block hash. Every field has a fixed
+/// width and integers are little endian, so two different headers never encode the same way.
+pub fn header_bytes(
+    version: u32,
+    id: u64,
+    timestamp: i64,
+    previous_hash: &Hash,
+    merkle_root: &Hash,
+    difficulty: u32,
+    nonce: u64,
+) -> [u8; HEADER_LEN] {
+    let mut header = [0; HEADER_LEN];
+    header[0..4].copy_from_slice(&version.to_le_bytes());
+    header[4..12].copy_from_slice(&id.to_le_bytes());
+    header[12..20].copy_from_slice(&timestamp.to_le_bytes());
+    header[20..52].copy_from_slice(previous_hash);
+    header[52..84].copy_from_slice(merkle_root);
+    header[84..88].copy_from_slice(&difficulty.to_le_bytes());
+    header[88..96].copy_from_slice(&nonce.to_le_bytes());
+    header
+}
+
+/// The fields of a block that its hash covers. This is all a light client needs to check
+/// the proof-of-work of a block and, with a `MerkleProof`, that a transaction is part of it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct BlockHeader {
+    pub version: u32,
+    pub id: u64,
+    pub timestamp: i64,
+    #[serde(with = "hash::hex_hash")]
+    pub previous_hash: Hash,
+    #[serde(with = "hash::hex_hash")]
+    pub merkle_root: Hash,
+    pub difficulty: u32,
+    pub nonce: u64,
+}
+
+impl BlockHeader {
+    /// Canonical encoding, see `header_bytes`
+    pub fn to_bytes(&self) -> [u8; HEADER_LEN] {
+        header_bytes(
+            self.version,
+            self.id,
+            self.timestamp,
+            &self.previous_hash,
+            &self.merkle_root,
+            self.difficulty,
+            self.nonce,
+        )
+    }
+
+    /// Decode a header encoded by `to_bytes`
+    pub fn from_bytes(bytes: &[u8; HEADER_LEN]) -> Self {
+        let field = |range: std::ops::Range<usize>| &bytes[range];
+        Self {
+            version: u32::from_le_bytes(field(0..4).try_into().unwrap()),
+            id: u64::from_le_bytes(field(4..12).try_into().unwrap()),
+            timestamp: i64::from_le_bytes(field(12..20).try_into().unwrap()),
+            previous_hash: field(20..52).try_into().unwrap(),
+            merkle_root: field(52..84).try_into().unwrap(),
+            difficulty: u32::from_le_bytes(field(84..88).try_into().unwrap()),
+            nonce: u64::from_le_bytes(field(88..96).try_into().unwrap()),
+        }
+    }
+
+    /// The block hash
+    pub fn hash(&self) -> Hash {
+        sha3(self.to_bytes())
+    }
+
+    /// Does the header's hash meet the difficulty it claims?
+    pub fn has_valid_work(&self) -> bool {
+        hash_meets_difficulty(&self.hash(), self.difficulty)
+    }
+
+    /// Check that the transaction with id `transaction` is part of this block
+    pub fn contains_transaction(&self, transaction: &Hash, proof: &MerkleProof) -> bool {
+        proof.verify(transaction, &self.merkle_root)
+    }
+}
+
+/// Block -> Contains the id (block number), transactions, hash, previous hash, timestamp, nonce
+/// and the difficulty (leading zero bits of the hash) it was mined at.
+/// The header commits to the transactions through their Merkle root.
+/// Blocks sealed by proof-of-authority carry the signer's signature of the hash instead of work.
+/// Hashes are serialized as hex strings.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Block {
+    /// Header encoding version, see `header_bytes`
+    pub version: u32,
+    pub id: u64,
+    pub nonce: u64,
+    pub transactions: Vec<Transaction>,
+    #[serde(with = "hash::hex_hash")]
+    pub merkle_root: Hash,
+    #[serde(with = "hash::hex_hash")]
+    pub hash: Hash,
+    #[serde(with = "hash::hex_hash")]
+    pub previous_hash: Hash,
+    pub timestamp: i64,
+    pub difficulty: u32,
+    /// Hex Ed25519 signature of the hash by a proof-of-authority signer, empty for proof-of-work
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    pub signature: String,
+}
+
+impl Block {
+    /// A block with every header field but the seal: the nonce is 0, the hash is not
+    /// calculated yet and there is no signature. `Consensus::seal` fills these in.
+    pub fn unsealed(
+        id: u64,
+        timestamp: i64,
+        previous_hash: Hash,
+        transactions: Vec<Transaction>,
+        difficulty: u32,
+    ) -> Self {
+        Self {
+            version: HEADER_VERSION,
+            id,
+            nonce: 0,
+            merkle_root: Block::transactions_root(&transactions),
+            transactions,
+            hash: ZERO_HASH,
+            previous_hash,
+            timestamp,
+            difficulty,
+            signature: String::new(),
+        }
+    }
+
+    /// Create a new block timestamped now, mined at the given difficulty
+    pub fn new(
+        id: u64,
+        previous_hash: Hash,
+        transactions: Vec<Transaction>,
+        difficulty: u32,
+    ) -> Self {
+        Block::new_at(
+            id,
+            Utc::now().timestamp(),
+            previous_hash,
+            transactions,
+            difficulty,
+        )
+    }
+
+    /// Create a new block with the given timestamp, mined at the given difficulty
+    pub fn new_at(
+        id: u64,
+        timestamp: i64,
+        previous_hash: Hash,
+        transactions: Vec<Transaction>,
+        difficulty: u32,
+    ) -> Self {
+        let merkle_root = Block::transactions_root(&transactions);
+
+        let (nonce, hash) =
+            Block::mine_block(id, timestamp, &previous_hash, &merkle_root, difficulty);
+
+        Self {
+            version: HEADER_VERSION,
+            id,
+            transactions,
+            merkle_root,
+            previous_hash,
+            nonce,
+            hash,
+            timestamp,
+            difficulty,
+            signature: String::new(),
+        }
+    }
+
+    /// Create a new block, searching for the nonce on all of `miner`'s threads.
+    /// Returns `None` if mining was cancelled through `cancel`. The hashes computed are
+    /// added to `miner.stats()`.
+    pub fn new_parallel(
+        id: u64,
+        timestamp: i64,
+        previous_hash: Hash,
+        transactions: Vec<Transaction>,
+        difficulty: u32,
+        miner: &Miner,
+        cancel: &CancelToken,
+    ) -> Option<Self> {
+        let merkle_root = Block::transactions_root(&transactions);
+
+        let hash_nonce = |nonce| {
+            Block::calculate_hash(
+                id,
+                timestamp,
+                &previous_hash,
+                &merkle_root,
+                nonce,
+                difficulty,
+            )
+        };
+        let result = miner.mine(difficulty, hash_nonce, cancel)?;
+
+        Some(Self {
+            version: HEADER_VERSION,
+            id,
+            transactions,
+            merkle_root,
+            previous_hash,
+            nonce: result.nonce,
+            hash: result.hash,
+            timestamp,
+            difficulty,
+            signature: String::new(),
+        })
+    }
+
+    /// Hash of a header with the given fields, encoded at the current `HEADER_VERSION`
+    pub fn calculate_hash(
+        id: u64,
+        timestamp: i64,
+        previous_hash: &Hash,
+        merkle_root: &Hash,
+        nonce: u64,
+        difficulty: u32,
+    ) -> Hash {
+        sha3(header_bytes(
+            HEADER_VERSION,
+            id,
+            timestamp,
+            previous_hash,
+            merkle_root,
+            difficulty,
+            nonce,
+        ))
+    }
+
+    /// Merkle root over the ids of the given transactions
+    pub fn transactions_root(transactions: &[Transaction]) -> Hash {
+        let leaves = transactions
+            .iter()
+            .map(Transaction::hash)
+            .collect::<Vec<Hash>>();
+        merkle_root(&leaves)
+    }
+
+    /// Mine a new block, outputs a tuple of Nonce (valid nonce) and Hash (hash of the block)
+    pub fn mine_block(
+        id: u64,
+        timestamp: i64,
+        previous_hash: &Hash,
+        merkle_root: &Hash,
+        difficulty: u32,
+    ) -> (u64, Hash) {
+        let mut nonce = 0;
+        loop {
+            let hash =
+                Block::calculate_hash(id, timestamp, previous_hash, merkle_root, nonce, difficulty);
+            if hash_meets_difficulty(&hash, difficulty) {
+                return (nonce, hash);
+            }
+            nonce += 1;
+        }
+    }
+
+    pub fn header(&self) -> BlockHeader {
+        BlockHeader {
+            version: self.version,
+            id: self.id,
+            timestamp: self.timestamp,
+            previous_hash: self.previous_hash,
+            merkle_root: self.merkle_root,
+            difficulty: self.difficulty,
+            nonce: self.nonce,
+        }
+    }
+
+    /// Recalculate the hash from the block's own fields
+    pub fn compute_hash(&self) -> Hash {
+        self.header().hash()
+    }
+
+    /// Proof that the transaction with id `transaction` is part of this block, checked
+    /// against the header with `BlockHeader::contains_transaction`
+    pub fn transaction_proof(&self, transaction: &Hash) -> Option<MerkleProof> {
+        let leaves = self
+            .transactions
+            .iter()
+            .map(Transaction::hash)
+            .collect::<Vec<Hash>>();
+        let index = leaves.iter().position(|leaf| leaf == transaction)?;
+        merkle_proof(&leaves, index)
+    }
+}
@@ -0,0 +1,1562 @@
+use std::collections::HashSet;
+use std::io;
+use std::net::IpAddr;
+use std::path::Path;
+use std::sync::Arc;
+
+use crate::block::{Block, BlockHeader, HEADER_VERSION};
+use crate::clock::{Clock, SystemClock};
+use crate::config::ChainConfig;
+use crate::consensus::{Consensus, ProofOfWork};
+use crate::difficulty;
+use crate::error::{BlockError, ChainError, LedgerError};
+use crate::hash::{Hash, ZERO_HASH};
+use crate::index::TransactionIndex;
+use crate::ledger::Ledger;
+use crate::metrics::Event;
+use crate::miner::CancelToken;
+use crate::script::{self, Context};
+use crate::snapshot::Snapshot;
+use crate::store::BlockStore;
+use crate::transaction::{is_valid_address, Transaction, MAX_FIELD_LEN};
+use crate::tree::BlockTree;
+
+/// Blockchain -> Basically a vector of blocks, optionally backed by an on-disk block store.
+/// `blocks` is the best chain: the branch of the block tree with the most cumulative work.
+/// The ledger holds the account balances that result from replaying the best chain.
+/// Timestamps are checked against `clock`, the system clock unless replaced with `set_clock`.
+/// Blocks are sealed and their seals verified by `consensus`, proof-of-work unless replaced.
+/// Once pruned, `blocks` starts with the recent blocks of the snapshot instead of genesis.
+/// What the chain repairs or skips on its own is kept as events for its owner to log, see
+/// `take_events`.
+#[derive(Debug)]
+pub struct BlockChain {
+    pub blocks: Vec<Block>,
+    pub config: ChainConfig,
+    clock: Arc<dyn Clock>,
+    consensus: Arc<dyn Consensus>,
+    ledger: Ledger,
+    /// Transactions of `blocks` by id and by address
+    index: TransactionIndex,
+    tree: BlockTree,
+    store: Option<BlockStore>,
+    /// Snapshot the chain was last pruned at
+    snapshot: Option<Snapshot>,
+    events: Vec<Event>,
+}
+
+/// What `try_add_block` did with a block
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BlockStatus {
+    /// The block extends the best chain
+    Added,
+    /// The block gave a competing branch more work than the best chain, which was switched to it
+    Reorganized,
+    /// The block is valid but its branch has less work than the best chain
+    SideBranch,
+    /// The block's parent is unknown, it is held until the parent arrives
+    Orphan,
+    /// The block is already known
+    Duplicate,
+}
+
+impl BlockStatus {
+    /// Is the block now part of the block tree?
+    pub fn is_connected(self) -> bool {
+        matches!(
+            self,
+            BlockStatus::Added | BlockStatus::Reorganized | BlockStatus::SideBranch
+        )
+    }
+
+    /// Name of the status in event logs and metric labels
+    pub fn name(self) -> &'static str {
+        match self {
+            BlockStatus::Added => "added",
+            BlockStatus::Reorganized => "reorganized",
+            BlockStatus::SideBranch => "side_branch",
+            BlockStatus::Orphan => "orphan",
+            BlockStatus::Duplicate => "duplicate",
+        }
+    }
+}
+
+/// Median timestamp of the last `span` blocks of `chain`, which a new block's timestamp must
+/// exceed. Unlike the last timestamp, a single miner with a wrong clock cannot move it far.
+pub fn median_time_past(chain: &[Block], span: usize) -> Option<i64> {
+    let mut timestamps = chain[chain.len().saturating_sub(span)..]
+        .iter()
+        .map(|block| block.timestamp)
+        .collect::<Vec<i64>>();
+    timestamps.sort_unstable();
+    timestamps.get(timestamps.len() / 2).copied()
+}
+
+/// Time the timelocks of a block after `previous` are checked against: its median time past,
+/// which unlike the block's own timestamp its miner cannot choose. Nothing is before the
+/// genesis block, so no timelock is met in it.
+fn lock_time(previous: &[Block], span: usize) -> i64 {
+    median_time_past(previous, span).unwrap_or(i64::MIN)
+}
+
+/// Number of leading blocks two chains have in common
+fn common_prefix_len(a: &[Block], b: &[Block]) -> usize {
+    a.iter()
+        .zip(b)
+        .take_while(|(x, y)| x.hash == y.hash)
+        .count()
+}
+
+impl Default for BlockChain {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl BlockChain {
+    /// Create a new in-memory blockchain, by initializing the blocks vector
+    pub fn new() -> Self {
+        Self::with_config(ChainConfig::default())
+    }
+
+    /// Create a new in-memory blockchain with the given consensus parameters
+    pub fn with_config(config: ChainConfig) -> Self {
+        Self {
+            blocks: vec![], // Empty vector
+            config,
+            clock: Arc::new(SystemClock),
+            consensus: Arc::new(ProofOfWork::default()),
+            ledger: Ledger::new(),
+            index: TransactionIndex::default(),
+            tree: BlockTree::new(),
+            store: None,
+            snapshot: None,
+            events: vec![],
+        }
+    }
+
+    /// Open a blockchain persisted at `path`. The stored blocks (of every branch) are replayed
+    /// and the resulting best chain re-validated. Every block added afterwards is appended
+    /// to the store. A pruned store starts from its snapshot, so only the blocks after it
+    /// are replayed.
+    pub fn open<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        Self::open_with_config(path, ChainConfig::default())
+    }
+
+    /// Same as `open`, with the given consensus parameters
+    pub fn open_with_config<P: AsRef<Path>>(path: P, config: ChainConfig) -> io::Result<Self> {
+        Self::open_with_consensus(path, config, Arc::new(ProofOfWork::default()))
+    }
+
+    /// Same as `open`, with the given consensus parameters and engine. The stored blocks are
+    /// verified by `consensus`.
+    pub fn open_with_consensus<P: AsRef<Path>>(
+        path: P,
+        config: ChainConfig,
+        consensus: Arc<dyn Consensus>,
+    ) -> io::Result<Self> {
+        let (mut store, stored) = BlockStore::open(path)?;
+        let invalid = |message: String| io::Error::new(io::ErrorKind::InvalidData, message);
+
+        // Replay without a store attached, so the blocks are not written again
+        let mut chain = Self::with_config(config);
+        chain.consensus = consensus;
+        chain.events = store.take_events();
+        let mut stored = stored.into_iter();
+        let replay = |chain: &mut Self, blocks: &mut dyn Iterator<Item = Block>| {
+            for block in blocks {
+                let (id, hash) = (block.id, block.hash);
+                if let Err(e) = chain.try_add_block(block) {
+                    chain.events.push(Event::StoredBlockSkipped {
+                        id,
+                        hash: hex::encode(hash),
+                        reason: e.reason(),
+                        error: e.to_string(),
+                    });
+                }
+            }
+        };
+        if let Some(snapshot) = store.snapshot()? {
+            let height = snapshot.height;
+            chain
+                .restore_snapshot(snapshot)
+                .map_err(|e| invalid(format!("The stored snapshot is invalid. {}", e)))?;
+            // Blocks up to the snapshot are left over from a prune interrupted by a crash
+            replay(&mut chain, &mut stored.filter(|block| block.id > height));
+        } else if let Some(genesis) = stored.next() {
+            chain
+                .is_genesis_valid(&genesis)
+                .and_then(|()| chain.push_block(genesis))
+                .map_err(|e| invalid(format!("The stored genesis block is invalid. {}", e)))?;
+            replay(&mut chain, &mut stored);
+        }
+
+        chain
+            .is_chain_valid(&chain.blocks)
+            .map_err(|e| invalid(format!("The stored chain failed validation. {}", e)))?;
+        chain.store = Some(store);
+        Ok(chain)
+    }
+
+    /// Remove and return what the chain did on its own since last asked: repairs of its store
+    /// when opened, stored blocks it skipped, and failed prunes
+    pub fn take_events(&mut self) -> Vec<Event> {
+        std::mem::take(&mut self.events)
+    }
+
+    /// Seal and verify blocks with `consensus`. Blocks already in the chain are not checked again.
+    pub fn set_consensus(&mut self, consensus: Arc<dyn Consensus>) {
+        self.consensus = consensus;
+    }
+
+    pub fn consensus(&self) -> &Arc<dyn Consensus> {
+        &self.consensus
+    }
+
+    /// Use `clock` for timestamp validation and for the timestamps of mined blocks
+    pub fn set_clock(&mut self, clock: Arc<dyn Clock>) {
+        self.clock = clock;
+    }
+
+    pub fn clock(&self) -> &Arc<dyn Clock> {
+        &self.clock
+    }
+
+    /// Timestamp for the next block: the current time, unless that is not after the median
+    /// time past, e.g. when several blocks are mined within a second
+    pub fn next_timestamp(&self) -> i64 {
+        let now = self.clock.now();
+        median_time_past(&self.blocks, self.config.median_time_span)
+            .map_or(now, |median| now.max(median + 1))
+    }
+
+    /// The next block on top of the chain, as seen by the scripts of its transactions
+    pub fn next_block_context(&self) -> Context {
+        Context {
+            height: self.blocks.last().map_or(1, |block| block.id + 1),
+            time: lock_time(&self.blocks, self.config.median_time_span),
+        }
+    }
+
+    /// Balances and nonces at the tip of the chain
+    pub fn ledger(&self) -> &Ledger {
+        &self.ledger
+    }
+
+    /// Snapshot the chain was last pruned at, if any
+    pub fn snapshot(&self) -> Option<&Snapshot> {
+        self.snapshot.as_ref()
+    }
+
+    /// Block of the best chain with id `height`, unless it was pruned
+    pub fn block_at_height(&self, height: u64) -> Option<&Block> {
+        let index = height.checked_sub(self.blocks.first()?.id)?;
+        self.blocks.get(index as usize)
+    }
+
+    /// The transaction of the best chain with id `hash`, and the block it is in
+    pub fn find_transaction(&self, hash: &Hash) -> Option<(&Block, &Transaction)> {
+        let location = self.index.transaction(hash)?;
+        let block = self.block_at_height(location.height)?;
+        Some((block, block.transactions.get(location.position)?))
+    }
+
+    /// The transactions of the best chain sent from or paid to `address`, with their blocks,
+    /// oldest first. A pruned chain only has those from its first block on.
+    pub fn address_transactions(&self, address: &str) -> Vec<(&Block, &Transaction)> {
+        self.index
+            .address(address)
+            .iter()
+            .filter_map(|location| {
+                let block = self.block_at_height(location.height)?;
+                Some((block, block.transactions.get(location.position)?))
+            })
+            .collect()
+    }
+
+    /// Headers of the blocks pruned from the store, oldest first. They can be checked
+    /// against the first block kept with `snapshot::verify_headers`.
+    pub fn pruned_headers(&self) -> io::Result<Vec<BlockHeader>> {
+        self.store
+            .as_ref()
+            .map_or(Ok(vec![]), BlockStore::pruned_headers)
+    }
+
+    /// Snapshot of the best chain at block `height`. Returns `None` if there is no such block,
+    /// or if it lies before the snapshot the chain was last pruned at.
+    pub fn snapshot_at(&self, height: u64) -> Option<Snapshot> {
+        if self.snapshot.as_ref().is_some_and(|s| height < s.height) {
+            return None;
+        }
+        let block = self.block_at_height(height)?;
+        let index = (height - self.blocks[0].id) as usize;
+
+        // Roll the ledger back from the tip to the checkpoint
+        let mut ledger = self.ledger.clone();
+        for block in self.blocks[index + 1..].iter().rev() {
+            ledger.rollback_block(block);
+        }
+        // As many blocks as difficulty retargeting and the median time past look back at
+        let window = (self.config.retarget_interval as usize + 1).max(self.config.median_time_span);
+        let start = (index + 1).saturating_sub(window);
+        Some(Snapshot {
+            height,
+            hash: block.hash,
+            ledger,
+            work_before: self.chain_work(&self.blocks[..start]),
+            recent_blocks: self.blocks[start..=index].to_vec(),
+        })
+    }
+
+    /// Take a snapshot at block `height` and drop the blocks before its recent blocks from
+    /// memory, along with the branches forking off before them. With a store, the store is
+    /// rewritten with the blocks after the snapshot and the headers of the dropped blocks kept.
+    pub fn prune(&mut self, height: u64) -> io::Result<()> {
+        let snapshot = self.snapshot_at(height).ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("Cannot take a snapshot at height {}", height),
+            )
+        })?;
+        let start = (snapshot.recent_blocks[0].id - self.blocks[0].id) as usize;
+        let pruned = self.blocks[..start]
+            .iter()
+            .map(Block::header)
+            .collect::<Vec<BlockHeader>>();
+
+        self.tree.prune(&snapshot.recent_blocks);
+        self.blocks.drain(..start);
+        self.index = TransactionIndex::from_blocks(&self.blocks);
+        if let Some(store) = self.store.as_mut() {
+            store.prune(&snapshot, &pruned, &self.tree.blocks_after(height))?;
+        }
+        self.snapshot = Some(snapshot);
+        Ok(())
+    }
+
+    /// Start over from a trusted snapshot, e.g. one copied from another node, instead of
+    /// replaying the chain from genesis. Blocks after the snapshot are then added as usual.
+    pub fn restore_snapshot(&mut self, snapshot: Snapshot) -> Result<(), ChainError> {
+        snapshot.check()?;
+        for (index, block) in snapshot.recent_blocks.iter().enumerate() {
+            self.consensus
+                .verify_seal(block)
+                .map_err(|error| ChainError { index, error })?;
+        }
+        if let Some(store) = self.store.as_mut() {
+            store
+                .remove_snapshot()
+                .and_then(|()| store.prune(&snapshot, &[], &[]))
+                .map_err(|e| ChainError {
+                    index: 0,
+                    error: BlockError::Storage(e.to_string()),
+                })?;
+        }
+
+        self.tree.clear();
+        for block in &snapshot.recent_blocks {
+            self.tree.insert(block.clone());
+        }
+        self.blocks = snapshot.recent_blocks.clone();
+        self.index = TransactionIndex::from_blocks(&self.blocks);
+        self.ledger = snapshot.ledger.clone();
+        self.snapshot = Some(snapshot);
+        Ok(())
+    }
+
+    /// Prune if the tip just reached a multiple of the snapshot interval. Only chains with a
+    /// store prune on their own.
+    fn prune_if_due(&mut self) {
+        let interval = self.config.snapshot_interval;
+        let Some(tip) = self.blocks.last() else {
+            return;
+        };
+        if interval == 0 || self.store.is_none() || !tip.id.is_multiple_of(interval) {
+            return;
+        }
+        let Some(height) = tip.id.checked_sub(self.config.prune_depth) else {
+            return;
+        };
+        if self.snapshot.as_ref().is_some_and(|s| s.height >= height) {
+            return;
+        }
+        if let Err(e) = self.prune(height) {
+            self.events.push(Event::PruneFailed {
+                height,
+                error: e.to_string(),
+            });
+        }
+    }
+
+    /// Seal the genesis block, at the configured initial difficulty for proof-of-work
+    pub fn generate_genesis_block(&mut self) -> Result<(), BlockError> {
+        let genesis_block = Block::unsealed(
+            1,
+            self.clock.now(),
+            ZERO_HASH,
+            vec![],
+            self.consensus.next_difficulty(&self.config, &[]),
+        );
+        let genesis_block = self
+            .consensus
+            .seal(genesis_block, &CancelToken::new())
+            .ok_or(BlockError::CannotSeal)?;
+        self.push_block(genesis_block)
+    }
+
+    /// Seal the next block on top of the chain, see `block_template`. Returns `None` if the
+    /// consensus engine may not seal it, e.g. it is another proof-of-authority signer's turn.
+    pub fn mine_next_block(&self, miner: &str, transactions: Vec<Transaction>) -> Option<Block> {
+        self.consensus.seal(
+            self.block_template(miner, transactions),
+            &CancelToken::new(),
+        )
+    }
+
+    /// The next block on top of the chain, ready to be sealed by the consensus engine.
+    /// Its transactions are the ones of `next_block_transactions`.
+    pub fn block_template(&self, miner: &str, transactions: Vec<Transaction>) -> Block {
+        let last_block = self.blocks.last().expect("The chain has no genesis block");
+        Block::unsealed(
+            last_block.id + 1,
+            self.next_timestamp(),
+            last_block.hash,
+            self.next_block_transactions(miner, transactions),
+            self.next_difficulty(),
+        )
+    }
+
+    /// Transactions of the next block: a coinbase transaction paying the block reward and
+    /// the fees of `transactions` to `miner`, followed by `transactions`
+    pub fn next_block_transactions(
+        &self,
+        miner: &str,
+        transactions: Vec<Transaction>,
+    ) -> Vec<Transaction> {
+        let id = self.blocks.last().map_or(1, |block| block.id + 1);
+        let fees = transactions
+            .iter()
+            .fold(0u64, |fees, t| fees.saturating_add(t.fee));
+        let mut block_transactions = vec![Transaction::coinbase(
+            miner.to_string(),
+            self.config.block_reward.saturating_add(fees),
+            id,
+        )];
+        block_transactions.extend(transactions);
+        block_transactions
+    }
+
+    /// Add a block received from anywhere. It may extend the best chain, start or extend a
+    /// competing branch (switching to it if it now has the most work), or wait for its parent.
+    /// An invalid block is not added and the reason is returned.
+    pub fn try_add_block(&mut self, block: Block) -> Result<BlockStatus, BlockError> {
+        self.try_add_block_from(block, None)
+    }
+
+    /// Same as `try_add_block` for a block sent by `peer`, which is held to a share of the
+    /// orphan pool if the block has to wait for its parent
+    pub fn try_add_block_from(
+        &mut self,
+        block: Block,
+        peer: Option<IpAddr>,
+    ) -> Result<BlockStatus, BlockError> {
+        let hash = block.hash;
+        let status = self.add_block(block, peer)?;
+        if status.is_connected() {
+            self.connect_orphans(&hash);
+            self.prune_if_due();
+        }
+        Ok(status)
+    }
+
+    fn add_block(&mut self, block: Block, peer: Option<IpAddr>) -> Result<BlockStatus, BlockError> {
+        let tip = self.blocks.last().ok_or(BlockError::NoGenesis)?;
+        // Checked first, so a block cannot pass for another one by claiming its hash
+        if block.compute_hash() != block.hash {
+            return Err(BlockError::HashMismatch);
+        }
+        if self.tree.contains(&block.hash) || self.tree.is_orphan(&block.hash) {
+            return Ok(BlockStatus::Duplicate);
+        }
+
+        if block.previous_hash == tip.hash {
+            self.is_block_valid(&block, &self.blocks)?;
+            self.push_block(block)?;
+            return Ok(BlockStatus::Added);
+        }
+
+        let Some(mut chain) = self.tree.chain_to(&block.previous_hash, &self.blocks) else {
+            self.check_orphan_seal(&block)?;
+            self.tree.add_orphan(block, peer);
+            return Ok(BlockStatus::Orphan);
+        };
+        self.is_block_valid(&block, &chain)?;
+
+        let tip_hash = tip.hash;
+        let tip_work = self.tree.cumulative_work(&tip_hash).unwrap_or(0);
+        let work = self.tree.insert(block.clone());
+        let old_chain = (work > tip_work).then(|| self.blocks.clone());
+        if old_chain.is_some() {
+            chain.push(block.clone());
+            if let Err(e) = self.reorganize(chain) {
+                self.tree.remove(&block.hash);
+                return Err(e.into());
+            }
+        }
+
+        if let Some(store) = self.store.as_mut() {
+            if let Err(e) = store.append(&block) {
+                if let Some(old_chain) = old_chain {
+                    self.reorganize(old_chain)
+                        .expect("Switching back to the previous chain cannot fail");
+                }
+                self.tree.remove(&block.hash);
+                return Err(BlockError::Storage(e.to_string()));
+            }
+        }
+
+        Ok(match old_chain {
+            Some(_) => BlockStatus::Reorganized,
+            None => BlockStatus::SideBranch,
+        })
+    }
+
+    /// Add the orphans that were waiting for `parent_hash`, and then their own orphans.
+    /// Invalid orphans are dropped.
+    fn connect_orphans(&mut self, parent_hash: &Hash) {
+        let mut parents = vec![*parent_hash];
+        while let Some(parent) = parents.pop() {
+            for orphan in self.tree.take_orphans(&parent) {
+                let hash = orphan.hash;
+                if self
+                    .add_block(orphan, None)
+                    .is_ok_and(BlockStatus::is_connected)
+                {
+                    parents.push(hash);
+                }
+            }
+        }
+    }
+
+    /// Apply the block to the ledger (rejecting overdrafts and double spends), then write it
+    /// to the store (if any) before adding it to memory, so the in-memory chain is never
+    /// ahead of what is on disk
+    fn push_block(&mut self, block: Block) -> Result<(), BlockError> {
+        self.ledger.apply_block(&block, self.config.block_reward)?;
+        if let Some(store) = self.store.as_mut() {
+            if let Err(e) = store.append(&block) {
+                self.ledger.rollback_block(&block);
+                return Err(BlockError::Storage(e.to_string()));
+            }
+        }
+        self.tree.insert(block.clone());
+        self.index.add_block(&block);
+        self.blocks.push(block);
+        Ok(())
+    }
+
+    /// Make `chain` the best chain: roll the ledger back to the last block both chains
+    /// share and apply the new blocks from there. The blocks must already be validated.
+    fn reorganize(&mut self, chain: Vec<Block>) -> Result<(), LedgerError> {
+        let common = common_prefix_len(&self.blocks, &chain);
+        self.ledger.switch_branch(
+            &self.blocks[common..],
+            &chain[common..],
+            self.config.block_reward,
+        )?;
+        for block in self.blocks[common..].iter().rev() {
+            self.index.remove_block(block);
+        }
+        for block in &chain[common..] {
+            self.index.add_block(block);
+        }
+        self.blocks = chain;
+        Ok(())
+    }
+
+    /// Replace the current blocks, e.g. with the chain returned by `chain_selector`.
+    /// The ledger is rolled back to the last block both chains share and the new blocks
+    /// are applied from there. The blocks of the old chain stay in the tree as a side branch.
+    /// A pruned chain only keeps `blocks` from its first block on, if `blocks` has it.
+    pub fn replace_chain(&mut self, mut blocks: Vec<Block>) -> Result<(), BlockError> {
+        // A pruned chain starts at its snapshot's recent blocks. A chain from genesis that
+        // passes through them is lined up with them, instead of being taken as a chain with
+        // nothing in common that undoes the pruning.
+        if let Some(start) = self
+            .blocks
+            .first()
+            .filter(|first| first.id != 1)
+            .and_then(|first| blocks.iter().position(|block| block.hash == first.hash))
+        {
+            blocks.drain(..start);
+        }
+        let common = common_prefix_len(&self.blocks, &blocks);
+        if common == 0 {
+            // Nothing in common, not even the genesis block: start over from `blocks`,
+            // which must then start with genesis
+            if let Some(first) = blocks.first().filter(|block| block.id != 1) {
+                return Err(BlockError::UnknownParent {
+                    previous_hash: first.previous_hash,
+                });
+            }
+            let ledger = Ledger::from_blocks(&blocks, self.config.block_reward)?;
+            if let Some(store) = self.store.as_mut() {
+                store
+                    .remove_snapshot()
+                    .and_then(|()| store.rewrite(&blocks))
+                    .map_err(|e| BlockError::Storage(e.to_string()))?;
+            }
+            self.snapshot = None;
+            self.tree.clear();
+            for block in &blocks {
+                self.tree.insert(block.clone());
+            }
+            self.ledger = ledger;
+            self.index = TransactionIndex::from_blocks(&blocks);
+            self.blocks = blocks;
+            return Ok(());
+        }
+
+        let old_chain = self.blocks.clone();
+        self.reorganize(blocks)?;
+
+        for i in common..self.blocks.len() {
+            let block = &self.blocks[i];
+            if self.tree.contains(&block.hash) {
+                continue;
+            }
+            if let Some(store) = self.store.as_mut() {
+                if let Err(e) = store.append(block) {
+                    self.reorganize(old_chain)
+                        .expect("Switching back to the previous chain cannot fail");
+                    return Err(BlockError::Storage(e.to_string()));
+                }
+            }
+            self.tree.insert(block.clone());
+        }
+        Ok(())
+    }
+
+    /// Blocks of the branch ending at `old_tip` that are not on the best chain, oldest first.
+    /// After a reorg these are the blocks that were dropped, whose transactions are pending again.
+    pub fn blocks_off_chain(&self, old_tip: &Hash) -> Vec<Block> {
+        let Some(branch) = self.tree.chain_to(old_tip, &self.blocks) else {
+            return vec![];
+        };
+        let common = common_prefix_len(&self.blocks, &branch);
+        branch[common..].to_vec()
+    }
+
+    /// Every known block, including competing branches
+    pub fn tree(&self) -> &BlockTree {
+        &self.tree
+    }
+
+    /// Cumulative proof-of-work of the best chain
+    pub fn total_work(&self) -> u128 {
+        self.chain_work(&self.blocks)
+    }
+
+    /// Cumulative work of `chain`, including the pruned blocks before it if it starts
+    /// where this chain was pruned
+    fn chain_work(&self, chain: &[Block]) -> u128 {
+        let before = match (chain.first(), &self.snapshot) {
+            (Some(first), Some(snapshot)) if first.id != 1 => snapshot.work_before,
+            _ => 0,
+        };
+        before.saturating_add(difficulty::chain_work(chain))
+    }
+
+    /// Difficulty the next block on top of this chain has to be mined at
+    pub fn next_difficulty(&self) -> u32 {
+        self.consensus.next_difficulty(&self.config, &self.blocks)
+    }
+
+    /// Check `new_block` against the chain it extends. `previous` is every block before it,
+    /// which is needed to work out the difficulty the block must have been mined at.
+    pub fn is_block_valid(&self, new_block: &Block, previous: &[Block]) -> Result<(), BlockError> {
+        let last_block = previous.last().ok_or(BlockError::NoGenesis)?;
+
+        // Check if new blocks previous hash is equal to the last block's hash
+        if new_block.previous_hash != last_block.hash {
+            return Err(BlockError::BadPreviousHash {
+                expected: last_block.hash,
+                found: new_block.previous_hash,
+            });
+        }
+
+        if new_block.id != last_block.id + 1 {
+            return Err(BlockError::BadId {
+                expected: last_block.id + 1,
+                found: new_block.id,
+            });
+        }
+
+        if let Some(median) = median_time_past(previous, self.config.median_time_span) {
+            if new_block.timestamp <= median {
+                return Err(BlockError::TimestampTooOld {
+                    timestamp: new_block.timestamp,
+                    median_time_past: median,
+                });
+            }
+        }
+        self.check_future_drift(new_block)?;
+
+        let expected_difficulty = self.consensus.next_difficulty(&self.config, previous);
+        self.check_seal(new_block, expected_difficulty)?;
+        let time = lock_time(previous, self.config.median_time_span);
+        self.check_transactions(new_block, time)
+    }
+
+    /// Without its parent, the difficulty a block must have cannot be worked out, and a seal
+    /// at the difficulty it declares itself could cost nothing. An orphan must declare at
+    /// least the difficulty of our next block less one retarget step, and be sealed at it.
+    fn check_orphan_seal(&self, block: &Block) -> Result<(), BlockError> {
+        let next = self.next_difficulty();
+        let minimum = next
+            .saturating_sub(difficulty::MAX_RETARGET_STEP)
+            .max(next.min(difficulty::MIN_DIFFICULTY));
+        if block.difficulty < minimum {
+            return Err(BlockError::EasyOrphan {
+                difficulty: block.difficulty,
+                minimum,
+            });
+        }
+        self.consensus.verify_seal(block)
+    }
+
+    /// The timestamp may not be more than `max_future_drift` ahead of our clock. A block
+    /// rejected for this can be accepted later, once the clock has caught up.
+    fn check_future_drift(&self, block: &Block) -> Result<(), BlockError> {
+        let max = self
+            .clock
+            .now()
+            .saturating_add(self.config.max_future_drift);
+        if block.timestamp > max {
+            return Err(BlockError::TimestampTooFarAhead {
+                timestamp: block.timestamp,
+                max,
+            });
+        }
+        Ok(())
+    }
+
+    /// The block must declare `expected_difficulty`, be sealed as the consensus engine
+    /// requires and its hash must be the hash of its header
+    fn check_seal(&self, block: &Block, expected_difficulty: u32) -> Result<(), BlockError> {
+        if block.version != HEADER_VERSION {
+            return Err(BlockError::BadVersion {
+                version: block.version,
+            });
+        }
+
+        if block.difficulty != expected_difficulty {
+            return Err(BlockError::BadDifficulty {
+                expected: expected_difficulty,
+                found: block.difficulty,
+            });
+        }
+
+        self.consensus.verify_seal(block)?;
+
+        if block.compute_hash() != block.hash {
+            return Err(BlockError::HashMismatch);
+        }
+        Ok(())
+    }
+
+    /// The Merkle root must commit to exactly the block's transactions, every transaction
+    /// must pay a valid address and be signed by its sender. Timelocks are checked against
+    /// `time`.
+    fn check_transactions(&self, block: &Block, time: i64) -> Result<(), BlockError> {
+        if Block::transactions_root(&block.transactions) != block.merkle_root {
+            return Err(BlockError::BadMerkleRoot);
+        }
+        // Repeating the last transactions of a level keeps the Merkle root (see `merkle_root`),
+        // so a block with a repeated transaction would share its hash with a valid block
+        let mut seen = HashSet::new();
+        if let Some(transaction) = block
+            .transactions
+            .iter()
+            .map(Transaction::hash)
+            .find(|hash| !seen.insert(*hash))
+        {
+            return Err(BlockError::DuplicateTransaction { transaction });
+        }
+        let longest = block
+            .transactions
+            .iter()
+            .map(Transaction::longest_field)
+            .fold(block.signature.len(), usize::max);
+        if longest > MAX_FIELD_LEN {
+            return Err(BlockError::FieldTooLong {
+                len: longest,
+                max: MAX_FIELD_LEN,
+            });
+        }
+        if let Some(transaction) = block
+            .transactions
+            .iter()
+            .find(|t| !is_valid_address(&t.recipient))
+        {
+            return Err(BlockError::BadRecipient {
+                transaction: transaction.hash(),
+            });
+        }
+
+        let context = Context {
+            height: block.id,
+            time,
+        };
+        for transaction in block.transactions.iter().filter(|t| !t.is_coinbase()) {
+            if transaction.is_script_spend() {
+                script::verify_spend(transaction, &context).map_err(|error| {
+                    BlockError::BadScript {
+                        transaction: transaction.hash(),
+                        error,
+                    }
+                })?;
+            } else if !transaction.verify_signature() {
+                return Err(BlockError::BadSignature {
+                    transaction: transaction.hash(),
+                });
+            }
+        }
+        Ok(())
+    }
+
+    /// The genesis block has nothing to extend, so only its own seal is checked
+    pub fn is_genesis_valid(&self, genesis: &Block) -> Result<(), BlockError> {
+        if genesis.id != 1 {
+            return Err(BlockError::BadId {
+                expected: 1,
+                found: genesis.id,
+            });
+        }
+        self.check_future_drift(genesis)?;
+        let expected_difficulty = self.consensus.next_difficulty(&self.config, &[]);
+        self.check_seal(genesis, expected_difficulty)?;
+        self.check_transactions(genesis, lock_time(&[], self.config.median_time_span))
+    }
+
+    /// Validate every block of `chain`, including the balances it results in. An empty
+    /// chain is valid. On failure, the index of the first bad block is returned with the reason.
+    /// A chain that does not start with genesis must start with the recent blocks of the
+    /// snapshot this chain was pruned at; those are trusted and validation starts after them.
+    pub fn is_chain_valid(&self, chain: &[Block]) -> Result<(), ChainError> {
+        let (mut ledger, start) = match chain.first() {
+            Some(first) if first.id != 1 => {
+                let Some(snapshot) = self.snapshot.as_ref().filter(|snapshot| {
+                    common_prefix_len(&snapshot.recent_blocks, chain)
+                        == snapshot.recent_blocks.len()
+                }) else {
+                    return Err(ChainError {
+                        index: 0,
+                        error: BlockError::UnknownParent {
+                            previous_hash: first.previous_hash,
+                        },
+                    });
+                };
+                (snapshot.ledger.clone(), snapshot.recent_blocks.len())
+            }
+            _ => (Ledger::new(), 0),
+        };
+        for (index, block) in chain.iter().enumerate().skip(start) {
+            let valid = if index == 0 {
+                self.is_genesis_valid(block)
+            } else {
+                self.is_block_valid(block, &chain[..index])
+            };
+            valid
+                .and_then(|()| {
+                    ledger
+                        .apply_block(block, self.config.block_reward)
+                        .map_err(BlockError::from)
+                })
+                .map_err(|error| ChainError { index, error })?;
+        }
+        Ok(())
+    }
+
+    /// Update the chain if there is update on other decentralized nodes.
+    /// Of two valid chains the one with the most cumulative proof-of-work wins,
+    /// not the one with the most blocks. Of a valid and an invalid chain the valid one is
+    /// returned, and `None` if both are invalid.
+    pub fn chain_selector(&self, local: Vec<Block>, remote: Vec<Block>) -> Option<Vec<Block>> {
+        match (self.is_chain_valid(&local), self.is_chain_valid(&remote)) {
+            (Ok(()), Ok(())) => {
+                if self.chain_work(&local) >= self.chain_work(&remote) {
+                    Some(local)
+                } else {
+                    Some(remote)
+                }
+            }
+            (Ok(()), Err(_)) => Some(local),
+            (Err(_), Ok(())) => Some(remote),
+            (Err(_), Err(_)) => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::clock::ManualClock;
+    use crate::error::{LedgerError, ScriptError};
+    use crate::snapshot;
+    use crate::transaction::{address, generate_keypair, Transaction};
+
+    fn easy_config() -> ChainConfig {
+        ChainConfig {
+            initial_difficulty: 1,
+            ..ChainConfig::default()
+        }
+    }
+
+    fn mine_next(chain: &BlockChain, transactions: Vec<Transaction>) -> Block {
+        let last_block = chain.blocks.last().unwrap();
+        Block::new_at(
+            last_block.id + 1,
+            chain.next_timestamp(),
+            last_block.hash,
+            transactions,
+            chain.next_difficulty(),
+        )
+    }
+
+    fn transfer(amount: u64) -> Transaction {
+        let recipient = address(&generate_keypair().verifying_key());
+        Transaction::new(&generate_keypair(), recipient, amount, 0)
+    }
+
+    #[test]
+    fn blocks_mined_at_difficulty_one_are_accepted() {
+        let miner = address(&generate_keypair().verifying_key());
+        let mut chain = BlockChain::with_config(easy_config());
+        chain.generate_genesis_block().unwrap();
+        for _ in 0..3 {
+            let block = chain.mine_next_block(&miner, vec![]).unwrap();
+            assert_eq!(chain.try_add_block(block), Ok(BlockStatus::Added));
+        }
+        assert_eq!(chain.blocks.len(), 4);
+        assert_eq!(chain.is_chain_valid(&chain.blocks), Ok(()));
+        assert_eq!(
+            chain.ledger().balance(&miner),
+            3 * chain.config.block_reward
+        );
+    }
+
+    #[test]
+    fn overdraft_block_is_rejected() {
+        let alice = generate_keypair();
+        let alice_address = address(&alice.verifying_key());
+        let bob_address = address(&generate_keypair().verifying_key());
+        let mut chain = BlockChain::with_config(easy_config());
+        chain.generate_genesis_block().unwrap();
+        let block = chain.mine_next_block(&alice_address, vec![]).unwrap();
+        chain.try_add_block(block).unwrap();
+
+        let reward = chain.config.block_reward;
+        let spend_too_much = Transaction::new(&alice, bob_address.clone(), reward + 1, 0);
+        let block = chain
+            .mine_next_block(&bob_address, vec![spend_too_much])
+            .unwrap();
+        assert!(matches!(
+            chain.try_add_block(block),
+            Err(BlockError::Ledger(LedgerError::InsufficientFunds { .. }))
+        ));
+        assert_eq!(chain.blocks.len(), 2);
+
+        let spend_all = Transaction::new(&alice, bob_address.clone(), reward, 0);
+        let block = chain
+            .mine_next_block(&bob_address, vec![spend_all])
+            .unwrap();
+        chain.try_add_block(block).unwrap();
+        assert_eq!(chain.blocks.len(), 3);
+        assert_eq!(chain.ledger().balance(&alice_address), 0);
+        assert_eq!(chain.ledger().balance(&bob_address), 2 * reward);
+    }
+
+    #[test]
+    fn switching_to_a_fork_rolls_back_the_ledger() {
+        let alice_address = address(&generate_keypair().verifying_key());
+        let bob_address = address(&generate_keypair().verifying_key());
+        let mut chain = BlockChain::with_config(easy_config());
+        chain.generate_genesis_block().unwrap();
+
+        // The fork shares the genesis block and then pays bob instead of alice
+        let mut fork = BlockChain::with_config(easy_config());
+        fork.replace_chain(chain.blocks.clone()).unwrap();
+
+        let block = chain.mine_next_block(&alice_address, vec![]).unwrap();
+        chain.try_add_block(block).unwrap();
+        for _ in 0..2 {
+            let block = fork.mine_next_block(&bob_address, vec![]).unwrap();
+            fork.try_add_block(block).unwrap();
+        }
+
+        let selected = chain
+            .chain_selector(chain.blocks.clone(), fork.blocks.clone())
+            .unwrap();
+        chain.replace_chain(selected).unwrap();
+
+        assert_eq!(chain.blocks, fork.blocks);
+        assert_eq!(chain.ledger(), fork.ledger());
+        assert_eq!(chain.ledger().balance(&alice_address), 0);
+        assert!(chain.address_transactions(&alice_address).is_empty());
+        assert_eq!(chain.address_transactions(&bob_address).len(), 2);
+        let coinbase = fork.blocks[2].transactions[0].hash();
+        assert_eq!(
+            chain.find_transaction(&coinbase).unwrap().0,
+            &fork.blocks[2]
+        );
+    }
+
+    #[test]
+    fn block_with_wrong_difficulty_is_rejected() {
+        let mut chain = BlockChain::with_config(easy_config());
+        chain.generate_genesis_block().unwrap();
+
+        // The hash matches the header, but the header claims less work than the chain requires
+        let last_block = chain.blocks.last().unwrap();
+        let block = Block::new_at(2, chain.next_timestamp(), last_block.hash, vec![], 0);
+        assert_eq!(
+            chain.is_block_valid(&block, &chain.blocks),
+            Err(BlockError::BadDifficulty {
+                expected: 1,
+                found: 0
+            })
+        );
+    }
+
+    #[test]
+    fn chain_validation_reports_first_bad_block() {
+        let miner = address(&generate_keypair().verifying_key());
+        let mut chain = BlockChain::with_config(easy_config());
+        chain.generate_genesis_block().unwrap();
+        for _ in 0..3 {
+            let block = chain.mine_next_block(&miner, vec![]).unwrap();
+            chain.try_add_block(block).unwrap();
+        }
+
+        let mut blocks = chain.blocks.clone();
+        blocks[2].timestamp += 1;
+        blocks[3].id = 7;
+        assert_eq!(
+            chain.is_chain_valid(&blocks),
+            Err(ChainError {
+                index: 2,
+                error: BlockError::HashMismatch
+            })
+        );
+
+        blocks[2] = chain.blocks[2].clone();
+        assert_eq!(
+            chain.is_chain_valid(&blocks),
+            Err(ChainError {
+                index: 3,
+                error: BlockError::BadId {
+                    expected: 4,
+                    found: 7
+                }
+            })
+        );
+    }
+
+    #[test]
+    fn block_that_cannot_be_stored_is_rejected() {
+        let mut chain = BlockChain::with_config(easy_config());
+        chain.generate_genesis_block().unwrap();
+        let mut witness = transfer(10);
+        witness.signature = "0".repeat(MAX_FIELD_LEN + 1);
+        let block = mine_next(&chain, vec![witness]);
+        assert_eq!(
+            chain.try_add_block(block),
+            Err(BlockError::FieldTooLong {
+                len: MAX_FIELD_LEN + 1,
+                max: MAX_FIELD_LEN
+            })
+        );
+    }
+
+    #[test]
+    fn tampered_block_is_rejected() {
+        let mut chain = BlockChain::with_config(easy_config());
+        chain.generate_genesis_block().unwrap();
+        let mut block = mine_next(&chain, vec![transfer(10)]);
+        block.transactions[0] = transfer(20);
+        assert_eq!(chain.try_add_block(block), Err(BlockError::BadMerkleRoot));
+        assert_eq!(chain.blocks.len(), 1);
+    }
+
+    #[test]
+    fn block_repeating_a_transaction_is_rejected() {
+        let mut chain = BlockChain::with_config(easy_config());
+        chain.generate_genesis_block().unwrap();
+        let block = mine_next(&chain, vec![transfer(10), transfer(20), transfer(30)]);
+
+        // Repeating the last of an odd number of transactions leaves the Merkle root, and so
+        // the hash, as it was
+        let mut repeated = block.clone();
+        repeated.transactions.push(block.transactions[2].clone());
+        assert_eq!(
+            Block::transactions_root(&repeated.transactions),
+            block.merkle_root
+        );
+        assert_eq!(
+            chain.try_add_block(repeated),
+            Err(BlockError::DuplicateTransaction {
+                transaction: block.transactions[2].hash()
+            })
+        );
+    }
+
+    #[test]
+    fn block_with_forged_signature_is_rejected() {
+        let mut chain = BlockChain::with_config(easy_config());
+        chain.generate_genesis_block().unwrap();
+
+        // The Merkle root and proof-of-work cover the forged amount, only the signature is wrong
+        let mut forged = transfer(10);
+        forged.amount = 1_000;
+        let block = mine_next(&chain, vec![forged.clone()]);
+        assert_eq!(
+            chain.try_add_block(block),
+            Err(BlockError::BadSignature {
+                transaction: forged.hash()
+            })
+        );
+        assert_eq!(chain.blocks.len(), 1);
+    }
+
+    /// Mine a block with a chosen timestamp on top of `parent_chain`, which does not have
+    /// to be the chain's best chain
+    fn mine_at(chain: &BlockChain, parent_chain: &[Block], timestamp: i64, miner: &str) -> Block {
+        let parent = parent_chain.last().unwrap();
+        let id = parent.id + 1;
+        let transactions = vec![Transaction::coinbase(
+            miner.to_string(),
+            chain.config.block_reward,
+            id,
+        )];
+        let merkle_root = Block::transactions_root(&transactions);
+        let difficulty = difficulty::next_difficulty(&chain.config, parent_chain);
+        let (nonce, hash) =
+            Block::mine_block(id, timestamp, &parent.hash, &merkle_root, difficulty);
+        Block {
+            version: HEADER_VERSION,
+            id,
+            nonce,
+            transactions,
+            merkle_root,
+            hash,
+            previous_hash: parent.hash,
+            timestamp,
+            difficulty,
+            signature: String::new(),
+        }
+    }
+
+    #[test]
+    fn branch_with_most_work_wins_over_longer_branch() {
+        let config = ChainConfig {
+            initial_difficulty: 1,
+            retarget_interval: 2,
+            ..ChainConfig::default()
+        };
+        let slow_miner = address(&generate_keypair().verifying_key());
+        let fast_miner = address(&generate_keypair().verifying_key());
+        let mut chain = BlockChain::with_config(config);
+        chain.generate_genesis_block().unwrap();
+        let start = chain.blocks[0].timestamp;
+
+        // Slow blocks make the retarget drop difficulty: 3 blocks of difficulty 1
+        let mut slow = chain.blocks.clone();
+        for i in 1..=3 {
+            let block = mine_at(&chain, &slow, start + 100 * i, &slow_miner);
+            slow.push(block.clone());
+            assert_eq!(chain.try_add_block(block), Ok(BlockStatus::Added));
+        }
+
+        // Fast blocks make it rise: 2 blocks, the second at difficulty 3
+        let mut fast = chain.blocks[..1].to_vec();
+        let block = mine_at(&chain, &fast, start + 1, &fast_miner);
+        fast.push(block.clone());
+        assert_eq!(chain.try_add_block(block), Ok(BlockStatus::SideBranch));
+        let block = mine_at(&chain, &fast, start + 2, &fast_miner);
+        assert_eq!(block.difficulty, 3);
+        fast.push(block.clone());
+        assert_eq!(chain.try_add_block(block), Ok(BlockStatus::Reorganized));
+
+        assert_eq!(chain.blocks, fast);
+        assert!(chain.total_work() > difficulty::chain_work(&slow));
+        assert_eq!(chain.ledger().balance(&slow_miner), 0);
+        assert_eq!(
+            chain.ledger().balance(&fast_miner),
+            2 * chain.config.block_reward
+        );
+        assert_eq!(chain.tree().tips().len(), 2);
+        let slow_tip = slow.last().unwrap().hash;
+        assert_eq!(chain.blocks_off_chain(&slow_tip), slow[1..]);
+        assert_eq!(chain.chain_selector(slow, fast.clone()), Some(fast));
+    }
+
+    #[test]
+    fn orphan_blocks_connect_when_parent_arrives() {
+        let miner = address(&generate_keypair().verifying_key());
+        let mut source = BlockChain::with_config(easy_config());
+        source.generate_genesis_block().unwrap();
+        for _ in 0..3 {
+            let block = source.mine_next_block(&miner, vec![]).unwrap();
+            source.try_add_block(block).unwrap();
+        }
+
+        let mut chain = BlockChain::with_config(easy_config());
+        chain.replace_chain(source.blocks[..1].to_vec()).unwrap();
+        assert_eq!(
+            chain.try_add_block(source.blocks[3].clone()),
+            Ok(BlockStatus::Orphan)
+        );
+        assert_eq!(
+            chain.try_add_block(source.blocks[2].clone()),
+            Ok(BlockStatus::Orphan)
+        );
+        assert_eq!(chain.blocks.len(), 1);
+
+        assert_eq!(
+            chain.try_add_block(source.blocks[1].clone()),
+            Ok(BlockStatus::Added)
+        );
+        assert_eq!(chain.blocks, source.blocks);
+        assert_eq!(chain.ledger(), source.ledger());
+    }
+
+    #[test]
+    fn unsealed_or_mislabelled_orphans_are_not_held() {
+        let miner = address(&generate_keypair().verifying_key());
+        let mut source = BlockChain::with_config(easy_config());
+        source.generate_genesis_block().unwrap();
+        for _ in 0..2 {
+            let block = source.mine_next_block(&miner, vec![]).unwrap();
+            source.try_add_block(block).unwrap();
+        }
+        let mut chain = BlockChain::with_config(easy_config());
+        chain.replace_chain(source.blocks[..1].to_vec()).unwrap();
+
+        // Junk claiming the hash of the block to come cannot shadow it
+        let mut junk = source.blocks[2].clone();
+        junk.nonce += 1;
+        assert_eq!(
+            chain.try_add_block(junk.clone()),
+            Err(BlockError::HashMismatch)
+        );
+        junk.difficulty = 200;
+        junk.hash = junk.compute_hash();
+        assert!(matches!(
+            chain.try_add_block(junk.clone()),
+            Err(BlockError::InsufficientWork { .. })
+        ));
+        // A seal at a difficulty of its own choosing costs nothing
+        junk.difficulty = 0;
+        junk.hash = junk.compute_hash();
+        assert_eq!(
+            chain.try_add_block(junk),
+            Err(BlockError::EasyOrphan {
+                difficulty: 0,
+                minimum: 1
+            })
+        );
+
+        assert_eq!(
+            chain.try_add_block(source.blocks[2].clone()),
+            Ok(BlockStatus::Orphan)
+        );
+        assert_eq!(
+            chain.try_add_block(source.blocks[1].clone()),
+            Ok(BlockStatus::Added)
+        );
+        assert_eq!(chain.blocks, source.blocks);
+    }
+
+    #[test]
+    fn reopened_store_restores_branches() {
+        let path = std::env::temp_dir().join(format!("blockchain-tree-{}.db", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        let miner = address(&generate_keypair().verifying_key());
+
+        let tip = {
+            let mut chain = BlockChain::open_with_config(&path, easy_config()).unwrap();
+            chain.generate_genesis_block().unwrap();
+            let other_miner = address(&generate_keypair().verifying_key());
+            let side = chain.mine_next_block(&other_miner, vec![]).unwrap();
+            for _ in 0..2 {
+                let block = chain.mine_next_block(&miner, vec![]).unwrap();
+                chain.try_add_block(block).unwrap();
+            }
+            assert_eq!(chain.try_add_block(side), Ok(BlockStatus::SideBranch));
+            chain.blocks.last().unwrap().hash
+        };
+
+        let chain = BlockChain::open_with_config(&path, easy_config()).unwrap();
+        assert_eq!(chain.blocks.last().unwrap().hash, tip);
+        assert_eq!(chain.tree().len(), 4);
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    /// A chain with a genesis block at `start`, validating against a clock that stays there
+    fn chain_at(start: i64) -> (BlockChain, ManualClock) {
+        let clock = ManualClock::new(start);
+        let mut chain = BlockChain::with_config(easy_config());
+        chain.set_clock(Arc::new(clock.clone()));
+        chain.generate_genesis_block().unwrap();
+        (chain, clock)
+    }
+
+    #[test]
+    fn block_not_after_median_time_past_is_rejected() {
+        let start = 1_700_000_000;
+        let miner = address(&generate_keypair().verifying_key());
+        let (mut chain, _) = chain_at(start);
+        for offset in [10, 30, 20] {
+            let block = mine_at(&chain, &chain.blocks, start + offset, &miner);
+            chain.try_add_block(block).unwrap();
+        }
+
+        // Median of start, +10, +30 and +20 is +20: an earlier block timestamp is fine, the median is not
+        let block = mine_at(&chain, &chain.blocks, start + 20, &miner);
+        assert_eq!(
+            chain.try_add_block(block),
+            Err(BlockError::TimestampTooOld {
+                timestamp: start + 20,
+                median_time_past: start + 20
+            })
+        );
+        let block = mine_at(&chain, &chain.blocks, start + 21, &miner);
+        assert_eq!(chain.try_add_block(block), Ok(BlockStatus::Added));
+    }
+
+    #[test]
+    fn block_too_far_ahead_is_accepted_once_the_clock_catches_up() {
+        let start = 1_700_000_000;
+        let miner = address(&generate_keypair().verifying_key());
+        let (mut chain, clock) = chain_at(start);
+        let max = start + chain.config.max_future_drift;
+
+        let block = mine_at(&chain, &chain.blocks, max + 1, &miner);
+        assert_eq!(
+            chain.try_add_block(block.clone()),
+            Err(BlockError::TimestampTooFarAhead {
+                timestamp: max + 1,
+                max
+            })
+        );
+        clock.advance(1);
+        assert_eq!(chain.try_add_block(block), Ok(BlockStatus::Added));
+    }
+
+    #[test]
+    fn mined_blocks_stay_after_median_time_past_on_a_stopped_clock() {
+        let miner = address(&generate_keypair().verifying_key());
+        let (mut chain, _) = chain_at(1_700_000_000);
+        for _ in 0..5 {
+            let block = chain.mine_next_block(&miner, vec![]).unwrap();
+            assert_eq!(chain.try_add_block(block), Ok(BlockStatus::Added));
+        }
+        assert_eq!(chain.is_chain_valid(&chain.blocks), Ok(()));
+        assert!(chain.blocks.last().unwrap().timestamp > 1_700_000_000);
+    }
+
+    #[test]
+    fn timelocked_script_spend_is_only_valid_from_its_height() {
+        let alice = generate_keypair();
+        let alice_address = address(&alice.verifying_key());
+        let mut chain = BlockChain::with_config(easy_config());
+        chain.generate_genesis_block().unwrap();
+        let block = chain.mine_next_block(&alice_address, vec![]).unwrap();
+        chain.try_add_block(block).unwrap();
+
+        // Alice locks 30 coins until block 5
+        let lock = script::Script::height_lock(5, &alice.verifying_key());
+        let deposit = Transaction::new(&alice, lock.address(), 30, 0);
+        let block = chain
+            .mine_next_block(&alice_address, vec![deposit])
+            .unwrap();
+        chain.try_add_block(block).unwrap();
+        assert_eq!(chain.ledger().balance(&lock.address()), 30);
+
+        let mut spend = Transaction::unsigned(lock.address(), alice_address.clone(), 30, 0, 0);
+        spend.signature = script::witness(vec![script::sign(&alice, &spend)], &lock);
+        let block = chain
+            .mine_next_block(&alice_address, vec![spend.clone()])
+            .unwrap();
+        assert_eq!(
+            chain.try_add_block(block),
+            Err(BlockError::BadScript {
+                transaction: spend.hash(),
+                error: ScriptError::HeightLocked {
+                    height: 4,
+                    until: 5
+                }
+            })
+        );
+
+        let block = chain.mine_next_block(&alice_address, vec![]).unwrap();
+        chain.try_add_block(block).unwrap();
+        let block = chain.mine_next_block(&alice_address, vec![spend]).unwrap();
+        assert_eq!(chain.try_add_block(block), Ok(BlockStatus::Added));
+        assert_eq!(chain.ledger().balance(&lock.address()), 0);
+    }
+
+    #[test]
+    fn timelocks_are_checked_against_median_time_past() {
+        let start = 1_700_000_000;
+        let alice = generate_keypair();
+        let alice_address = address(&alice.verifying_key());
+        let (mut chain, clock) = chain_at(start);
+        let block = chain.mine_next_block(&alice_address, vec![]).unwrap();
+        chain.try_add_block(block).unwrap();
+
+        let until = start + 1000;
+        let lock = script::Script::parse(&format!(
+            "{} CHECKTIMEVERIFY 0x{} CHECKSIG",
+            until,
+            hex::encode(alice.verifying_key().to_bytes())
+        ))
+        .unwrap();
+        let deposit = Transaction::new(&alice, lock.address(), 30, 0);
+        let block = chain
+            .mine_next_block(&alice_address, vec![deposit])
+            .unwrap();
+        chain.try_add_block(block).unwrap();
+
+        // A block stamped past the lock cannot spend it while the median time is before it
+        let mut spend = Transaction::unsigned(lock.address(), alice_address.clone(), 30, 0, 0);
+        spend.signature = script::witness(vec![script::sign(&alice, &spend)], &lock);
+        let last = chain.blocks.last().unwrap();
+        let block = Block::new_at(
+            last.id + 1,
+            until,
+            last.hash,
+            vec![spend.clone()],
+            chain.next_difficulty(),
+        );
+        let median = median_time_past(&chain.blocks, chain.config.median_time_span).unwrap();
+        assert_eq!(chain.next_block_context().time, median);
+        assert_eq!(
+            chain.try_add_block(block),
+            Err(BlockError::BadScript {
+                transaction: spend.hash(),
+                error: ScriptError::TimeLocked {
+                    time: median,
+                    until
+                }
+            })
+        );
+
+        clock.advance(2000);
+        for _ in 0..6 {
+            let block = chain.mine_next_block(&alice_address, vec![]).unwrap();
+            chain.try_add_block(block).unwrap();
+        }
+        let block = chain.mine_next_block(&alice_address, vec![spend]).unwrap();
+        assert_eq!(chain.try_add_block(block), Ok(BlockStatus::Added));
+        assert_eq!(chain.ledger().balance(&lock.address()), 0);
+    }
+
+    #[test]
+    fn pruned_chain_keeps_validating_and_restores_on_another_node() {
+        let miner = address(&generate_keypair().verifying_key());
+        let mut chain = BlockChain::with_config(easy_config());
+        chain.generate_genesis_block().unwrap();
+        for _ in 0..24 {
+            let block = chain.mine_next_block(&miner, vec![]).unwrap();
+            chain.try_add_block(block).unwrap();
+        }
+        let (work, ledger) = (chain.total_work(), chain.ledger().clone());
+
+        // Retargeting looks back 11 blocks, so the snapshot at 20 keeps blocks 10 to 20
+        chain.prune(20).unwrap();
+        assert_eq!(chain.blocks.first().unwrap().id, 10);
+        assert_eq!(chain.block_at_height(9), None);
+        assert_eq!(chain.total_work(), work);
+        assert_eq!(chain.ledger(), &ledger);
+        assert_eq!(chain.is_chain_valid(&chain.blocks), Ok(()));
+        let block = chain.mine_next_block(&miner, vec![]).unwrap();
+        assert_eq!(chain.try_add_block(block), Ok(BlockStatus::Added));
+
+        let mut other = BlockChain::with_config(easy_config());
+        other
+            .restore_snapshot(chain.snapshot().unwrap().clone())
+            .unwrap();
+        for block in chain.blocks.iter().filter(|block| block.id > 20) {
+            assert_eq!(other.try_add_block(block.clone()), Ok(BlockStatus::Added));
+        }
+        assert_eq!(other.blocks, chain.blocks);
+        assert_eq!(other.ledger(), chain.ledger());
+        assert_eq!(other.total_work(), chain.total_work());
+    }
+
+    #[test]
+    fn pruned_chain_lines_up_a_chain_from_genesis() {
+        let miner = address(&generate_keypair().verifying_key());
+        let mut chain = BlockChain::with_config(easy_config());
+        chain.generate_genesis_block().unwrap();
+        for _ in 0..24 {
+            let block = chain.mine_next_block(&miner, vec![]).unwrap();
+            chain.try_add_block(block).unwrap();
+        }
+        let mut other = BlockChain::with_config(easy_config());
+        other.replace_chain(chain.blocks.clone()).unwrap();
+        let block = other.mine_next_block(&miner, vec![]).unwrap();
+        other.try_add_block(block).unwrap();
+
+        chain.prune(20).unwrap();
+        chain.replace_chain(other.blocks.clone()).unwrap();
+        assert!(chain.snapshot().is_some());
+        assert_eq!(chain.blocks.first().unwrap().id, 10);
+        assert_eq!(chain.blocks[..], other.blocks[9..]);
+        assert_eq!(chain.ledger(), other.ledger());
+    }
+
+    #[test]
+    fn reopened_pruned_store_starts_from_the_snapshot() {
+        let path =
+            std::env::temp_dir().join(format!("blockchain-pruned-{}.db", std::process::id()));
+        let config = ChainConfig {
+            retarget_interval: 0,
+            median_time_span: 3,
+            snapshot_interval: 5,
+            prune_depth: 3,
+            ..easy_config()
+        };
+        let miner = address(&generate_keypair().verifying_key());
+
+        let (tip, ledger) = {
+            let mut chain = BlockChain::open_with_config(&path, config.clone()).unwrap();
+            chain.generate_genesis_block().unwrap();
+            for _ in 0..11 {
+                let block = chain.mine_next_block(&miner, vec![]).unwrap();
+                chain.try_add_block(block).unwrap();
+            }
+            (chain.blocks.last().unwrap().hash, chain.ledger().clone())
+        };
+
+        // Pruned at block 10, 3 blocks below the tip: blocks 5 to 7 are the snapshot's
+        let chain = BlockChain::open_with_config(&path, config).unwrap();
+        assert_eq!(chain.snapshot().unwrap().height, 7);
+        assert_eq!(chain.blocks.first().unwrap().id, 5);
+        assert_eq!(chain.blocks.last().unwrap().hash, tip);
+        assert_eq!(chain.ledger(), &ledger);
+
+        let headers = chain.pruned_headers().unwrap();
+        assert_eq!(headers.len(), 4);
+        assert_eq!(snapshot::verify_headers(&headers, &chain.blocks[0]), Ok(()));
+        let mut forged = headers.clone();
+        forged[1].nonce += 1;
+        assert!(snapshot::verify_headers(&forged, &chain.blocks[0]).is_err());
+
+        for extension in ["db", "snapshot", "headers"] {
+            std::fs::remove_file(path.with_extension(extension)).unwrap();
+        }
+    }
+}
@@ -0,0 +1,36 @@
+/// Consensus parameters of a chain
+#[derive(Debug, Clone, PartialEq)]
+pub struct ChainConfig {
+    /// Difficulty (number of leading zero bits of the hash) of the genesis block
+    pub initial_difficulty: u32,
+    /// Block time, in seconds, that difficulty retargeting aims for
+    pub target_block_time: i64,
+    /// Difficulty is recalculated every `retarget_interval` blocks. 0 disables retargeting.
+    pub retarget_interval: u64,
+    /// Amount the coinbase transaction of every block may pay to its miner
+    pub block_reward: u64,
+    /// A block's timestamp must be later than the median timestamp of this many blocks before it
+    pub median_time_span: usize,
+    /// How many seconds a block's timestamp may be ahead of the validating node's clock
+    pub max_future_drift: i64,
+    /// A node with a block store takes a snapshot every `snapshot_interval` blocks and prunes
+    /// the bodies of the blocks before it. 0 keeps every block.
+    pub snapshot_interval: u64,
+    /// Snapshots are taken this many blocks below the tip, deep enough not to be reorganized
+    pub prune_depth: u64,
+}
+
+impl Default for ChainConfig {
+    fn default() -> Self {
+        Self {
+            initial_difficulty: 16, // Same as the old "0000" hex prefix
+            target_block_time: 10,
+            retarget_interval: 10,
+            block_reward: 50,
+            median_time_span: 11,
+            max_future_drift: 2 * 60 * 60,
+            snapshot_interval: 0,
+            prune_depth: 100,
+        }
+    }
+}
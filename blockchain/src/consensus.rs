@@ -0,0 +1,213 @@
+use std::fmt;
+
+use ed25519_dalek::{Signature, Signer, SigningKey, VerifyingKey};
+
+use crate::block::Block;
+use crate::config::ChainConfig;
+use crate::difficulty::{self, hash_meets_difficulty};
+use crate::error::BlockError;
+use crate::miner::{CancelToken, Miner};
+use crate::transaction::address;
+
+/// Decides who may create a block and how the block proves it: seals new blocks and
+/// verifies the seal of received ones.
+pub trait Consensus: fmt::Debug + Send + Sync {
+    /// Difficulty the block after `previous` must declare. `previous` is empty for the
+    /// genesis block.
+    fn next_difficulty(&self, config: &ChainConfig, previous: &[Block]) -> u32;
+
+    /// Seal `block`, built with `Block::unsealed`. Returns `None` if sealing was cancelled
+    /// through `cancel`, or if this node may not seal the block.
+    fn seal(&self, block: Block, cancel: &CancelToken) -> Option<Block>;
+
+    /// Check the seal of `block`. That the hash is the hash of the header is checked separately.
+    fn verify_seal(&self, block: &Block) -> Result<(), BlockError>;
+
+    /// The miner that seals blocks, for engines that mine
+    fn miner(&self) -> Option<&Miner> {
+        None
+    }
+}
+
+/// Proof-of-work: the block hash must have `difficulty` leading zero bits, and the difficulty
+/// is retargeted to keep the block time (see `difficulty::next_difficulty`)
+#[derive(Debug, Clone, Default)]
+pub struct ProofOfWork {
+    miner: Miner,
+}
+
+impl ProofOfWork {
+    /// Proof-of-work that mines on `miner`'s threads
+    pub fn new(miner: Miner) -> Self {
+        Self { miner }
+    }
+}
+
+impl Consensus for ProofOfWork {
+    fn next_difficulty(&self, config: &ChainConfig, previous: &[Block]) -> u32 {
+        difficulty::next_difficulty(config, previous)
+    }
+
+    fn seal(&self, block: Block, cancel: &CancelToken) -> Option<Block> {
+        Block::new_parallel(
+            block.id,
+            block.timestamp,
+            block.previous_hash,
+            block.transactions,
+            block.difficulty,
+            &self.miner,
+            cancel,
+        )
+    }
+
+    fn verify_seal(&self, block: &Block) -> Result<(), BlockError> {
+        if !hash_meets_difficulty(&block.hash, block.difficulty) {
+            return Err(BlockError::InsufficientWork {
+                difficulty: block.difficulty,
+            });
+        }
+        Ok(())
+    }
+
+    fn miner(&self) -> Option<&Miner> {
+        Some(&self.miner)
+    }
+}
+
+/// Proof-of-authority: a fixed list of signers take turns, block `id` is signed by signer
+/// `(id - 1) % signers`. There is no mining, so blocks are sealed instantly, and every block
+/// has difficulty 0 and counts as the same work: the longest chain wins.
+#[derive(Debug, Clone)]
+pub struct ProofOfAuthority {
+    signers: Vec<VerifyingKey>,
+    key: Option<SigningKey>,
+}
+
+impl ProofOfAuthority {
+    /// Verify blocks of the given signers, without sealing any. Panics if `signers` is empty.
+    pub fn new(signers: Vec<VerifyingKey>) -> Self {
+        assert!(!signers.is_empty(), "Proof-of-authority needs a signer");
+        Self { signers, key: None }
+    }
+
+    /// Verify blocks of the given signers, and seal the blocks whose turn belongs to `key`
+    pub fn with_key(signers: Vec<VerifyingKey>, key: SigningKey) -> Self {
+        Self {
+            key: Some(key),
+            ..Self::new(signers)
+        }
+    }
+
+    /// The signer whose turn it is to seal block `id`
+    pub fn signer_of(&self, id: u64) -> &VerifyingKey {
+        let turn = id.saturating_sub(1) % self.signers.len() as u64;
+        &self.signers[turn as usize]
+    }
+}
+
+impl Consensus for ProofOfAuthority {
+    fn next_difficulty(&self, _config: &ChainConfig, _previous: &[Block]) -> u32 {
+        0
+    }
+
+    fn seal(&self, mut block: Block, _cancel: &CancelToken) -> Option<Block> {
+        let key = self.key.as_ref()?;
+        if key.verifying_key() != *self.signer_of(block.id) {
+            return None;
+        }
+        block.nonce = 0;
+        block.hash = block.compute_hash();
+        block.signature = hex::encode(key.sign(&block.hash).to_bytes());
+        Some(block)
+    }
+
+    fn verify_seal(&self, block: &Block) -> Result<(), BlockError> {
+        let signer = self.signer_of(block.id);
+        let signed = hex::decode(&block.signature)
+            .ok()
+            .and_then(|bytes| Signature::from_slice(&bytes).ok())
+            .is_some_and(|signature| signer.verify_strict(&block.hash, &signature).is_ok());
+        if !signed {
+            return Err(BlockError::BadSeal {
+                signer: address(signer),
+            });
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use super::*;
+    use crate::blockchain::{BlockChain, BlockStatus};
+    use crate::transaction::generate_keypair;
+
+    /// A chain sealed by proof-of-authority, where this node holds `key`
+    fn authority_chain(signers: &[VerifyingKey], key: &SigningKey) -> BlockChain {
+        let mut chain = BlockChain::new();
+        chain.set_consensus(Arc::new(ProofOfAuthority::with_key(
+            signers.to_vec(),
+            key.clone(),
+        )));
+        chain
+    }
+
+    #[test]
+    fn signers_take_turns() {
+        let (alice, bob) = (generate_keypair(), generate_keypair());
+        let signers = [alice.verifying_key(), bob.verifying_key()];
+        let mut alice_chain = authority_chain(&signers, &alice);
+        let mut bob_chain = authority_chain(&signers, &bob);
+        let miner = address(&alice.verifying_key());
+
+        // The genesis block is alice's turn
+        assert_eq!(
+            bob_chain.generate_genesis_block(),
+            Err(BlockError::CannotSeal)
+        );
+        alice_chain.generate_genesis_block().unwrap();
+        bob_chain.replace_chain(alice_chain.blocks.clone()).unwrap();
+
+        // Block 2 is bob's turn, block 3 alice's
+        assert_eq!(alice_chain.mine_next_block(&miner, vec![]), None);
+        let block = bob_chain.mine_next_block(&miner, vec![]).unwrap();
+        assert_eq!(block.difficulty, 0);
+        bob_chain.try_add_block(block.clone()).unwrap();
+        assert_eq!(alice_chain.try_add_block(block), Ok(BlockStatus::Added));
+
+        let block = alice_chain.mine_next_block(&miner, vec![]).unwrap();
+        assert_eq!(bob_chain.try_add_block(block), Ok(BlockStatus::Added));
+        assert_eq!(bob_chain.is_chain_valid(&bob_chain.blocks), Ok(()));
+    }
+
+    #[test]
+    fn block_sealed_out_of_turn_is_rejected() {
+        let (alice, bob) = (generate_keypair(), generate_keypair());
+        let signers = [alice.verifying_key(), bob.verifying_key()];
+        let mut chain = authority_chain(&signers, &alice);
+        chain.generate_genesis_block().unwrap();
+
+        // Alice signs block 2 herself, although it is bob's turn
+        let mut block = chain.block_template(&address(&alice.verifying_key()), vec![]);
+        block.hash = block.compute_hash();
+        block.signature = hex::encode(alice.sign(&block.hash).to_bytes());
+        assert_eq!(
+            chain.try_add_block(block),
+            Err(BlockError::BadSeal {
+                signer: address(&bob.verifying_key())
+            })
+        );
+    }
+
+    #[test]
+    fn proof_of_work_rejects_a_hash_without_the_work() {
+        let mut block = Block::unsealed(2, 0, [1; 32], vec![], 8);
+        block.hash = [0xff; 32];
+        assert_eq!(
+            ProofOfWork::default().verify_seal(&block),
+            Err(BlockError::InsufficientWork { difficulty: 8 })
+        );
+    }
+}
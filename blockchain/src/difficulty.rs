@@ -0,0 +1,164 @@
+use crate::block::Block;
+use crate::config::ChainConfig;
+use crate::hash::Hash;
+
+pub const MIN_DIFFICULTY: u32 = 1;
+pub const MAX_DIFFICULTY: u32 = 256;
+
+/// Most the difficulty moves at one retarget, in either direction
+pub const MAX_RETARGET_STEP: u32 = 2;
+
+/// Number of leading zero bits of a hash
+pub fn leading_zero_bits(hash: &Hash) -> u32 {
+    let mut bits = 0;
+    for byte in hash {
+        if *byte != 0 {
+            return bits + byte.leading_zeros();
+        }
+        bits += 8;
+    }
+    bits
+}
+
+/// Does the hash satisfy the proof-of-work target for the given difficulty?
+pub fn hash_meets_difficulty(hash: &Hash, difficulty: u32) -> bool {
+    leading_zero_bits(hash) >= difficulty
+}
+
+/// Expected number of hashes needed to mine a block at `difficulty`, i.e. 2^difficulty
+pub fn block_work(difficulty: u32) -> u128 {
+    1u128.checked_shl(difficulty).unwrap_or(u128::MAX)
+}
+
+/// Total proof-of-work of a chain
+pub fn chain_work(chain: &[Block]) -> u128 {
+    chain.iter().fold(0, |work, block| {
+        work.saturating_add(block_work(block.difficulty))
+    })
+}
+
+/// Difficulty required for the block that follows `previous`.
+///
+/// Every `retarget_interval` blocks the time taken by the last interval is compared with the
+/// target time. Like Bitcoin, the adjustment is limited to a factor of 4 in either direction,
+/// which with a leading-zero-bits target is at most 2 bits per retarget.
+pub fn next_difficulty(config: &ChainConfig, previous: &[Block]) -> u32 {
+    let Some(last) = previous.last() else {
+        return config.initial_difficulty;
+    };
+
+    // Ids rather than positions, `previous` may start after a snapshot instead of at genesis
+    if config.retarget_interval == 0 || !last.id.is_multiple_of(config.retarget_interval) {
+        return last.difficulty;
+    }
+
+    let interval = config.retarget_interval as usize;
+    let first_index = previous.len().saturating_sub(interval + 1);
+    let first = &previous[first_index];
+    let block_gaps = (previous.len() - 1 - first_index) as i64;
+
+    // Timestamps come from the blocks, saturating keeps absurd ones from overflowing
+    let expected = config.target_block_time.saturating_mul(block_gaps);
+    let actual = last.timestamp.saturating_sub(first.timestamp).max(1);
+
+    let step = MAX_RETARGET_STEP as i64;
+    let adjustment: i64 = if actual.saturating_mul(4) <= expected {
+        step
+    } else if actual.saturating_mul(2) <= expected {
+        step / 2
+    } else if actual >= expected.saturating_mul(4) {
+        -step
+    } else if actual >= expected.saturating_mul(2) {
+        -step / 2
+    } else {
+        0
+    };
+
+    (last.difficulty as i64 + adjustment).clamp(MIN_DIFFICULTY as i64, MAX_DIFFICULTY as i64) as u32
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::hash::ZERO_HASH;
+
+    fn chain_with_block_time(len: usize, block_time: i64, difficulty: u32) -> Vec<Block> {
+        (0..len)
+            .map(|i| Block {
+                version: crate::block::HEADER_VERSION,
+                id: i as u64 + 1,
+                nonce: 0,
+                transactions: vec![],
+                merkle_root: ZERO_HASH,
+                hash: ZERO_HASH,
+                previous_hash: ZERO_HASH,
+                timestamp: 1_700_000_000 + i as i64 * block_time,
+                difficulty,
+                signature: String::new(),
+            })
+            .collect()
+    }
+
+    #[test]
+    fn counts_leading_zero_bits() {
+        let hash = |prefix: &[u8]| {
+            let mut hash = [0xff; 32];
+            hash[..prefix.len()].copy_from_slice(prefix);
+            hash
+        };
+        assert_eq!(leading_zero_bits(&hash(&[])), 0);
+        assert_eq!(leading_zero_bits(&hash(&[0x7f])), 1);
+        assert_eq!(leading_zero_bits(&hash(&[0x0f])), 4);
+        assert_eq!(leading_zero_bits(&hash(&[0, 0, 0x0f])), 20);
+        assert_eq!(leading_zero_bits(&hash(&[0, 0, 0x01])), 23);
+        assert_eq!(leading_zero_bits(&ZERO_HASH), 256);
+    }
+
+    #[test]
+    fn difficulty_is_kept_between_retargets() {
+        let config = ChainConfig::default();
+        let chain = chain_with_block_time(5, 1, 8);
+        assert_eq!(next_difficulty(&config, &chain), 8);
+        assert_eq!(next_difficulty(&config, &[]), config.initial_difficulty);
+    }
+
+    #[test]
+    fn fast_blocks_raise_difficulty() {
+        let config = ChainConfig::default();
+        let chain = chain_with_block_time(10, 1, 8);
+        assert_eq!(next_difficulty(&config, &chain), 10);
+
+        let chain = chain_with_block_time(10, 4, 8);
+        assert_eq!(next_difficulty(&config, &chain), 9);
+    }
+
+    #[test]
+    fn slow_blocks_lower_difficulty() {
+        let config = ChainConfig::default();
+        let chain = chain_with_block_time(20, 25, 8);
+        assert_eq!(next_difficulty(&config, &chain), 7);
+
+        let chain = chain_with_block_time(20, 100, 1);
+        assert_eq!(next_difficulty(&config, &chain), MIN_DIFFICULTY);
+    }
+
+    #[test]
+    fn on_target_blocks_keep_difficulty() {
+        let config = ChainConfig::default();
+        let chain = chain_with_block_time(20, config.target_block_time, 8);
+        assert_eq!(next_difficulty(&config, &chain), 8);
+    }
+
+    #[test]
+    fn extreme_timestamps_do_not_overflow() {
+        let config = ChainConfig::default();
+        let mut chain = chain_with_block_time(10, 1, 8);
+        chain[0].timestamp = i64::MIN;
+        chain[9].timestamp = i64::MAX;
+        assert_eq!(next_difficulty(&config, &chain), 6);
+
+        chain[0].timestamp = i64::MAX;
+        chain[9].timestamp = i64::MIN;
+        assert_eq!(next_difficulty(&config, &chain), 10);
+    }
+}
@@ -0,0 +1,472 @@
+use std::fmt;
+use std::io;
+
+use crate::hash::Hash;
+
+/// Why a block was not accepted
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum BlockError {
+    /// The chain has no genesis block for the block to extend
+    NoGenesis,
+    /// The header is encoded with a version this node does not know
+    BadVersion { version: u32 },
+    /// The previous hash is not the hash of the block it claims to extend
+    BadPreviousHash { expected: Hash, found: Hash },
+    /// The previous hash is not the hash of any known block
+    UnknownParent { previous_hash: Hash },
+    /// The id is not the previous block's id + 1 (or 1 for a genesis block)
+    BadId { expected: u64, found: u64 },
+    /// The header claims a different difficulty than the chain requires at this height
+    BadDifficulty { expected: u32, found: u32 },
+    /// The hash does not have the leading zero bits its difficulty requires
+    InsufficientWork { difficulty: u32 },
+    /// A block whose parent is unknown declares less difficulty than the chain could require
+    /// by its height, so it cannot be checked and is not worth holding
+    EasyOrphan { difficulty: u32, minimum: u32 },
+    /// The block is not signed by the proof-of-authority signer whose turn it is
+    BadSeal { signer: String },
+    /// The consensus engine of this node cannot seal the block, e.g. it is another signer's turn
+    CannotSeal,
+    /// The stored hash is not the hash of the block's header
+    HashMismatch,
+    /// The timestamp is not later than the median timestamp of the blocks before it
+    TimestampTooOld {
+        timestamp: i64,
+        median_time_past: i64,
+    },
+    /// The timestamp is further ahead of the validating node's clock than the allowed drift
+    TimestampTooFarAhead { timestamp: i64, max: i64 },
+    /// The Merkle root does not commit to the block's transactions
+    BadMerkleRoot,
+    /// The block holds the same transaction more than once
+    DuplicateTransaction { transaction: Hash },
+    /// A string field of a transaction, or the block's signature, is longer than
+    /// `MAX_FIELD_LEN`
+    FieldTooLong { len: usize, max: usize },
+    /// A transaction is not signed by its sender
+    BadSignature { transaction: Hash },
+    /// A transaction pays an address that is neither a hex key address nor a script address
+    BadRecipient { transaction: Hash },
+    /// A transaction spending from a script address does not satisfy the script
+    BadScript {
+        transaction: Hash,
+        error: ScriptError,
+    },
+    /// The transactions cannot be applied to the balances of the chain it extends
+    Ledger(LedgerError),
+    /// The block is valid but could not be written to the block store
+    Storage(String),
+}
+
+impl fmt::Display for BlockError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            BlockError::NoGenesis => write!(f, "The chain has no genesis block."),
+            BlockError::BadVersion { version } => {
+                write!(f, "Header version {} is not supported.", version)
+            }
+            BlockError::BadPreviousHash { expected, found } => write!(
+                f,
+                "Previous hash {} does not match last block's hash {}.",
+                hex::encode(found),
+                hex::encode(expected)
+            ),
+            BlockError::UnknownParent { previous_hash } => write!(
+                f,
+                "Previous hash {} is not the hash of a known block.",
+                hex::encode(previous_hash)
+            ),
+            BlockError::BadId { expected, found } => {
+                write!(f, "Block id is {} but should be {}.", found, expected)
+            }
+            BlockError::BadDifficulty { expected, found } => {
+                write!(f, "Difficulty is {} but should be {}.", found, expected)
+            }
+            BlockError::InsufficientWork { difficulty } => {
+                write!(f, "Hash does not have {} leading zero bits.", difficulty)
+            }
+            BlockError::EasyOrphan {
+                difficulty,
+                minimum,
+            } => write!(
+                f,
+                "Block with an unknown parent has difficulty {}, below the minimum {}.",
+                difficulty, minimum
+            ),
+            BlockError::BadSeal { signer } => write!(
+                f,
+                "Block is not signed by {}, whose turn it is to seal it.",
+                signer
+            ),
+            BlockError::CannotSeal => write!(f, "This node cannot seal the block."),
+            BlockError::HashMismatch => write!(f, "Hash does not match the hash of the block."),
+            BlockError::TimestampTooOld {
+                timestamp,
+                median_time_past,
+            } => write!(
+                f,
+                "Timestamp {} is not after the median time past {}.",
+                timestamp, median_time_past
+            ),
+            BlockError::TimestampTooFarAhead { timestamp, max } => write!(
+                f,
+                "Timestamp {} is too far in the future, the latest accepted is {}.",
+                timestamp, max
+            ),
+            BlockError::BadMerkleRoot => {
+                write!(f, "Merkle root does not match the block's transactions.")
+            }
+            BlockError::DuplicateTransaction { transaction } => write!(
+                f,
+                "Transaction {} appears more than once in the block.",
+                hex::encode(transaction)
+            ),
+            BlockError::FieldTooLong { len, max } => write!(
+                f,
+                "Block has a field of {} bytes, longer than the {} bytes allowed.",
+                len, max
+            ),
+            BlockError::BadSignature { transaction } => write!(
+                f,
+                "Transaction {} has an invalid signature.",
+                hex::encode(transaction)
+            ),
+            BlockError::BadRecipient { transaction } => write!(
+                f,
+                "Transaction {} pays an invalid address.",
+                hex::encode(transaction)
+            ),
+            BlockError::BadScript { transaction, error } => write!(
+                f,
+                "Transaction {} does not satisfy its script. {}",
+                hex::encode(transaction),
+                error
+            ),
+            BlockError::Ledger(e) => e.fmt(f),
+            BlockError::Storage(e) => write!(f, "Could not persist the block: {}", e),
+        }
+    }
+}
+
+impl std::error::Error for BlockError {}
+
+impl BlockError {
+    /// Short name of the kind of error, for event logs and metric labels
+    pub fn reason(&self) -> &'static str {
+        match self {
+            BlockError::NoGenesis => "no_genesis",
+            BlockError::BadVersion { .. } => "bad_version",
+            BlockError::BadPreviousHash { .. } => "bad_previous_hash",
+            BlockError::UnknownParent { .. } => "unknown_parent",
+            BlockError::BadId { .. } => "bad_id",
+            BlockError::BadDifficulty { .. } => "bad_difficulty",
+            BlockError::InsufficientWork { .. } => "insufficient_work",
+            BlockError::EasyOrphan { .. } => "easy_orphan",
+            BlockError::BadSeal { .. } => "bad_seal",
+            BlockError::CannotSeal => "cannot_seal",
+            BlockError::HashMismatch => "hash_mismatch",
+            BlockError::TimestampTooOld { .. } => "timestamp_too_old",
+            BlockError::TimestampTooFarAhead { .. } => "timestamp_too_far_ahead",
+            BlockError::BadMerkleRoot => "bad_merkle_root",
+            BlockError::DuplicateTransaction { .. } => "duplicate_transaction",
+            BlockError::FieldTooLong { .. } => "field_too_long",
+            BlockError::BadSignature { .. } => "bad_signature",
+            BlockError::BadRecipient { .. } => "bad_recipient",
+            BlockError::BadScript { .. } => "bad_script",
+            BlockError::Ledger(_) => "ledger",
+            BlockError::Storage(_) => "storage",
+        }
+    }
+}
+
+impl From<LedgerError> for BlockError {
+    fn from(e: LedgerError) -> Self {
+        BlockError::Ledger(e)
+    }
+}
+
+/// Why the transactions of a block cannot be applied to the ledger
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum LedgerError {
+    /// A coinbase transaction that is not the first in its block
+    MisplacedCoinbase,
+    /// The coinbase nonce must be the block id, so every coinbase has a unique hash
+    BadCoinbaseNonce { expected: u64, found: u64 },
+    /// The coinbase pays more than the block reward plus the fees of the block
+    ExcessiveReward { amount: u64, reward: u64 },
+    /// The nonce is not the sender's next nonce: a double spend or a replay
+    BadNonce {
+        transaction: Hash,
+        expected: u64,
+        found: u64,
+    },
+    /// The sender's balance does not cover the amount
+    InsufficientFunds {
+        transaction: Hash,
+        balance: u64,
+        amount: u64,
+    },
+    /// The recipient is neither a hex key address nor a script address
+    BadRecipient { transaction: Hash },
+    /// The recipient's balance would overflow
+    BalanceOverflow { transaction: Hash },
+    /// The fees of the block's transactions add up to more than fits in a u64
+    FeeOverflow,
+}
+
+impl fmt::Display for LedgerError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            LedgerError::MisplacedCoinbase => write!(
+                f,
+                "The coinbase transaction must be the first in the block."
+            ),
+            LedgerError::BadCoinbaseNonce { expected, found } => write!(
+                f,
+                "The coinbase transaction nonce is {} but must be the block id {}.",
+                found, expected
+            ),
+            LedgerError::ExcessiveReward { amount, reward } => write!(
+                f,
+                "The coinbase pays {} but the block reward plus fees is {}.",
+                amount, reward
+            ),
+            LedgerError::BadNonce {
+                transaction,
+                expected,
+                found,
+            } => write!(
+                f,
+                "Transaction {} has nonce {} but {} was expected (double spend or replay).",
+                hex::encode(transaction),
+                found,
+                expected
+            ),
+            LedgerError::InsufficientFunds {
+                transaction,
+                balance,
+                amount,
+            } => write!(
+                f,
+                "Transaction {} spends {} but the sender only has {}.",
+                hex::encode(transaction),
+                amount,
+                balance
+            ),
+            LedgerError::BadRecipient { transaction } => write!(
+                f,
+                "Transaction {} pays an invalid address.",
+                hex::encode(transaction)
+            ),
+            LedgerError::BalanceOverflow { transaction } => write!(
+                f,
+                "Transaction {} overflows the recipient's balance.",
+                hex::encode(transaction)
+            ),
+            LedgerError::FeeOverflow => write!(f, "The fees of the block overflow."),
+        }
+    }
+}
+
+impl std::error::Error for LedgerError {}
+
+/// Why a transaction was not accepted into the mempool
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum MempoolError {
+    /// Coinbase transactions only exist inside the block that pays them
+    Coinbase,
+    /// The transaction is already pending
+    Duplicate,
+    /// The transaction is not signed by its sender
+    BadSignature,
+    /// The recipient is neither a hex key address nor a script address
+    BadRecipient,
+    /// The transaction spends from a script address without satisfying the script
+    BadScript(ScriptError),
+    /// The sender already used this nonce on the chain
+    StaleNonce { expected: u64, found: u64 },
+    /// Another pending transaction of the sender has the same nonce
+    Conflict,
+    /// The transaction skips nonces that no pending transaction of the sender has
+    NonceGap { expected: u64, found: u64 },
+    /// The sender cannot pay the amount plus the fee, on top of its pending transactions
+    InsufficientFunds { balance: u64, cost: u64 },
+    /// The transaction would not fit in a block
+    TooLarge { size: usize, max: usize },
+    /// The sender, recipient or signature is longer than `MAX_FIELD_LEN`
+    FieldTooLong { len: usize, max: usize },
+    /// The pool is full of transactions paying a higher fee per byte
+    PoolFull,
+}
+
+impl fmt::Display for MempoolError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            MempoolError::Coinbase => write!(f, "Coinbase transactions cannot be submitted."),
+            MempoolError::Duplicate => write!(f, "The transaction is already pending."),
+            MempoolError::BadSignature => write!(f, "The transaction has an invalid signature."),
+            MempoolError::BadRecipient => {
+                write!(f, "The transaction pays an invalid address.")
+            }
+            MempoolError::BadScript(e) => {
+                write!(f, "The transaction does not satisfy its script. {}", e)
+            }
+            MempoolError::StaleNonce { expected, found } => write!(
+                f,
+                "The transaction has nonce {} but the sender's next nonce is {}.",
+                found, expected
+            ),
+            MempoolError::Conflict => write!(
+                f,
+                "Another pending transaction of the sender has the same nonce."
+            ),
+            MempoolError::NonceGap { expected, found } => write!(
+                f,
+                "The transaction has nonce {} but the sender's next nonce after its pending transactions is {}.",
+                found, expected
+            ),
+            MempoolError::InsufficientFunds { balance, cost } => write!(
+                f,
+                "The transaction costs {} but the sender only has {}.",
+                cost, balance
+            ),
+            MempoolError::TooLarge { size, max } => write!(
+                f,
+                "The transaction is {} bytes, more than the {} bytes of a block.",
+                size, max
+            ),
+            MempoolError::FieldTooLong { len, max } => write!(
+                f,
+                "The transaction has a field of {} bytes, longer than the {} bytes allowed.",
+                len, max
+            ),
+            MempoolError::PoolFull => write!(
+                f,
+                "The mempool is full of transactions paying a higher fee per byte."
+            ),
+        }
+    }
+}
+
+impl std::error::Error for MempoolError {}
+
+/// Why a script did not let a transaction spend from a script address
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ScriptError {
+    /// The script text is not a list of known operations and literals
+    Parse(String),
+    /// The signature field of the transaction is not an unlocking script ending with the
+    /// locking script
+    BadWitness(String),
+    /// The locking script revealed is not the one behind the sender's address
+    WrongScript,
+    /// An operation needed more items than the stack holds
+    StackUnderflow,
+    /// The stack grew beyond its limit
+    StackOverflow,
+    /// The script ran more steps than allowed
+    StepLimit,
+    /// An operation got a number where it needed bytes, or the other way around
+    TypeMismatch { op: String },
+    /// A VERIFY operation, or the final result, was false
+    VerifyFailed { op: String },
+    /// The block is below the height the script is locked until
+    HeightLocked { height: u64, until: u64 },
+    /// The block is earlier than the time the script is locked until
+    TimeLocked { time: i64, until: i64 },
+}
+
+impl fmt::Display for ScriptError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ScriptError::Parse(reason) => write!(f, "Invalid script: {}.", reason),
+            ScriptError::BadWitness(reason) => write!(f, "Invalid witness: {}.", reason),
+            ScriptError::WrongScript => write!(
+                f,
+                "The locking script is not the one of the sender's address."
+            ),
+            ScriptError::StackUnderflow => write!(f, "The script popped an empty stack."),
+            ScriptError::StackOverflow => write!(f, "The script overflowed the stack."),
+            ScriptError::StepLimit => write!(f, "The script ran too many steps."),
+            ScriptError::TypeMismatch { op } => {
+                write!(f, "{} got an operand of the wrong type.", op)
+            }
+            ScriptError::VerifyFailed { op } => write!(f, "{} failed.", op),
+            ScriptError::HeightLocked { height, until } => write!(
+                f,
+                "The script is locked until height {}, the block is at {}.",
+                until, height
+            ),
+            ScriptError::TimeLocked { time, until } => write!(
+                f,
+                "The script is locked until time {}, the median time past is {}.",
+                until, time
+            ),
+        }
+    }
+}
+
+impl std::error::Error for ScriptError {}
+
+/// Why a wallet operation failed
+#[derive(Debug)]
+pub enum WalletError {
+    /// The keystore file could not be read or written, or the node could not be reached
+    Io(io::Error),
+    /// The keystore file exists but is not a keystore
+    BadKeystore(String),
+    /// The keystore already exists and would be overwritten
+    KeystoreExists,
+    /// The password does not decrypt the keystore
+    WrongPassword,
+    /// No key in the keystore has this name or address
+    UnknownKey(String),
+    /// A key with this name is already in the keystore
+    DuplicateName(String),
+    /// The node answered with an error, or with something that is not a valid answer
+    Node(String),
+}
+
+impl fmt::Display for WalletError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            WalletError::Io(e) => write!(f, "{}", e),
+            WalletError::BadKeystore(reason) => write!(f, "Invalid keystore: {}", reason),
+            WalletError::KeystoreExists => write!(f, "The keystore already exists."),
+            WalletError::WrongPassword => write!(f, "The password does not open the keystore."),
+            WalletError::UnknownKey(key) => write!(f, "No key named {} in the keystore.", key),
+            WalletError::DuplicateName(name) => {
+                write!(f, "The keystore already has a key named {}.", name)
+            }
+            WalletError::Node(message) => write!(f, "The node answered: {}", message),
+        }
+    }
+}
+
+impl std::error::Error for WalletError {}
+
+impl From<io::Error> for WalletError {
+    fn from(e: io::Error) -> Self {
+        WalletError::Io(e)
+    }
+}
+
+/// A chain that failed validation: the position of the first bad block and why it is bad
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ChainError {
+    /// Index of the block in the validated slice (the genesis block is 0)
+    pub index: usize,
+    pub error: BlockError,
+}
+
+impl fmt::Display for ChainError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "Block at index {} is invalid. {}",
+            self.index, self.error
+        )
+    }
+}
+
+impl std::error::Error for ChainError {}
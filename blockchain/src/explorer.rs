@@ -0,0 +1,447 @@
+use std::fmt::Write;
+
+use chrono::DateTime;
+
+use crate::block::Block;
+use crate::blockchain::BlockChain;
+use crate::hash::{hash_from_hex, Hash, ZERO_HASH};
+use crate::transaction::Transaction;
+use crate::wallet::{EntryKind, HistoryEntry};
+
+/// Number of blocks listed on the explorer's front page
+const RECENT_BLOCKS: usize = 20;
+
+/// Read-only HTML explorer of a chain, rendered on the server from the in-memory chain, with
+/// no JavaScript. `target` is the request path below `/explorer`, with its query string:
+///
+/// - `/`: summary of the chain and its most recent blocks
+/// - `/blocks/<hash>`: header and transactions of a block, including blocks on side branches
+/// - `/transactions/<hash>`: a transaction of the best chain and the block it is in
+/// - `/addresses/<address>`: balance, next nonce and transactions of an address
+/// - `/search?q=<height, hash or address>`: the page of whatever `q` names
+///
+/// Returns the HTTP status and the page.
+pub fn page(chain: &BlockChain, target: &str) -> (u16, String) {
+    let (path, query) = target.split_once('?').unwrap_or((target, ""));
+    let segments = path
+        .split('/')
+        .filter(|segment| !segment.is_empty())
+        .map(percent_decode)
+        .collect::<Vec<String>>();
+    let segments = segments.iter().map(String::as_str).collect::<Vec<&str>>();
+
+    let page = match segments.as_slice() {
+        [] => Ok(index(chain)),
+        ["blocks", hash] => block_page(chain, hash),
+        ["transactions", hash] => transaction_page(chain, hash),
+        ["addresses", address] => Ok(address_page(chain, address)),
+        ["search"] => search(chain, &query_param(query, "q").unwrap_or_default()),
+        _ => Err(format!("There is no page {}.", path)),
+    };
+    match page {
+        Ok(html) => (200, html),
+        Err(message) => (
+            404,
+            layout("Not found", &format!("<p>{}</p>", escape(&message))),
+        ),
+    }
+}
+
+/// The page of a block height, a block or transaction hash, or else an address
+fn search(chain: &BlockChain, query: &str) -> Result<String, String> {
+    let query = query.trim();
+    if query.is_empty() {
+        return Ok(index(chain));
+    }
+    if let Ok(height) = query.parse::<u64>() {
+        let block = chain
+            .block_at_height(height)
+            .ok_or_else(|| format!("No block at height {}.", height))?;
+        return Ok(render_block(chain, block));
+    }
+    // Addresses are hex too, a hash that names no block or transaction is taken as one
+    if let Some(hash) = hash_from_hex(query) {
+        if let Some(block) = chain.tree().get(&hash) {
+            return Ok(render_block(chain, block));
+        }
+        if let Some((block, transaction)) = chain.find_transaction(&hash) {
+            return Ok(render_transaction(chain, block, transaction));
+        }
+    }
+    Ok(address_page(chain, query))
+}
+
+fn index(chain: &BlockChain) -> String {
+    let mut body = String::from("<h2>Chain</h2><table>");
+    let tip = chain.blocks.last();
+    row(
+        &mut body,
+        "Height",
+        &tip.map_or(0, |block| block.id).to_string(),
+    );
+    if let Some(tip) = tip {
+        row(&mut body, "Tip", &block_link(&tip.hash));
+    }
+    row(&mut body, "Total work", &chain.total_work().to_string());
+    if !chain.blocks.is_empty() {
+        row(
+            &mut body,
+            "Next difficulty",
+            &chain.next_difficulty().to_string(),
+        );
+    }
+    if let Some(snapshot) = chain.snapshot() {
+        row(
+            &mut body,
+            "Pruned",
+            &format!("up to the snapshot at height {}", snapshot.height),
+        );
+    }
+    row(&mut body, "Known blocks", &chain.tree().len().to_string());
+    body.push_str("</table>");
+
+    body.push_str(
+        "<h2>Recent blocks</h2><table><tr><th>Height</th><th>Hash</th><th>Time</th>\
+         <th>Transactions</th><th>Difficulty</th><th>Nonce</th></tr>",
+    );
+    for block in chain.blocks.iter().rev().take(RECENT_BLOCKS) {
+        let _ = write!(
+            body,
+            "<tr><td>{}</td><td>{}</td><td>{}</td><td>{}</td><td>{}</td><td>{}</td></tr>",
+            block.id,
+            block_link(&block.hash),
+            format_time(block.timestamp),
+            block.transactions.len(),
+            block.difficulty,
+            block.nonce
+        );
+    }
+    body.push_str("</table>");
+    layout("Blocks", &body)
+}
+
+fn block_page(chain: &BlockChain, hash: &str) -> Result<String, String> {
+    hash_from_hex(hash)
+        .and_then(|parsed| chain.tree().get(&parsed))
+        .map(|block| render_block(chain, block))
+        .ok_or_else(|| format!("No block with hash {}.", hash))
+}
+
+fn render_block(chain: &BlockChain, block: &Block) -> String {
+    let on_best_chain = chain
+        .block_at_height(block.id)
+        .is_some_and(|b| b.hash == block.hash);
+
+    let mut body = String::from("<table>");
+    row(&mut body, "Height", &block.id.to_string());
+    row(&mut body, "Hash", &hex::encode(block.hash));
+    let previous = if block.previous_hash == ZERO_HASH {
+        "none, this is the genesis block".to_string()
+    } else {
+        block_link(&block.previous_hash)
+    };
+    row(&mut body, "Previous hash", &previous);
+    if let Some(next) = chain
+        .block_at_height(block.id + 1)
+        .filter(|next| on_best_chain && next.previous_hash == block.hash)
+    {
+        row(&mut body, "Next block", &block_link(&next.hash));
+    }
+    row(
+        &mut body,
+        "Status",
+        if on_best_chain {
+            "best chain"
+        } else {
+            "side branch"
+        },
+    );
+    row(&mut body, "Time", &format_time(block.timestamp));
+    row(&mut body, "Merkle root", &hex::encode(block.merkle_root));
+    row(&mut body, "Difficulty", &block.difficulty.to_string());
+    row(&mut body, "Nonce", &block.nonce.to_string());
+    row(&mut body, "Version", &block.version.to_string());
+    if !block.signature.is_empty() {
+        row(&mut body, "Signature", &escape(&block.signature));
+    }
+    body.push_str("</table>");
+
+    let _ = write!(
+        body,
+        "<h2>Transactions ({})</h2><table><tr><th>Hash</th><th>From</th><th>To</th>\
+         <th>Amount</th><th>Fee</th><th>Nonce</th></tr>",
+        block.transactions.len()
+    );
+    for transaction in &block.transactions {
+        let from = if transaction.is_coinbase() {
+            "coinbase".to_string()
+        } else {
+            address_link(&transaction.sender)
+        };
+        let _ = write!(
+            body,
+            "<tr><td>{}</td><td>{}</td><td>{}</td><td>{}</td><td>{}</td><td>{}</td></tr>",
+            transaction_link(&transaction.hash()),
+            from,
+            address_link(&transaction.recipient),
+            transaction.amount,
+            transaction.fee,
+            transaction.nonce
+        );
+    }
+    body.push_str("</table>");
+    layout(&format!("Block {}", block.id), &body)
+}
+
+fn transaction_page(chain: &BlockChain, hash: &str) -> Result<String, String> {
+    hash_from_hex(hash)
+        .and_then(|parsed| chain.find_transaction(&parsed))
+        .map(|(block, transaction)| render_transaction(chain, block, transaction))
+        .ok_or_else(|| format!("No transaction with hash {} in the best chain.", hash))
+}
+
+fn render_transaction(chain: &BlockChain, block: &Block, transaction: &Transaction) -> String {
+    let confirmations = chain
+        .blocks
+        .last()
+        .map_or(0, |tip| tip.id.saturating_sub(block.id) + 1);
+
+    let mut body = String::from("<table>");
+    row(&mut body, "Hash", &hex::encode(transaction.hash()));
+    row(
+        &mut body,
+        "Block",
+        &format!("{} (height {})", block_link(&block.hash), block.id),
+    );
+    row(&mut body, "Confirmations", &confirmations.to_string());
+    let from = if transaction.is_coinbase() {
+        "coinbase".to_string()
+    } else {
+        address_link(&transaction.sender)
+    };
+    row(&mut body, "From", &from);
+    row(&mut body, "To", &address_link(&transaction.recipient));
+    row(&mut body, "Amount", &transaction.amount.to_string());
+    row(&mut body, "Fee", &transaction.fee.to_string());
+    row(&mut body, "Nonce", &transaction.nonce.to_string());
+    row(&mut body, "Signature", &escape(&transaction.signature));
+    body.push_str("</table>");
+    layout("Transaction", &body)
+}
+
+fn address_page(chain: &BlockChain, address: &str) -> String {
+    let ledger = chain.ledger();
+    let mut body = String::from("<table>");
+    row(&mut body, "Address", &escape(address));
+    row(&mut body, "Balance", &ledger.balance(address).to_string());
+    row(
+        &mut body,
+        "Next nonce",
+        &ledger.next_nonce(address).to_string(),
+    );
+    body.push_str("</table><h2>Transactions</h2>");
+    if let Some(snapshot) = chain.snapshot() {
+        let _ = write!(
+            body,
+            "<p>Transactions before block {} were pruned.</p>",
+            snapshot.recent_blocks[0].id
+        );
+    }
+
+    body.push_str(
+        "<table><tr><th>Block</th><th>Hash</th><th></th><th>Counterparty</th>\
+         <th>Amount</th><th>Fee</th></tr>",
+    );
+    let history = chain
+        .address_transactions(address)
+        .into_iter()
+        .flat_map(|(block, transaction)| HistoryEntry::of(address, block.id, transaction))
+        .collect::<Vec<HistoryEntry>>();
+    for entry in history.iter().rev() {
+        let transaction = &entry.transaction;
+        let (kind, counterparty, fee) = match entry.kind {
+            EntryKind::Mined => ("mined", String::new(), String::new()),
+            EntryKind::Received => ("from", address_link(&transaction.sender), String::new()),
+            EntryKind::Sent => (
+                "to",
+                address_link(&transaction.recipient),
+                transaction.fee.to_string(),
+            ),
+        };
+        let block_hash = chain
+            .block_at_height(entry.block_id)
+            .map_or(ZERO_HASH, |block| block.hash);
+        let _ = write!(
+            body,
+            "<tr><td><a href=\"/explorer/blocks/{}\">{}</a></td><td>{}</td><td>{}</td>\
+             <td>{}</td><td>{}</td><td>{}</td></tr>",
+            hex::encode(block_hash),
+            entry.block_id,
+            transaction_link(&transaction.hash()),
+            kind,
+            counterparty,
+            transaction.amount,
+            fee
+        );
+    }
+    body.push_str("</table>");
+    layout("Address", &body)
+}
+
+/// A complete page with the search box above `body`
+fn layout(title: &str, body: &str) -> String {
+    format!(
+        "<!DOCTYPE html>\n<html><head><meta charset=\"utf-8\"><title>{title} - Block explorer</title>\
+         <style>body{{font-family:sans-serif;margin:2em}}table{{border-collapse:collapse}}\
+         td,th{{border:1px solid #ccc;padding:4px 8px;text-align:left}}\
+         td{{font-family:monospace;word-break:break-all}}</style></head><body>\
+         <p><a href=\"/explorer\">Block explorer</a></p>\
+         <form action=\"/explorer/search\" method=\"get\">\
+         <input name=\"q\" size=\"70\" placeholder=\"Height, block or transaction hash, address\">\
+         <button>Search</button></form><h1>{title}</h1>{body}</body></html>",
+        title = escape(title),
+        body = body
+    )
+}
+
+fn row(body: &mut String, name: &str, value: &str) {
+    let _ = write!(body, "<tr><th>{}</th><td>{}</td></tr>", name, value);
+}
+
+fn block_link(hash: &Hash) -> String {
+    let hash = hex::encode(hash);
+    format!("<a href=\"/explorer/blocks/{0}\">{0}</a>", hash)
+}
+
+fn transaction_link(hash: &Hash) -> String {
+    let hash = hex::encode(hash);
+    format!("<a href=\"/explorer/transactions/{0}\">{0}</a>", hash)
+}
+
+/// Addresses are whatever a transaction names, so they are escaped for the link and the text
+fn address_link(address: &str) -> String {
+    format!(
+        "<a href=\"/explorer/addresses/{}\">{}</a>",
+        escape(&percent_encode(address)),
+        escape(address)
+    )
+}
+
+fn format_time(timestamp: i64) -> String {
+    DateTime::from_timestamp(timestamp, 0).map_or(timestamp.to_string(), |time| {
+        time.format("%Y-%m-%d %H:%M:%S UTC").to_string()
+    })
+}
+
+fn escape(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&#39;"),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+/// Value of `name` in a form-encoded query string
+fn query_param(query: &str, name: &str) -> Option<String> {
+    query
+        .split('&')
+        .filter_map(|pair| pair.split_once('='))
+        .find(|(key, _)| *key == name)
+        .map(|(_, value)| percent_decode(&value.replace('+', " ")))
+}
+
+fn percent_decode(text: &str) -> String {
+    let bytes = text.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        let escape = bytes.get(i + 1..i + 3).filter(|_| bytes[i] == b'%');
+        match escape.and_then(|hex| u8::from_str_radix(std::str::from_utf8(hex).ok()?, 16).ok()) {
+            Some(byte) => {
+                decoded.push(byte);
+                i += 3;
+            }
+            None => {
+                decoded.push(bytes[i]);
+                i += 1;
+            }
+        }
+    }
+    String::from_utf8_lossy(&decoded).into_owned()
+}
+
+fn percent_encode(text: &str) -> String {
+    text.bytes()
+        .map(|byte| match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'_' | b'.' | b'~' => {
+                (byte as char).to_string()
+            }
+            byte => format!("%{:02X}", byte),
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::ChainConfig;
+    use crate::transaction::{address, generate_keypair};
+
+    /// A chain with a genesis block and a block paying `miner`
+    fn chain_paying(miner: &str) -> BlockChain {
+        let mut chain = BlockChain::with_config(ChainConfig {
+            initial_difficulty: 1,
+            ..ChainConfig::default()
+        });
+        chain.generate_genesis_block().unwrap();
+        let block = chain.mine_next_block(miner, vec![]).unwrap();
+        chain.try_add_block(block).unwrap();
+        chain
+    }
+
+    #[test]
+    fn search_finds_heights_hashes_and_addresses() {
+        let miner = address(&generate_keypair().verifying_key());
+        let chain = chain_paying(&miner);
+        let tip = chain.blocks.last().unwrap();
+        let coinbase = tip.transactions[0].hash();
+
+        let (status, html) = page(&chain, "/search?q=2");
+        assert_eq!(status, 200);
+        assert!(html.contains("<h1>Block 2</h1>"));
+        assert!(html.contains(&format!(
+            "<a href=\"/explorer/transactions/{0}\">{0}</a>",
+            hex::encode(coinbase)
+        )));
+
+        let (_, html) = page(&chain, &format!("/search?q={}", hex::encode(coinbase)));
+        assert!(html.contains("<h1>Transaction</h1>"));
+        assert!(html.contains(&format!("/explorer/blocks/{}", hex::encode(tip.hash))));
+
+        let (_, html) = page(&chain, &format!("/search?q=+{}+", miner));
+        assert!(html.contains("<h1>Address</h1>"));
+        assert!(html.contains(&format!(
+            "<th>Balance</th><td>{}</td>",
+            chain.config.block_reward
+        )));
+
+        assert_eq!(page(&chain, "/search?q=7").0, 404);
+        assert_eq!(page(&chain, "/blocks/00").0, 404);
+    }
+
+    #[test]
+    fn addresses_named_by_requests_are_escaped() {
+        let chain = chain_paying(&address(&generate_keypair().verifying_key()));
+        let (_, html) = page(&chain, "/addresses/%3Cscript%3Ealert%281%29%3C%2Fscript%3E");
+        assert!(!html.contains("<script>"));
+        assert!(html.contains("<th>Address</th><td>&lt;script&gt;alert(1)&lt;/script&gt;</td>"));
+        assert!(html.contains("<th>Balance</th><td>0</td>"));
+    }
+}
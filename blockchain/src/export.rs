@@ -0,0 +1,254 @@
+use std::io::{self, Read, Write};
+
+use crate::block::{Block, HEADER_VERSION};
+use crate::hash::{Hash, ZERO_HASH};
+use crate::transaction::Transaction;
+
+/// First bytes of a binary chain export
+pub const EXPORT_MAGIC: &[u8; 8] = b"BLKCHAIN";
+
+/// Binary encoding of a block: the canonical header (see `header_bytes`) followed by the
+/// transactions. The block hash is not stored, it is the SHA3-256 of the header.
+///
+/// `transaction count (u32) | transaction... | [block signature]`, where each transaction is
+/// `sender | recipient | amount (u64) | fee (u64) | nonce (u64) | signature` and the strings are
+/// written as a u16 byte length followed by their UTF-8 bytes (see `MAX_FIELD_LEN`). Integers
+/// are little endian.
+/// The block signature is only written for proof-of-authority blocks, so proof-of-work blocks
+/// encode the same as before it existed.
+pub fn encode_block(block: &Block) -> io::Result<Vec<u8>> {
+    let mut bytes = block.header().to_bytes().to_vec();
+    bytes.extend_from_slice(&(block.transactions.len() as u32).to_le_bytes());
+    for transaction in &block.transactions {
+        write_str(&mut bytes, &transaction.sender)?;
+        write_str(&mut bytes, &transaction.recipient)?;
+        bytes.extend_from_slice(&transaction.amount.to_le_bytes());
+        bytes.extend_from_slice(&transaction.fee.to_le_bytes());
+        bytes.extend_from_slice(&transaction.nonce.to_le_bytes());
+        write_str(&mut bytes, &transaction.signature)?;
+    }
+    if !block.signature.is_empty() {
+        write_str(&mut bytes, &block.signature)?;
+    }
+    Ok(bytes)
+}
+
+/// Decode a block written by `encode_block`. Trailing bytes are an error.
+pub fn decode_block(bytes: &[u8]) -> io::Result<Block> {
+    let mut reader = Reader(bytes);
+    let version = reader.u32()?;
+    if version != HEADER_VERSION {
+        return Err(invalid(format!("Unsupported header version {}", version)));
+    }
+    let id = reader.u64()?;
+    let timestamp = i64::from_le_bytes(reader.array()?);
+    let previous_hash = reader.hash()?;
+    let merkle_root = reader.hash()?;
+    let difficulty = reader.u32()?;
+    let nonce = reader.u64()?;
+
+    let count = reader.u32()?;
+    let mut transactions = Vec::new();
+    for _ in 0..count {
+        transactions.push(Transaction {
+            sender: reader.string()?,
+            recipient: reader.string()?,
+            amount: reader.u64()?,
+            fee: reader.u64()?,
+            nonce: reader.u64()?,
+            signature: reader.string()?,
+        });
+    }
+    let signature = if reader.0.is_empty() {
+        String::new()
+    } else {
+        reader.string()?
+    };
+    if !reader.0.is_empty() {
+        return Err(invalid("Trailing bytes after the block".to_string()));
+    }
+
+    let mut block = Block {
+        version,
+        id,
+        nonce,
+        transactions,
+        merkle_root,
+        hash: ZERO_HASH,
+        previous_hash,
+        timestamp,
+        difficulty,
+        signature,
+    };
+    block.hash = block.compute_hash();
+    Ok(block)
+}
+
+/// Write `blocks` as `EXPORT_MAGIC` followed by each block's encoding, prefixed by its length (u32)
+pub fn write_binary<W: Write>(blocks: &[Block], mut writer: W) -> io::Result<()> {
+    writer.write_all(EXPORT_MAGIC)?;
+    for block in blocks {
+        let bytes = encode_block(block)?;
+        writer.write_all(&(bytes.len() as u32).to_le_bytes())?;
+        writer.write_all(&bytes)?;
+    }
+    writer.flush()
+}
+
+/// Read blocks written by `write_binary`
+pub fn read_binary<R: Read>(mut reader: R) -> io::Result<Vec<Block>> {
+    let mut bytes = Vec::new();
+    reader.read_to_end(&mut bytes)?;
+    let Some(mut rest) = bytes.strip_prefix(EXPORT_MAGIC.as_slice()) else {
+        return Err(invalid("Not a binary chain export".to_string()));
+    };
+
+    let mut blocks = Vec::new();
+    while !rest.is_empty() {
+        let mut reader = Reader(rest);
+        let len = reader.u32()? as usize;
+        let block = reader.take(len)?;
+        blocks.push(decode_block(block)?);
+        rest = reader.0;
+    }
+    Ok(blocks)
+}
+
+/// Write `blocks` as a JSON array, with hashes as hex strings
+pub fn write_json<W: Write>(blocks: &[Block], mut writer: W) -> io::Result<()> {
+    serde_json::to_writer_pretty(&mut writer, blocks)?;
+    writer.flush()
+}
+
+/// Read blocks written by `write_json`
+pub fn read_json<R: Read>(reader: R) -> io::Result<Vec<Block>> {
+    Ok(serde_json::from_reader(reader)?)
+}
+
+fn write_str(bytes: &mut Vec<u8>, value: &str) -> io::Result<()> {
+    let len = u16::try_from(value.len())
+        .map_err(|_| invalid(format!("String of {} bytes is too long", value.len())))?;
+    bytes.extend_from_slice(&len.to_le_bytes());
+    bytes.extend_from_slice(value.as_bytes());
+    Ok(())
+}
+
+fn invalid(message: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+/// Reads little endian fields from the front of a byte slice
+struct Reader<'a>(&'a [u8]);
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, len: usize) -> io::Result<&'a [u8]> {
+        if self.0.len() < len {
+            return Err(io::Error::new(
+                io::ErrorKind::UnexpectedEof,
+                "Block encoding is truncated",
+            ));
+        }
+        let (field, rest) = self.0.split_at(len);
+        self.0 = rest;
+        Ok(field)
+    }
+
+    fn array<const N: usize>(&mut self) -> io::Result<[u8; N]> {
+        Ok(self.take(N)?.try_into().unwrap())
+    }
+
+    fn u16(&mut self) -> io::Result<u16> {
+        Ok(u16::from_le_bytes(self.array()?))
+    }
+
+    fn u32(&mut self) -> io::Result<u32> {
+        Ok(u32::from_le_bytes(self.array()?))
+    }
+
+    fn u64(&mut self) -> io::Result<u64> {
+        Ok(u64::from_le_bytes(self.array()?))
+    }
+
+    fn hash(&mut self) -> io::Result<Hash> {
+        self.array()
+    }
+
+    fn string(&mut self) -> io::Result<String> {
+        let len = self.u16()? as usize;
+        String::from_utf8(self.take(len)?.to_vec())
+            .map_err(|e| invalid(format!("Invalid string: {}", e)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::block::BlockHeader;
+    use crate::transaction::{address, generate_keypair};
+
+    fn sample_chain() -> Vec<Block> {
+        let alice = generate_keypair();
+        let bob = address(&generate_keypair().verifying_key());
+        let genesis = Block::new(1, ZERO_HASH, vec![], 1);
+        let next = Block::new(
+            2,
+            genesis.hash,
+            vec![
+                Transaction::coinbase(address(&alice.verifying_key()), 50, 2),
+                Transaction::with_fee(&alice, bob, 10, 2, 0),
+            ],
+            1,
+        );
+        vec![genesis, next]
+    }
+
+    #[test]
+    fn header_has_fixed_layout() {
+        let block = &sample_chain()[1];
+        let header = block.header().to_bytes();
+        assert_eq!(header[0..4], HEADER_VERSION.to_le_bytes());
+        assert_eq!(header[4..12], 2u64.to_le_bytes());
+        assert_eq!(header[20..52], block.previous_hash);
+        assert_eq!(header[52..84], block.merkle_root);
+        assert_eq!(header[88..96], block.nonce.to_le_bytes());
+        assert_eq!(BlockHeader::from_bytes(&header), block.header());
+    }
+
+    #[test]
+    fn binary_export_round_trips() {
+        let blocks = sample_chain();
+        let mut bytes = Vec::new();
+        write_binary(&blocks, &mut bytes).unwrap();
+        assert_eq!(read_binary(bytes.as_slice()).unwrap(), blocks);
+
+        // A truncated export is an error, not a shorter chain
+        assert!(read_binary(&bytes[..bytes.len() - 1]).is_err());
+    }
+
+    #[test]
+    fn json_export_uses_hex_hashes() {
+        let blocks = sample_chain();
+        let mut json = Vec::new();
+        write_json(&blocks, &mut json).unwrap();
+        let text = String::from_utf8(json.clone()).unwrap();
+        assert!(text.contains(&hex::encode(blocks[1].hash)));
+        assert_eq!(read_json(json.as_slice()).unwrap(), blocks);
+    }
+
+    #[test]
+    fn block_signature_round_trips() {
+        let mut block = sample_chain().remove(1);
+        let unsigned = encode_block(&block).unwrap();
+        block.signature = "ab".repeat(64);
+        let signed = encode_block(&block).unwrap();
+        assert_eq!(signed.len(), unsigned.len() + 2 + 128);
+        assert_eq!(decode_block(&signed).unwrap(), block);
+    }
+
+    #[test]
+    fn unknown_header_version_is_rejected() {
+        let mut bytes = encode_block(&sample_chain()[0]).unwrap();
+        bytes[0..4].copy_from_slice(&2u32.to_le_bytes());
+        assert!(decode_block(&bytes).is_err());
+    }
+}
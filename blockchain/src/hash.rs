@@ -0,0 +1,41 @@
+use sha3::{Digest, Sha3_256};
+
+/// A SHA3-256 digest: block hashes, Merkle roots and transaction ids
+pub type Hash = [u8; 32];
+
+/// Previous hash of the genesis block, and Merkle root of a block without transactions
+pub const ZERO_HASH: Hash = [0; 32];
+
+/// SHA3-256 of `data`
+pub fn sha3(data: impl AsRef<[u8]>) -> Hash {
+    Sha3_256::digest(data).into()
+}
+
+/// SHA3-256 of `data`, as a lowercase hex string
+pub fn sha3_hex(data: impl AsRef<[u8]>) -> String {
+    hex::encode(sha3(data))
+}
+
+/// Parse a 64 character hex string into a hash
+pub fn hash_from_hex(hex_hash: &str) -> Option<Hash> {
+    hex::decode(hex_hash).ok()?.try_into().ok()
+}
+
+/// Serialize hashes as hex strings, so JSON exports can be read by tools in other languages.
+/// Used with `#[serde(with = "crate::hash::hex_hash")]`.
+pub mod hex_hash {
+    use serde::de::Error;
+    use serde::{Deserialize, Deserializer, Serializer};
+
+    use super::Hash;
+
+    pub fn serialize<S: Serializer>(hash: &Hash, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&hex::encode(hash))
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Hash, D::Error> {
+        let hex_hash = String::deserialize(deserializer)?;
+        super::hash_from_hex(&hex_hash)
+            .ok_or_else(|| D::Error::custom(format!("invalid hash {}", hex_hash)))
+    }
+}
@@ -0,0 +1,129 @@
+use std::collections::HashMap;
+
+use crate::block::Block;
+use crate::hash::Hash;
+use crate::transaction::Transaction;
+
+/// Where a transaction of the best chain is: the height of its block and its position in it
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Location {
+    pub height: u64,
+    pub position: usize,
+}
+
+/// The transactions of the best chain by id and by the addresses they send from or pay to,
+/// so they are found without scanning and hashing the whole chain. Blocks are added and
+/// removed at the tip as the best chain changes.
+#[derive(Debug, Clone, Default)]
+pub struct TransactionIndex {
+    transactions: HashMap<Hash, Location>,
+    /// Oldest first
+    addresses: HashMap<String, Vec<Location>>,
+}
+
+impl TransactionIndex {
+    /// Index of the transactions of `blocks`, oldest first
+    pub fn from_blocks(blocks: &[Block]) -> Self {
+        let mut index = Self::default();
+        for block in blocks {
+            index.add_block(block);
+        }
+        index
+    }
+
+    /// Add the transactions of `block`, the new tip
+    pub fn add_block(&mut self, block: &Block) {
+        for (position, transaction) in block.transactions.iter().enumerate() {
+            let location = Location {
+                height: block.id,
+                position,
+            };
+            self.transactions.insert(transaction.hash(), location);
+            for address in addresses(transaction) {
+                self.addresses
+                    .entry(address.to_string())
+                    .or_default()
+                    .push(location);
+            }
+        }
+    }
+
+    /// Remove the transactions of `block`, the tip
+    pub fn remove_block(&mut self, block: &Block) {
+        for transaction in &block.transactions {
+            let hash = transaction.hash();
+            if self
+                .transactions
+                .get(&hash)
+                .is_some_and(|location| location.height == block.id)
+            {
+                self.transactions.remove(&hash);
+            }
+            for address in addresses(transaction) {
+                let Some(locations) = self.addresses.get_mut(address) else {
+                    continue;
+                };
+                while locations
+                    .last()
+                    .is_some_and(|location| location.height == block.id)
+                {
+                    locations.pop();
+                }
+                if locations.is_empty() {
+                    self.addresses.remove(address);
+                }
+            }
+        }
+    }
+
+    pub fn transaction(&self, hash: &Hash) -> Option<Location> {
+        self.transactions.get(hash).copied()
+    }
+
+    /// Locations of the transactions sent from or paid to `address`, oldest first
+    pub fn address(&self, address: &str) -> &[Location] {
+        self.addresses.get(address).map_or(&[], Vec::as_slice)
+    }
+}
+
+/// The accounts a transaction touches: its recipient, and its sender unless it is a coinbase
+fn addresses(transaction: &Transaction) -> impl Iterator<Item = &str> {
+    let sender = (!transaction.is_coinbase() && transaction.sender != transaction.recipient)
+        .then_some(transaction.sender.as_str());
+    sender.into_iter().chain([transaction.recipient.as_str()])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::hash::ZERO_HASH;
+    use crate::transaction::{address, generate_keypair};
+
+    #[test]
+    fn removing_the_tip_forgets_its_transactions() {
+        let alice = generate_keypair();
+        let alice_address = address(&alice.verifying_key());
+        let bob_address = address(&generate_keypair().verifying_key());
+        let mined = Transaction::coinbase(alice_address.clone(), 100, 2);
+        let first = Block::new(2, ZERO_HASH, vec![mined.clone()], 0);
+        let sent = Transaction::new(&alice, bob_address.clone(), 10, 0);
+        let second = Block::new(3, first.hash, vec![sent.clone()], 0);
+
+        let mut index = TransactionIndex::from_blocks(&[first.clone(), second.clone()]);
+        assert_eq!(
+            index.transaction(&sent.hash()),
+            Some(Location {
+                height: 3,
+                position: 0
+            })
+        );
+        assert_eq!(index.address(&alice_address).len(), 2);
+        assert_eq!(index.address(&bob_address).len(), 1);
+
+        index.remove_block(&second);
+        assert_eq!(index.transaction(&sent.hash()), None);
+        assert_eq!(index.transaction(&mined.hash()).unwrap().height, 2);
+        assert_eq!(index.address(&alice_address).len(), 1);
+        assert!(index.address(&bob_address).is_empty());
+    }
+}
@@ -0,0 +1,418 @@
+use std::collections::HashMap;
+
+use serde::{Deserialize, Serialize};
+
+use crate::block::Block;
+use crate::error::LedgerError;
+use crate::transaction::{is_valid_address, Transaction};
+
+/// Account balances and nonces derived by replaying the blocks of a chain.
+///
+/// Every state change is reversible, so a block can be rolled back exactly by undoing
+/// its transactions in reverse order. This is what lets the chain switch to a fork.
+/// Zero balances and nonces are never stored, so a rolled back ledger compares equal
+/// to the one before the block was applied.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct Ledger {
+    balances: HashMap<String, u64>,
+    nonces: HashMap<String, u64>,
+}
+
+impl Ledger {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Replay `blocks` from an empty state
+    pub fn from_blocks(blocks: &[Block], block_reward: u64) -> Result<Self, LedgerError> {
+        let mut ledger = Ledger::new();
+        for block in blocks {
+            ledger.apply_block(block, block_reward)?;
+        }
+        Ok(ledger)
+    }
+
+    pub fn balance(&self, address: &str) -> u64 {
+        self.balances.get(address).copied().unwrap_or(0)
+    }
+
+    /// Nonce the next transaction sent from `address` must carry
+    pub fn next_nonce(&self, address: &str) -> u64 {
+        self.nonces.get(address).copied().unwrap_or(0)
+    }
+
+    /// Apply every transaction of `block`. Either the whole block is applied or, if any
+    /// transaction is rejected, the ledger is left unchanged and the reason is returned.
+    /// The coinbase may pay out the block reward plus the fees of the block's transactions.
+    pub fn apply_block(&mut self, block: &Block, block_reward: u64) -> Result<(), LedgerError> {
+        let fees = block
+            .transactions
+            .iter()
+            .filter(|t| !t.is_coinbase())
+            .try_fold(0u64, |fees, t| fees.checked_add(t.fee))
+            .ok_or(LedgerError::FeeOverflow)?;
+        let payout = block_reward.saturating_add(fees);
+
+        for (i, transaction) in block.transactions.iter().enumerate() {
+            if let Err(e) = self.apply_transaction(block.id, i, transaction, payout) {
+                for applied in block.transactions[..i].iter().rev() {
+                    self.revert_transaction(applied);
+                }
+                return Err(e);
+            }
+        }
+        Ok(())
+    }
+
+    /// Undo a block previously applied with `apply_block`
+    pub fn rollback_block(&mut self, block: &Block) {
+        for transaction in block.transactions.iter().rev() {
+            self.revert_transaction(transaction);
+        }
+    }
+
+    /// Move the ledger from one branch of the chain to another: roll back `rolled_back`
+    /// (the old blocks above the common ancestor) and apply `applied` (the new ones).
+    /// If a new block is rejected, the old branch is restored and the reason is returned.
+    pub fn switch_branch(
+        &mut self,
+        rolled_back: &[Block],
+        applied: &[Block],
+        block_reward: u64,
+    ) -> Result<(), LedgerError> {
+        for block in rolled_back.iter().rev() {
+            self.rollback_block(block);
+        }
+        for (i, block) in applied.iter().enumerate() {
+            if let Err(e) = self.apply_block(block, block_reward) {
+                for block in applied[..i].iter().rev() {
+                    self.rollback_block(block);
+                }
+                for block in rolled_back {
+                    self.apply_block(block, block_reward)
+                        .expect("Re-applying previously applied blocks cannot fail");
+                }
+                return Err(e);
+            }
+        }
+        Ok(())
+    }
+
+    /// Apply a single transfer, as if it were included in the next block.
+    /// Coinbase transactions are rejected, they only exist inside a block.
+    pub fn apply_transfer(&mut self, transaction: &Transaction) -> Result<(), LedgerError> {
+        if transaction.is_coinbase() {
+            return Err(LedgerError::MisplacedCoinbase);
+        }
+        self.apply_transaction(0, 1, transaction, 0)
+    }
+
+    /// `payout` is the most the coinbase of the block may pay: the block reward plus fees
+    fn apply_transaction(
+        &mut self,
+        block_id: u64,
+        index: usize,
+        transaction: &Transaction,
+        payout: u64,
+    ) -> Result<(), LedgerError> {
+        if !is_valid_address(&transaction.recipient) {
+            return Err(LedgerError::BadRecipient {
+                transaction: transaction.hash(),
+            });
+        }
+        if transaction.is_coinbase() {
+            if index != 0 {
+                return Err(LedgerError::MisplacedCoinbase);
+            }
+            if transaction.nonce != block_id {
+                return Err(LedgerError::BadCoinbaseNonce {
+                    expected: block_id,
+                    found: transaction.nonce,
+                });
+            }
+            if transaction.amount > payout {
+                return Err(LedgerError::ExcessiveReward {
+                    amount: transaction.amount,
+                    reward: payout,
+                });
+            }
+        } else {
+            let expected_nonce = self.next_nonce(&transaction.sender);
+            if transaction.nonce != expected_nonce {
+                return Err(LedgerError::BadNonce {
+                    transaction: transaction.hash(),
+                    expected: expected_nonce,
+                    found: transaction.nonce,
+                });
+            }
+            let balance = self.balance(&transaction.sender);
+            let cost = transaction.amount.checked_add(transaction.fee);
+            if cost.is_none_or(|cost| balance < cost) {
+                return Err(LedgerError::InsufficientFunds {
+                    transaction: transaction.hash(),
+                    balance,
+                    amount: transaction.amount.saturating_add(transaction.fee),
+                });
+            }
+        }
+
+        let recipient_balance = self.balance(&transaction.recipient);
+        if recipient_balance.checked_add(transaction.amount).is_none() {
+            return Err(LedgerError::BalanceOverflow {
+                transaction: transaction.hash(),
+            });
+        }
+
+        if !transaction.is_coinbase() {
+            adjust(
+                &mut self.balances,
+                &transaction.sender,
+                -(transaction.amount as i128 + transaction.fee as i128),
+            );
+            adjust(&mut self.nonces, &transaction.sender, 1);
+        }
+        adjust(
+            &mut self.balances,
+            &transaction.recipient,
+            transaction.amount as i128,
+        );
+        Ok(())
+    }
+
+    fn revert_transaction(&mut self, transaction: &Transaction) {
+        adjust(
+            &mut self.balances,
+            &transaction.recipient,
+            -(transaction.amount as i128),
+        );
+        if !transaction.is_coinbase() {
+            adjust(&mut self.nonces, &transaction.sender, -1);
+            adjust(
+                &mut self.balances,
+                &transaction.sender,
+                transaction.amount as i128 + transaction.fee as i128,
+            );
+        }
+    }
+}
+
+/// Add `delta` to the value stored for `address`, dropping the entry when it reaches zero.
+/// Callers have already checked that the result fits in a u64.
+fn adjust(map: &mut HashMap<String, u64>, address: &str, delta: i128) {
+    let value = (map.get(address).copied().unwrap_or(0) as i128 + delta) as u64;
+    if value == 0 {
+        map.remove(address);
+    } else {
+        map.insert(address.to_string(), value);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::hash::ZERO_HASH;
+    use crate::transaction::{address, generate_keypair};
+
+    const REWARD: u64 = 50;
+
+    fn block(id: u64, transactions: Vec<Transaction>) -> Block {
+        Block::new(id, ZERO_HASH, transactions, 0)
+    }
+
+    #[test]
+    fn coinbase_and_transfers_update_balances() {
+        let alice = generate_keypair();
+        let alice_address = address(&alice.verifying_key());
+        let bob_address = address(&generate_keypair().verifying_key());
+
+        let blocks = vec![
+            block(
+                2,
+                vec![Transaction::coinbase(alice_address.clone(), REWARD, 2)],
+            ),
+            block(
+                3,
+                vec![
+                    Transaction::coinbase(bob_address.clone(), REWARD, 3),
+                    Transaction::new(&alice, bob_address.clone(), 30, 0),
+                ],
+            ),
+        ];
+        let ledger = Ledger::from_blocks(&blocks, REWARD).unwrap();
+
+        assert_eq!(ledger.balance(&alice_address), 20);
+        assert_eq!(ledger.balance(&bob_address), 80);
+        assert_eq!(ledger.next_nonce(&alice_address), 1);
+    }
+
+    #[test]
+    fn overdraft_is_rejected_and_leaves_ledger_unchanged() {
+        let alice = generate_keypair();
+        let alice_address = address(&alice.verifying_key());
+        let bob_address = address(&generate_keypair().verifying_key());
+
+        let mut ledger = Ledger::new();
+        ledger
+            .apply_block(
+                &block(
+                    2,
+                    vec![Transaction::coinbase(alice_address.clone(), REWARD, 2)],
+                ),
+                REWARD,
+            )
+            .unwrap();
+        let before = ledger.clone();
+
+        let overdraft = block(
+            3,
+            vec![
+                Transaction::new(&alice, bob_address.clone(), 40, 0),
+                Transaction::new(&alice, bob_address, 40, 1),
+            ],
+        );
+        assert!(matches!(
+            ledger.apply_block(&overdraft, REWARD),
+            Err(LedgerError::InsufficientFunds { balance: 10, .. })
+        ));
+        assert_eq!(ledger, before);
+    }
+
+    #[test]
+    fn double_spend_is_rejected() {
+        let alice = generate_keypair();
+        let alice_address = address(&alice.verifying_key());
+        let bob_address = address(&generate_keypair().verifying_key());
+        let transfer = Transaction::new(&alice, bob_address, 10, 0);
+
+        let mut ledger = Ledger::new();
+        ledger
+            .apply_block(
+                &block(2, vec![Transaction::coinbase(alice_address, REWARD, 2)]),
+                REWARD,
+            )
+            .unwrap();
+        ledger
+            .apply_block(&block(3, vec![transfer.clone()]), REWARD)
+            .unwrap();
+        assert!(matches!(
+            ledger.apply_block(&block(4, vec![transfer]), REWARD),
+            Err(LedgerError::BadNonce {
+                expected: 1,
+                found: 0,
+                ..
+            })
+        ));
+    }
+
+    #[test]
+    fn coinbase_rules_are_enforced() {
+        let miner = address(&generate_keypair().verifying_key());
+        let mut ledger = Ledger::new();
+
+        let too_much = block(2, vec![Transaction::coinbase(miner.clone(), REWARD + 1, 2)]);
+        assert_eq!(
+            ledger.apply_block(&too_much, REWARD),
+            Err(LedgerError::ExcessiveReward {
+                amount: REWARD + 1,
+                reward: REWARD
+            })
+        );
+
+        let wrong_nonce = block(2, vec![Transaction::coinbase(miner.clone(), REWARD, 7)]);
+        assert_eq!(
+            ledger.apply_block(&wrong_nonce, REWARD),
+            Err(LedgerError::BadCoinbaseNonce {
+                expected: 2,
+                found: 7
+            })
+        );
+
+        let two_coinbases = block(
+            2,
+            vec![
+                Transaction::coinbase(miner.clone(), REWARD, 2),
+                Transaction::coinbase(miner, REWARD, 2),
+            ],
+        );
+        assert_eq!(
+            ledger.apply_block(&two_coinbases, REWARD),
+            Err(LedgerError::MisplacedCoinbase)
+        );
+        assert_eq!(ledger, Ledger::new());
+    }
+
+    #[test]
+    fn fees_go_to_the_miner() {
+        let alice = generate_keypair();
+        let alice_address = address(&alice.verifying_key());
+        let bob_address = address(&generate_keypair().verifying_key());
+        let miner = address(&generate_keypair().verifying_key());
+
+        let mut ledger = Ledger::new();
+        ledger
+            .apply_block(
+                &block(
+                    2,
+                    vec![Transaction::coinbase(alice_address.clone(), REWARD, 2)],
+                ),
+                REWARD,
+            )
+            .unwrap();
+
+        let transfer = Transaction::with_fee(&alice, bob_address.clone(), 10, 3, 0);
+        let greedy = block(
+            3,
+            vec![
+                Transaction::coinbase(miner.clone(), REWARD + 4, 3),
+                transfer.clone(),
+            ],
+        );
+        assert!(matches!(
+            ledger.apply_block(&greedy, REWARD),
+            Err(LedgerError::ExcessiveReward {
+                amount: 54,
+                reward: 53
+            })
+        ));
+
+        let next = block(
+            3,
+            vec![
+                Transaction::coinbase(miner.clone(), REWARD + 3, 3),
+                transfer,
+            ],
+        );
+        ledger.apply_block(&next, REWARD).unwrap();
+        assert_eq!(ledger.balance(&alice_address), REWARD - 13);
+        assert_eq!(ledger.balance(&bob_address), 10);
+        assert_eq!(ledger.balance(&miner), REWARD + 3);
+    }
+
+    #[test]
+    fn rollback_restores_previous_state() {
+        let alice = generate_keypair();
+        let alice_address = address(&alice.verifying_key());
+        let bob_address = address(&generate_keypair().verifying_key());
+
+        let mut ledger = Ledger::new();
+        ledger
+            .apply_block(
+                &block(2, vec![Transaction::coinbase(alice_address, REWARD, 2)]),
+                REWARD,
+            )
+            .unwrap();
+        let before = ledger.clone();
+
+        let next = block(
+            3,
+            vec![
+                Transaction::coinbase(bob_address.clone(), REWARD, 3),
+                Transaction::with_fee(&alice, bob_address, 25, 5, 0),
+            ],
+        );
+        ledger.apply_block(&next, REWARD).unwrap();
+        assert_ne!(ledger, before);
+        ledger.rollback_block(&next);
+        assert_eq!(ledger, before);
+    }
+}
@@ -0,0 +1,195 @@
+use std::collections::HashMap;
+use std::io::{self, Read};
+
+use serde::{Deserialize, Serialize};
+
+use crate::block::{Block, HEADER_VERSION};
+use crate::error::{BlockError, ChainError};
+use crate::hash::{hash_from_hex, sha3_hex, Hash, ZERO_HASH};
+use crate::transaction::Transaction;
+
+/// A block in the format written before header version 1. Hashes were hex strings, and the
+/// block hash was the SHA3-256 of the header fields concatenated as decimal text, which is
+/// ambiguous: different field values can concatenate to the same string.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct LegacyBlock {
+    pub id: u64,
+    pub nonce: u64,
+    pub transactions: Vec<Transaction>,
+    pub merkle_root: String,
+    pub hash: String,
+    pub previous_hash: String,
+    pub timestamp: i64,
+    pub difficulty: u32,
+}
+
+impl LegacyBlock {
+    /// The block hash under the old rules
+    pub fn calculate_hash(&self) -> String {
+        sha3_hex(format!(
+            "{}{}{}{}{}{}",
+            self.id,
+            self.previous_hash,
+            self.merkle_root,
+            self.timestamp,
+            self.nonce,
+            self.difficulty
+        ))
+    }
+
+    /// The Merkle root under the old rules, where each node hashes the hex text of its children
+    pub fn transactions_root(&self) -> String {
+        let mut level = self
+            .transactions
+            .iter()
+            .map(|t| hex::encode(t.hash()))
+            .collect::<Vec<String>>();
+        if level.is_empty() {
+            return hex::encode(ZERO_HASH);
+        }
+        while level.len() > 1 {
+            level = level
+                .chunks(2)
+                .map(|pair| sha3_hex(format!("{}{}", pair[0], pair.get(1).unwrap_or(&pair[0]))))
+                .collect();
+        }
+        level.remove(0)
+    }
+}
+
+/// Convert legacy blocks to the current format. `legacy` may hold several branches, but every
+/// block must come after its parent, as they do in a block store.
+///
+/// Each block is first checked against the old hashing rules. Its hash then changes, so it is
+/// mined again at its original difficulty. Ids, timestamps and transactions are kept, so the
+/// migrated chain has the same difficulty schedule and the same balances.
+pub fn migrate(legacy: &[LegacyBlock]) -> Result<Vec<Block>, ChainError> {
+    // Old hash -> new hash of every migrated block
+    let mut migrated_hashes = HashMap::<&str, Hash>::new();
+    let mut blocks = Vec::new();
+
+    for (index, old) in legacy.iter().enumerate() {
+        let fail = |error| ChainError { index, error };
+        if old.calculate_hash() != old.hash {
+            return Err(fail(BlockError::HashMismatch));
+        }
+        if old.transactions_root() != old.merkle_root {
+            return Err(fail(BlockError::BadMerkleRoot));
+        }
+
+        let previous_hash = if old.id == 1 && old.previous_hash == hex::encode(ZERO_HASH) {
+            ZERO_HASH
+        } else {
+            *migrated_hashes
+                .get(old.previous_hash.as_str())
+                .ok_or_else(|| {
+                    fail(BlockError::UnknownParent {
+                        previous_hash: hash_from_hex(&old.previous_hash).unwrap_or_default(),
+                    })
+                })?
+        };
+
+        let merkle_root = Block::transactions_root(&old.transactions);
+        let (nonce, hash) = Block::mine_block(
+            old.id,
+            old.timestamp,
+            &previous_hash,
+            &merkle_root,
+            old.difficulty,
+        );
+        migrated_hashes.insert(&old.hash, hash);
+        blocks.push(Block {
+            version: HEADER_VERSION,
+            id: old.id,
+            nonce,
+            transactions: old.transactions.clone(),
+            merkle_root,
+            hash,
+            previous_hash,
+            timestamp: old.timestamp,
+            difficulty: old.difficulty,
+            signature: String::new(),
+        });
+    }
+    Ok(blocks)
+}
+
+/// Import a JSON array of legacy blocks, e.g. an old `serde_json` dump of `BlockChain::blocks`
+pub fn import_json<R: Read>(reader: R) -> io::Result<Vec<Block>> {
+    let legacy: Vec<LegacyBlock> = serde_json::from_reader(reader)?;
+    migrate(&legacy).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use crate::blockchain::BlockChain;
+    use crate::config::ChainConfig;
+    use crate::transaction::{address, generate_keypair};
+
+    /// A legacy chain of `len` blocks, each paying the block reward to a new miner
+    pub(crate) fn legacy_chain(len: u64) -> Vec<LegacyBlock> {
+        let mut blocks: Vec<LegacyBlock> = vec![];
+        for id in 1..=len {
+            let miner = address(&generate_keypair().verifying_key());
+            let mut block = LegacyBlock {
+                id,
+                nonce: id * 3,
+                transactions: vec![],
+                merkle_root: String::new(),
+                hash: String::new(),
+                previous_hash: blocks
+                    .last()
+                    .map_or(hex::encode(ZERO_HASH), |b| b.hash.clone()),
+                timestamp: 1_700_000_000 + id as i64,
+                difficulty: 1,
+            };
+            if id > 1 {
+                block.transactions = vec![Transaction::coinbase(miner, 50, id)];
+            }
+            block.merkle_root = block.transactions_root();
+            block.hash = block.calculate_hash();
+            blocks.push(block);
+        }
+        blocks
+    }
+
+    #[test]
+    fn migrated_chain_is_valid() {
+        let legacy = legacy_chain(4);
+        let blocks = migrate(&legacy).unwrap();
+
+        let config = ChainConfig {
+            initial_difficulty: 1,
+            ..ChainConfig::default()
+        };
+        let chain = BlockChain::with_config(config);
+        assert_eq!(chain.is_chain_valid(&blocks), Ok(()));
+        for (block, old) in blocks.iter().zip(&legacy) {
+            assert_eq!(block.timestamp, old.timestamp);
+            assert_eq!(block.transactions, old.transactions);
+        }
+    }
+
+    #[test]
+    fn tampered_legacy_block_is_not_migrated() {
+        let mut legacy = legacy_chain(3);
+        legacy[1].timestamp += 1;
+        assert_eq!(
+            migrate(&legacy),
+            Err(ChainError {
+                index: 1,
+                error: BlockError::HashMismatch
+            })
+        );
+    }
+
+    #[test]
+    fn legacy_json_is_imported() {
+        let legacy = legacy_chain(2);
+        let json = serde_json::to_vec(&legacy).unwrap();
+        let blocks = import_json(json.as_slice()).unwrap();
+        assert_eq!(blocks.len(), 2);
+        assert_eq!(blocks[1].previous_hash, blocks[0].hash);
+    }
+}
@@ -0,0 +1,44 @@
+//----------------------------------------------------------------
+//       Blockchain in Rust from scratch
+//----------------------------------------------------------------
+pub mod api;
+mod block;
+mod blockchain;
+mod clock;
+mod config;
+pub mod consensus;
+pub mod difficulty;
+mod error;
+pub mod explorer;
+pub mod export;
+mod hash;
+mod index;
+mod ledger;
+pub mod legacy;
+mod mempool;
+pub mod merkle;
+pub mod metrics;
+mod miner;
+pub mod node;
+pub mod protocol;
+pub mod script;
+pub mod simulation;
+pub mod snapshot;
+mod store;
+mod transaction;
+mod tree;
+pub mod wallet;
+
+pub use block::{Block, BlockHeader};
+pub use blockchain::{BlockChain, BlockStatus};
+pub use clock::{Clock, ManualClock, SystemClock};
+pub use config::ChainConfig;
+pub use error::{BlockError, ChainError, LedgerError, MempoolError, ScriptError, WalletError};
+pub use hash::{Hash, ZERO_HASH};
+pub use ledger::Ledger;
+pub use mempool::{Mempool, MempoolConfig};
+pub use miner::{CancelToken, Miner, MinerStats, MiningResult};
+pub use node::{Node, Validation};
+pub use store::BlockStore;
+pub use transaction::{address, generate_keypair, is_valid_address, Transaction, COINBASE_SENDER};
+pub use tree::BlockTree;
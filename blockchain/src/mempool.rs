@@ -0,0 +1,520 @@
+use std::cmp::Ordering;
+use std::collections::{HashMap, HashSet};
+use std::time::{Duration, Instant};
+
+use crate::block::Block;
+use crate::error::{LedgerError, MempoolError};
+use crate::hash::Hash;
+use crate::ledger::Ledger;
+use crate::script::{self, Context};
+use crate::transaction::{is_valid_address, Transaction, MAX_FIELD_LEN};
+
+/// Limits of a `Mempool`
+#[derive(Debug, Clone, PartialEq)]
+pub struct MempoolConfig {
+    /// Most transactions held at once
+    pub max_transactions: usize,
+    /// Most bytes (see `Transaction::size`) held at once
+    pub max_bytes: usize,
+    /// Transactions that are not mined within this time are dropped
+    pub max_age: Duration,
+    /// Most transaction bytes assembled into one block
+    pub max_block_bytes: usize,
+}
+
+impl Default for MempoolConfig {
+    fn default() -> Self {
+        Self {
+            max_transactions: 5_000,
+            max_bytes: 4 * 1024 * 1024,
+            max_age: Duration::from_secs(60 * 60),
+            max_block_bytes: 1024 * 1024,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+struct Entry {
+    transaction: Transaction,
+    size: usize,
+    added: Instant,
+}
+
+impl Entry {
+    /// Highest fee per byte first, then the oldest first
+    fn priority(&self, other: &Entry) -> Ordering {
+        let rate = self.transaction.fee as u128 * other.size as u128;
+        let other_rate = other.transaction.fee as u128 * self.size as u128;
+        other_rate.cmp(&rate).then(self.added.cmp(&other.added))
+    }
+}
+
+/// Pending transactions waiting to be mined.
+///
+/// Transactions are checked against the ledger of the best chain when they arrive. When the
+/// pool is full the transactions paying the lowest fee per byte are evicted first, and the
+/// next block is assembled from the highest paying ones. Spends from script addresses are
+/// checked against the next block, see `set_next_block`.
+#[derive(Debug, Default)]
+pub struct Mempool {
+    config: MempoolConfig,
+    entries: HashMap<Hash, Entry>,
+    bytes: usize,
+    next_block: Context,
+}
+
+impl Mempool {
+    pub fn new(config: MempoolConfig) -> Self {
+        Self {
+            config,
+            entries: HashMap::new(),
+            bytes: 0,
+            next_block: Context::default(),
+        }
+    }
+
+    /// Height and median time past of the next block, which the timelocks of script spends are
+    /// checked against. Set it whenever the tip changes.
+    pub fn set_next_block(&mut self, context: Context) {
+        self.next_block = context;
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// Total size of the pending transactions
+    pub fn bytes(&self) -> usize {
+        self.bytes
+    }
+
+    pub fn contains(&self, hash: &Hash) -> bool {
+        self.entries.contains_key(hash)
+    }
+
+    /// Every pending transaction, highest fee per byte first
+    pub fn transactions(&self) -> Vec<Transaction> {
+        self.sorted()
+            .into_iter()
+            .map(|entry| entry.transaction.clone())
+            .collect()
+    }
+
+    /// Validate `transaction` against `ledger` (the balances at the tip of the best chain) and
+    /// add it to the pool. Returns the transaction id.
+    ///
+    /// The sender's pending transactions count as spent: the transaction must take the nonce
+    /// after them, and the sender must afford it together with them.
+    pub fn add(&mut self, transaction: Transaction, ledger: &Ledger) -> Result<Hash, MempoolError> {
+        let hash = transaction.hash();
+        if transaction.is_coinbase() {
+            return Err(MempoolError::Coinbase);
+        }
+        if self.entries.contains_key(&hash) {
+            return Err(MempoolError::Duplicate);
+        }
+        if !is_valid_address(&transaction.recipient) {
+            return Err(MempoolError::BadRecipient);
+        }
+        if transaction.longest_field() > MAX_FIELD_LEN {
+            return Err(MempoolError::FieldTooLong {
+                len: transaction.longest_field(),
+                max: MAX_FIELD_LEN,
+            });
+        }
+        if transaction.is_script_spend() {
+            script::verify_spend(&transaction, &self.next_block)
+                .map_err(MempoolError::BadScript)?;
+        } else if !transaction.verify_signature() {
+            return Err(MempoolError::BadSignature);
+        }
+
+        let expected_nonce = ledger.next_nonce(&transaction.sender);
+        if transaction.nonce < expected_nonce {
+            return Err(MempoolError::StaleNonce {
+                expected: expected_nonce,
+                found: transaction.nonce,
+            });
+        }
+        let pending = self
+            .entries
+            .values()
+            .map(|entry| &entry.transaction)
+            .filter(|pending| {
+                pending.sender == transaction.sender && pending.nonce >= expected_nonce
+            })
+            .collect::<Vec<&Transaction>>();
+        let nonces = pending
+            .iter()
+            .map(|pending| pending.nonce)
+            .collect::<HashSet<u64>>();
+        if nonces.contains(&transaction.nonce) {
+            return Err(MempoolError::Conflict);
+        }
+        let next_nonce = (expected_nonce..)
+            .find(|nonce| !nonces.contains(nonce))
+            .expect("The pending nonces are finitely many");
+        if transaction.nonce > next_nonce {
+            return Err(MempoolError::NonceGap {
+                expected: next_nonce,
+                found: transaction.nonce,
+            });
+        }
+
+        let balance = ledger.balance(&transaction.sender);
+        let cost = pending.iter().fold(
+            transaction.amount.saturating_add(transaction.fee),
+            |cost, pending| {
+                cost.saturating_add(pending.amount)
+                    .saturating_add(pending.fee)
+            },
+        );
+        if cost > balance {
+            return Err(MempoolError::InsufficientFunds { balance, cost });
+        }
+
+        let size = transaction.size();
+        if size > self.config.max_block_bytes {
+            return Err(MempoolError::TooLarge {
+                size,
+                max: self.config.max_block_bytes,
+            });
+        }
+
+        self.evict_expired();
+        self.insert(
+            hash,
+            Entry {
+                transaction,
+                size,
+                added: Instant::now(),
+            },
+        );
+        self.evict_over_limits();
+        if !self.entries.contains_key(&hash) {
+            return Err(MempoolError::PoolFull);
+        }
+        Ok(hash)
+    }
+
+    /// Transactions for the next block on top of `ledger`: the highest fee per byte first,
+    /// as long as they apply and fit in `max_block_bytes`. A transaction whose nonce follows
+    /// another pending transaction of the same sender waits until that one is selected.
+    /// Script spends that do not satisfy their script at the next block, e.g. after a reorg
+    /// to a shorter chain, are left out.
+    pub fn select(&self, ledger: &Ledger) -> Vec<Transaction> {
+        let mut candidates = self.sorted();
+        candidates.retain(|entry| {
+            !entry.transaction.is_script_spend()
+                || script::verify_spend(&entry.transaction, &self.next_block).is_ok()
+        });
+        let mut scratch = ledger.clone();
+        let mut selected = vec![];
+        let mut bytes = 0;
+
+        loop {
+            let mut progress = false;
+            candidates.retain(|entry| {
+                if bytes + entry.size > self.config.max_block_bytes {
+                    return false;
+                }
+                match scratch.apply_transfer(&entry.transaction) {
+                    Ok(()) => {
+                        selected.push(entry.transaction.clone());
+                        bytes += entry.size;
+                        progress = true;
+                        false
+                    }
+                    Err(LedgerError::BadNonce {
+                        expected, found, ..
+                    }) => found > expected,
+                    Err(_) => false,
+                }
+            });
+            if !progress {
+                return selected;
+            }
+        }
+    }
+
+    /// Drop transactions that were mined (or replaced by another transaction with the same
+    /// nonce) on the chain that `ledger` belongs to, and transactions that are too old
+    pub fn prune(&mut self, ledger: &Ledger) {
+        self.evict_expired();
+        let stale = self
+            .entries
+            .iter()
+            .filter(|(_, entry)| {
+                entry.transaction.nonce < ledger.next_nonce(&entry.transaction.sender)
+            })
+            .map(|(hash, _)| *hash)
+            .collect::<Vec<Hash>>();
+        for hash in stale {
+            self.remove(&hash);
+        }
+    }
+
+    /// Return the transactions of blocks that a reorg dropped from the best chain.
+    /// Those that the new chain (with balances `ledger`) already contains are skipped.
+    pub fn return_transactions(&mut self, blocks: &[Block], ledger: &Ledger) {
+        for transaction in blocks.iter().flat_map(|block| &block.transactions) {
+            if !transaction.is_coinbase() {
+                // Transactions the new chain made invalid are dropped
+                let _ = self.add(transaction.clone(), ledger);
+            }
+        }
+    }
+
+    fn sorted(&self) -> Vec<&Entry> {
+        let mut entries = self.entries.values().collect::<Vec<&Entry>>();
+        entries.sort_by(|a, b| a.priority(b));
+        entries
+    }
+
+    fn insert(&mut self, hash: Hash, entry: Entry) {
+        self.bytes += entry.size;
+        self.entries.insert(hash, entry);
+    }
+
+    fn remove(&mut self, hash: &Hash) {
+        if let Some(entry) = self.entries.remove(hash) {
+            self.bytes -= entry.size;
+        }
+    }
+
+    fn evict_expired(&mut self) {
+        let max_age = self.config.max_age;
+        let expired = self
+            .entries
+            .iter()
+            .filter(|(_, entry)| entry.added.elapsed() > max_age)
+            .map(|(hash, _)| *hash)
+            .collect::<Vec<Hash>>();
+        for hash in expired {
+            self.remove(&hash);
+        }
+    }
+
+    /// Evict the lowest paying transactions until the pool is within its limits
+    fn evict_over_limits(&mut self) {
+        while self.entries.len() > self.config.max_transactions
+            || self.bytes > self.config.max_bytes
+        {
+            let Some(lowest) = self
+                .entries
+                .iter()
+                .max_by(|(_, a), (_, b)| a.priority(b))
+                .map(|(hash, _)| *hash)
+            else {
+                return;
+            };
+            self.remove(&lowest);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::hash::ZERO_HASH;
+    use crate::transaction::{address, generate_keypair};
+    use ed25519_dalek::SigningKey;
+
+    const REWARD: u64 = 100;
+
+    /// A ledger where each of `keys` holds the block reward
+    fn funded_ledger(keys: &[&SigningKey]) -> Ledger {
+        let mut ledger = Ledger::new();
+        for (i, key) in keys.iter().enumerate() {
+            let id = i as u64 + 2;
+            let coinbase = Transaction::coinbase(address(&key.verifying_key()), REWARD, id);
+            let block = Block::new(id, ZERO_HASH, vec![coinbase], 0);
+            ledger.apply_block(&block, REWARD).unwrap();
+        }
+        ledger
+    }
+
+    fn recipient() -> String {
+        address(&generate_keypair().verifying_key())
+    }
+
+    #[test]
+    fn invalid_and_duplicate_transactions_are_refused() {
+        let alice = generate_keypair();
+        let ledger = funded_ledger(&[&alice]);
+        let mut pool = Mempool::default();
+
+        let transfer = Transaction::new(&alice, recipient(), 10, 0);
+        pool.add(transfer.clone(), &ledger).unwrap();
+        assert_eq!(pool.add(transfer, &ledger), Err(MempoolError::Duplicate));
+
+        let conflicting = Transaction::new(&alice, recipient(), 20, 0);
+        assert_eq!(pool.add(conflicting, &ledger), Err(MempoolError::Conflict));
+
+        let mut forged = Transaction::new(&alice, recipient(), 10, 1);
+        forged.amount = 90;
+        assert_eq!(pool.add(forged, &ledger), Err(MempoolError::BadSignature));
+
+        let to_nobody = Transaction::new(&alice, "bob".to_string(), 10, 1);
+        assert_eq!(
+            pool.add(to_nobody, &ledger),
+            Err(MempoolError::BadRecipient)
+        );
+
+        let mut oversized = Transaction::new(&alice, recipient(), 10, 1);
+        oversized.signature = "0".repeat(MAX_FIELD_LEN + 1);
+        assert_eq!(
+            pool.add(oversized, &ledger),
+            Err(MempoolError::FieldTooLong {
+                len: MAX_FIELD_LEN + 1,
+                max: MAX_FIELD_LEN
+            })
+        );
+
+        let too_much = Transaction::new(&alice, recipient(), REWARD + 1, 1);
+        assert!(matches!(
+            pool.add(too_much, &ledger),
+            Err(MempoolError::InsufficientFunds { .. })
+        ));
+        assert_eq!(pool.len(), 1);
+    }
+
+    #[test]
+    fn block_is_assembled_by_fee_per_byte() {
+        let keys = [generate_keypair(), generate_keypair(), generate_keypair()];
+        let ledger = funded_ledger(&keys.iter().collect::<Vec<_>>());
+        let low = Transaction::with_fee(&keys[0], recipient(), 10, 1, 0);
+        let high = Transaction::with_fee(&keys[1], recipient(), 10, 9, 0);
+        let medium = Transaction::with_fee(&keys[2], recipient(), 10, 5, 0);
+
+        let mut pool = Mempool::new(MempoolConfig {
+            max_block_bytes: 2 * high.size(),
+            ..MempoolConfig::default()
+        });
+        for transaction in [&low, &high, &medium] {
+            pool.add(transaction.clone(), &ledger).unwrap();
+        }
+        assert_eq!(pool.select(&ledger), vec![high, medium]);
+    }
+
+    #[test]
+    fn later_nonces_follow_earlier_ones() {
+        let alice = generate_keypair();
+        let ledger = funded_ledger(&[&alice]);
+        let mut pool = Mempool::default();
+
+        // The second transfer pays more, but cannot be mined before the first
+        let first = Transaction::with_fee(&alice, recipient(), 10, 1, 0);
+        let second = Transaction::with_fee(&alice, recipient(), 10, 8, 1);
+        pool.add(first.clone(), &ledger).unwrap();
+        pool.add(second.clone(), &ledger).unwrap();
+        assert_eq!(pool.select(&ledger), vec![first, second]);
+    }
+
+    #[test]
+    fn pending_transactions_count_against_the_sender() {
+        let alice = generate_keypair();
+        let ledger = funded_ledger(&[&alice]);
+        let mut pool = Mempool::default();
+
+        let skipping = Transaction::new(&alice, recipient(), 10, 1);
+        assert_eq!(
+            pool.add(skipping, &ledger),
+            Err(MempoolError::NonceGap {
+                expected: 0,
+                found: 1
+            })
+        );
+
+        // Each transfer alone is affordable, both together are not
+        pool.add(Transaction::new(&alice, recipient(), 60, 0), &ledger)
+            .unwrap();
+        let overspending = Transaction::new(&alice, recipient(), 60, 1);
+        assert_eq!(
+            pool.add(overspending, &ledger),
+            Err(MempoolError::InsufficientFunds {
+                balance: REWARD,
+                cost: 120
+            })
+        );
+        pool.add(Transaction::new(&alice, recipient(), 40, 1), &ledger)
+            .unwrap();
+        assert_eq!(pool.len(), 2);
+    }
+
+    #[test]
+    fn full_pool_evicts_lowest_fee_rate() {
+        let keys = [
+            generate_keypair(),
+            generate_keypair(),
+            generate_keypair(),
+            generate_keypair(),
+        ];
+        let ledger = funded_ledger(&keys.iter().collect::<Vec<_>>());
+        let mut pool = Mempool::new(MempoolConfig {
+            max_transactions: 2,
+            ..MempoolConfig::default()
+        });
+
+        let low = Transaction::with_fee(&keys[0], recipient(), 10, 1, 0);
+        let high = Transaction::with_fee(&keys[1], recipient(), 10, 9, 0);
+        pool.add(low.clone(), &ledger).unwrap();
+        pool.add(high.clone(), &ledger).unwrap();
+
+        // Paying less than everything in the pool, the new transaction does not get in
+        let free = Transaction::new(&keys[2], recipient(), 10, 0);
+        assert_eq!(pool.add(free, &ledger), Err(MempoolError::PoolFull));
+
+        let medium = Transaction::with_fee(&keys[3], recipient(), 10, 5, 0);
+        pool.add(medium.clone(), &ledger).unwrap();
+        assert_eq!(pool.transactions(), vec![high, medium]);
+        assert!(!pool.contains(&low.hash()));
+    }
+
+    #[test]
+    fn old_transactions_expire() {
+        let alice = generate_keypair();
+        let ledger = funded_ledger(&[&alice]);
+        let mut pool = Mempool::new(MempoolConfig {
+            max_age: Duration::ZERO,
+            ..MempoolConfig::default()
+        });
+        pool.add(Transaction::new(&alice, recipient(), 10, 0), &ledger)
+            .unwrap();
+        std::thread::sleep(Duration::from_millis(5));
+        pool.prune(&ledger);
+        assert!(pool.is_empty());
+        assert_eq!(pool.bytes(), 0);
+    }
+
+    #[test]
+    fn mined_transactions_leave_and_reorged_ones_return() {
+        let alice = generate_keypair();
+        let ledger = funded_ledger(&[&alice]);
+        let mut pool = Mempool::default();
+        let transfer = Transaction::new(&alice, recipient(), 10, 0);
+        pool.add(transfer.clone(), &ledger).unwrap();
+
+        let block = Block::new(
+            9,
+            ZERO_HASH,
+            vec![
+                Transaction::coinbase(recipient(), REWARD, 9),
+                transfer.clone(),
+            ],
+            0,
+        );
+        let mut mined = ledger.clone();
+        mined.apply_block(&block, REWARD).unwrap();
+        pool.prune(&mined);
+        assert!(pool.is_empty());
+
+        // A reorg drops the block again: the transfer is pending once more
+        pool.return_transactions(&[block], &ledger);
+        assert_eq!(pool.transactions(), vec![transfer]);
+    }
+}
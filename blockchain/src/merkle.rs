@@ -0,0 +1,155 @@
+use serde::{Deserialize, Serialize};
+
+use crate::hash::{self, sha3, Hash, ZERO_HASH};
+
+/// Merkle root of an empty list of leaves
+pub const EMPTY_MERKLE_ROOT: Hash = ZERO_HASH;
+
+/// Hash of an inner node: SHA3-256 over the two child hashes, left first
+pub fn hash_pair(left: &Hash, right: &Hash) -> Hash {
+    sha3([left.as_slice(), right.as_slice()].concat())
+}
+
+/// Merkle root over the given leaf hashes. Each level hashes adjacent pairs together;
+/// when a level has an odd number of nodes the last one is paired with itself (as in Bitcoin).
+/// So `[a, b, c]` and `[a, b, c, c]` have the same root, and blocks must not repeat a
+/// transaction.
+pub fn merkle_root(leaves: &[Hash]) -> Hash {
+    if leaves.is_empty() {
+        return EMPTY_MERKLE_ROOT;
+    }
+
+    let mut level = leaves.to_vec();
+    while level.len() > 1 {
+        level = level
+            .chunks(2)
+            .map(|pair| {
+                let left = &pair[0];
+                let right = pair.get(1).unwrap_or(left);
+                hash_pair(left, right)
+            })
+            .collect();
+    }
+    level[0]
+}
+
+/// Which side of the running hash a proof step's sibling sits on
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Side {
+    Left,
+    Right,
+}
+
+/// One level of a `MerkleProof`: the sibling of the node on the path from the leaf to the root
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ProofStep {
+    pub side: Side,
+    #[serde(with = "hash::hex_hash")]
+    pub hash: Hash,
+}
+
+/// Proof that a leaf is part of a Merkle tree, checked with nothing but the tree's root
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct MerkleProof {
+    pub steps: Vec<ProofStep>,
+}
+
+impl MerkleProof {
+    /// The root obtained by hashing `leaf` up the tree along the proof's siblings
+    pub fn root(&self, leaf: &Hash) -> Hash {
+        self.steps.iter().fold(*leaf, |node, step| match step.side {
+            Side::Left => hash_pair(&step.hash, &node),
+            Side::Right => hash_pair(&node, &step.hash),
+        })
+    }
+
+    /// Is `leaf` part of the tree with the given root?
+    pub fn verify(&self, leaf: &Hash, root: &Hash) -> bool {
+        self.root(leaf) == *root
+    }
+}
+
+/// Proof for the leaf at `index`, built the same way as `merkle_root`. `None` if there is no such leaf.
+pub fn merkle_proof(leaves: &[Hash], index: usize) -> Option<MerkleProof> {
+    if index >= leaves.len() {
+        return None;
+    }
+
+    let mut steps = Vec::new();
+    let mut level = leaves.to_vec();
+    let mut index = index;
+    while level.len() > 1 {
+        let step = if index.is_multiple_of(2) {
+            ProofStep {
+                side: Side::Right,
+                hash: *level.get(index + 1).unwrap_or(&level[index]),
+            }
+        } else {
+            ProofStep {
+                side: Side::Left,
+                hash: level[index - 1],
+            }
+        };
+        steps.push(step);
+        level = level
+            .chunks(2)
+            .map(|pair| hash_pair(&pair[0], pair.get(1).unwrap_or(&pair[0])))
+            .collect();
+        index /= 2;
+    }
+    Some(MerkleProof { steps })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn leaves(n: usize) -> Vec<Hash> {
+        (0..n).map(|i| sha3(i.to_string())).collect()
+    }
+
+    #[test]
+    fn root_of_one_leaf_is_the_leaf() {
+        let leaves = leaves(1);
+        assert_eq!(merkle_root(&leaves), leaves[0]);
+        assert_eq!(merkle_root(&[]), EMPTY_MERKLE_ROOT);
+    }
+
+    #[test]
+    fn odd_leaf_is_paired_with_itself() {
+        let leaves = leaves(3);
+        let left = hash_pair(&leaves[0], &leaves[1]);
+        let right = hash_pair(&leaves[2], &leaves[2]);
+        assert_eq!(merkle_root(&leaves), hash_pair(&left, &right));
+    }
+
+    #[test]
+    fn root_depends_on_leaf_order() {
+        let mut leaves = leaves(4);
+        let root = merkle_root(&leaves);
+        leaves.swap(1, 2);
+        assert_ne!(merkle_root(&leaves), root);
+    }
+
+    #[test]
+    fn every_leaf_has_a_valid_proof() {
+        for n in 1..=7 {
+            let leaves = leaves(n);
+            let root = merkle_root(&leaves);
+            for (index, leaf) in leaves.iter().enumerate() {
+                let proof = merkle_proof(&leaves, index).unwrap();
+                assert!(proof.verify(leaf, &root), "leaf {} of {}", index, n);
+            }
+        }
+        assert_eq!(merkle_proof(&leaves(3), 3), None);
+    }
+
+    #[test]
+    fn proof_does_not_verify_another_leaf_or_root() {
+        let leaves = leaves(5);
+        let root = merkle_root(&leaves);
+        let proof = merkle_proof(&leaves, 2).unwrap();
+        assert!(!proof.verify(&leaves[3], &root));
+        assert!(!proof.verify(&leaves[2], &merkle_root(&leaves[..4])));
+    }
+}
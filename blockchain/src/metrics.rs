@@ -0,0 +1,338 @@
+use std::collections::BTreeMap;
+use std::fmt::{self, Write as _};
+use std::io::Write;
+use std::net::SocketAddr;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
+
+use serde::Serialize;
+use serde_json::Value;
+
+use crate::miner::MinerStats;
+
+/// Something a node did. Logged as one JSON object per line: the fields of the variant, its
+/// name as `event`, the RFC 3339 `time` and the `node` address.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub enum Event {
+    NodeStarted,
+    ApiStarted {
+        address: SocketAddr,
+    },
+    ConnectionError {
+        error: String,
+    },
+    /// This node sealed a block. `hash_rate` is only known for proof-of-work.
+    BlockMined {
+        id: u64,
+        hash: String,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        hash_rate: Option<f64>,
+    },
+    /// A mined or received block joined the block tree or the orphan pool.
+    /// `status` is the `BlockStatus::name` of what happened to it.
+    BlockAdded {
+        id: u64,
+        hash: String,
+        status: &'static str,
+    },
+    /// `reason` is the `BlockError::reason` of the rejection
+    BlockRejected {
+        id: u64,
+        hash: String,
+        reason: &'static str,
+        error: String,
+    },
+    /// The node replaced its chain with a peer's chain
+    ChainSwitched {
+        height: u64,
+        tip: String,
+    },
+    /// A peer's chain was selected but could not replace the node's chain
+    SyncFailed {
+        error: String,
+    },
+    /// The block store ended with a record torn by a crash, the bytes from `offset` on were cut
+    StoreTruncated {
+        offset: u64,
+        bytes: u64,
+    },
+    /// The block store held blocks in the old JSON format and was rewritten in the binary one
+    StoreMigrated {
+        blocks: usize,
+    },
+    /// A stored block is no longer valid and was left out when the store was replayed
+    StoredBlockSkipped {
+        id: u64,
+        hash: String,
+        reason: &'static str,
+        error: String,
+    },
+    /// Pruning failed, the chain keeps its blocks until the next snapshot is due
+    PruneFailed {
+        height: u64,
+        error: String,
+    },
+    /// The chain was started from a trusted snapshot instead of the genesis block
+    SnapshotRestored {
+        height: u64,
+    },
+    /// No peer had a chain, so this node sealed a genesis block
+    GenesisGenerated {
+        hash: String,
+    },
+    /// This node cannot seal the genesis block and waits for a peer's
+    WaitingForGenesis,
+}
+
+/// Writes events as JSON lines
+pub struct EventLog {
+    /// `None` writes to stdout through `println!`, so test harnesses capture it
+    output: Mutex<Option<Box<dyn Write + Send>>>,
+}
+
+impl fmt::Debug for EventLog {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("EventLog").finish_non_exhaustive()
+    }
+}
+
+impl Default for EventLog {
+    fn default() -> Self {
+        Self {
+            output: Mutex::new(None),
+        }
+    }
+}
+
+impl EventLog {
+    /// Write the following events to `output` instead of stdout
+    pub fn set_output(&self, output: Box<dyn Write + Send>) {
+        *self.output.lock().unwrap() = Some(output);
+    }
+
+    pub fn write(&self, node: SocketAddr, event: &Event) {
+        let Ok(Value::Object(mut line)) = serde_json::to_value(event) else {
+            return;
+        };
+        line.insert(
+            "time".to_string(),
+            Value::String(chrono::Utc::now().to_rfc3339()),
+        );
+        line.insert("node".to_string(), Value::String(node.to_string()));
+        let line = Value::Object(line).to_string();
+
+        match self.output.lock().unwrap().as_mut() {
+            // A log that cannot be written must not stop the node
+            Some(output) => {
+                let _ = writeln!(output, "{}", line).and_then(|()| output.flush());
+            }
+            None => println!("{}", line),
+        }
+    }
+}
+
+/// Values read from the node when the metrics are scraped
+#[derive(Debug, Clone, Default)]
+pub struct Gauges {
+    /// Id of the last block of the best chain, 0 without a genesis block
+    pub height: u64,
+    pub peers: usize,
+    pub mempool_transactions: usize,
+    /// `None` if the consensus engine does not mine
+    pub miner: Option<MinerStats>,
+}
+
+/// Counters of a node's events, rendered together with its `Gauges` in the Prometheus text
+/// exposition format
+#[derive(Debug, Default)]
+pub struct Metrics {
+    blocks_mined: AtomicU64,
+    blocks_added: Mutex<BTreeMap<&'static str, u64>>,
+    blocks_rejected: Mutex<BTreeMap<&'static str, u64>>,
+    chain_switches: AtomicU64,
+}
+
+impl Metrics {
+    pub fn record(&self, event: &Event) {
+        match event {
+            Event::BlockMined { .. } => {
+                self.blocks_mined.fetch_add(1, Ordering::Relaxed);
+            }
+            Event::BlockAdded { status, .. } => {
+                *self.blocks_added.lock().unwrap().entry(status).or_default() += 1;
+            }
+            Event::BlockRejected { reason, .. } => {
+                *self
+                    .blocks_rejected
+                    .lock()
+                    .unwrap()
+                    .entry(reason)
+                    .or_default() += 1;
+            }
+            Event::ChainSwitched { .. } => {
+                self.chain_switches.fetch_add(1, Ordering::Relaxed);
+            }
+            _ => {}
+        }
+    }
+
+    pub fn render(&self, gauges: &Gauges) -> String {
+        let mut text = String::new();
+        let mut metric = |name: &str, kind: &str, help: &str, samples: Vec<(String, String)>| {
+            let _ = writeln!(text, "# HELP {} {}", name, help);
+            let _ = writeln!(text, "# TYPE {} {}", name, kind);
+            for (labels, value) in samples {
+                let _ = writeln!(text, "{}{} {}", name, labels, value);
+            }
+        };
+        let single = |value: String| vec![(String::new(), value)];
+        let by = |label: &str, counts: &BTreeMap<&'static str, u64>| {
+            counts
+                .iter()
+                .map(|(name, count)| (format!("{{{}=\"{}\"}}", label, name), count.to_string()))
+                .collect()
+        };
+
+        metric(
+            "blockchain_height",
+            "gauge",
+            "Id of the last block of the best chain.",
+            single(gauges.height.to_string()),
+        );
+        metric(
+            "blockchain_peers",
+            "gauge",
+            "Number of known peers.",
+            single(gauges.peers.to_string()),
+        );
+        metric(
+            "blockchain_mempool_transactions",
+            "gauge",
+            "Number of transactions waiting to be mined.",
+            single(gauges.mempool_transactions.to_string()),
+        );
+        if let Some(miner) = &gauges.miner {
+            metric(
+                "blockchain_hash_rate",
+                "gauge",
+                "Hashes per second of the most recent nonce search.",
+                single(format!("{:.0}", miner.last_hash_rate)),
+            );
+            metric(
+                "blockchain_hashes_total",
+                "counter",
+                "Hashes computed while mining.",
+                single(miner.hashes.to_string()),
+            );
+            metric(
+                "blockchain_mining_seconds_total",
+                "counter",
+                "Time spent mining.",
+                single(format!("{:.3}", miner.elapsed.as_secs_f64())),
+            );
+        }
+        metric(
+            "blockchain_blocks_mined_total",
+            "counter",
+            "Blocks sealed by this node.",
+            single(self.blocks_mined.load(Ordering::Relaxed).to_string()),
+        );
+        metric(
+            "blockchain_blocks_added_total",
+            "counter",
+            "Blocks added, by what happened to them.",
+            by("status", &self.blocks_added.lock().unwrap()),
+        );
+        metric(
+            "blockchain_blocks_rejected_total",
+            "counter",
+            "Blocks rejected, by reason.",
+            by("reason", &self.blocks_rejected.lock().unwrap()),
+        );
+        metric(
+            "blockchain_chain_switches_total",
+            "counter",
+            "Times the chain was replaced by a peer's chain.",
+            single(self.chain_switches.load(Ordering::Relaxed).to_string()),
+        );
+        text
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use super::*;
+
+    #[test]
+    fn rejected_blocks_are_counted_by_reason() {
+        let metrics = Metrics::default();
+        for reason in ["bad_id", "bad_seal", "bad_id"] {
+            metrics.record(&Event::BlockRejected {
+                id: 2,
+                hash: String::new(),
+                reason,
+                error: String::new(),
+            });
+        }
+        let text = metrics.render(&Gauges {
+            height: 5,
+            peers: 2,
+            ..Gauges::default()
+        });
+
+        assert!(text.contains("# TYPE blockchain_blocks_rejected_total counter\n"));
+        assert!(text.contains("blockchain_blocks_rejected_total{reason=\"bad_id\"} 2\n"));
+        assert!(text.contains("blockchain_blocks_rejected_total{reason=\"bad_seal\"} 1\n"));
+        assert!(text.contains("blockchain_height 5\n"));
+        assert!(text.contains("blockchain_peers 2\n"));
+        // Without a miner there is no hash rate
+        assert!(!text.contains("blockchain_hash_rate"));
+    }
+
+    /// Collects what an `EventLog` writes
+    #[derive(Clone, Default)]
+    struct Buffer(Arc<Mutex<Vec<u8>>>);
+
+    impl Write for Buffer {
+        fn write(&mut self, bytes: &[u8]) -> std::io::Result<usize> {
+            self.0.lock().unwrap().write(bytes)
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn events_are_logged_as_json_lines() {
+        let buffer = Buffer::default();
+        let log = EventLog::default();
+        log.set_output(Box::new(buffer.clone()));
+        let node = "127.0.0.1:9000".parse().unwrap();
+        log.write(node, &Event::NodeStarted);
+        log.write(
+            node,
+            &Event::BlockAdded {
+                id: 3,
+                hash: "ab".to_string(),
+                status: "added",
+            },
+        );
+
+        let text = String::from_utf8(buffer.0.lock().unwrap().clone()).unwrap();
+        let lines = text
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect::<Vec<Value>>();
+        assert_eq!(lines.len(), 2);
+        assert_eq!(lines[0]["event"], "node_started");
+        assert_eq!(lines[1]["event"], "block_added");
+        assert_eq!(lines[1]["node"], "127.0.0.1:9000");
+        assert_eq!(lines[1]["id"], 3);
+        assert_eq!(lines[1]["status"], "added");
+        assert!(lines[1]["time"].is_string());
+    }
+}
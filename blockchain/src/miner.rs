@@ -0,0 +1,201 @@
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crate::difficulty::hash_meets_difficulty;
+use crate::hash::Hash;
+
+/// Cancellation flag shared between a running miner and whoever may want to stop it,
+/// for example a node that just received a competing block
+#[derive(Debug, Clone, Default)]
+pub struct CancelToken(Arc<AtomicBool>);
+
+impl CancelToken {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn cancel(&self) {
+        self.0.store(true, Ordering::Relaxed);
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.0.load(Ordering::Relaxed)
+    }
+}
+
+/// A successful nonce search
+#[derive(Debug, Clone)]
+pub struct MiningResult {
+    pub nonce: u64,
+    pub hash: Hash,
+    /// Hashes computed by all workers together
+    pub hashes: u64,
+    pub elapsed: Duration,
+}
+
+impl MiningResult {
+    /// Hashes per second over the whole search
+    pub fn hash_rate(&self) -> f64 {
+        self.hashes as f64 / self.elapsed.as_secs_f64().max(f64::EPSILON)
+    }
+}
+
+/// Work done by a miner over all its searches, cancelled ones included
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct MinerStats {
+    pub hashes: u64,
+    pub elapsed: Duration,
+    /// Hashes per second of the most recent search
+    pub last_hash_rate: f64,
+}
+
+/// Multi-threaded proof-of-work miner. Worker `i` of `n` tries the nonces `i, i + n, i + 2n, ...`
+///
+/// Clones share their `stats`.
+#[derive(Debug, Clone)]
+pub struct Miner {
+    threads: usize,
+    stats: Arc<Mutex<MinerStats>>,
+}
+
+impl Default for Miner {
+    fn default() -> Self {
+        let threads = thread::available_parallelism().map_or(1, |n| n.get());
+        Self::new(threads)
+    }
+}
+
+impl Miner {
+    pub fn new(threads: usize) -> Self {
+        Self {
+            threads: threads.max(1),
+            stats: Arc::default(),
+        }
+    }
+
+    pub fn threads(&self) -> usize {
+        self.threads
+    }
+
+    pub fn stats(&self) -> MinerStats {
+        *self.stats.lock().unwrap()
+    }
+
+    /// Search for a nonce whose hash meets `difficulty`. `hash_nonce` hashes the block header
+    /// with the given nonce. Returns `None` if the search was cancelled through `cancel`.
+    pub fn mine<F>(
+        &self,
+        difficulty: u32,
+        hash_nonce: F,
+        cancel: &CancelToken,
+    ) -> Option<MiningResult>
+    where
+        F: Fn(u64) -> Hash + Sync,
+    {
+        let start = Instant::now();
+        let found = AtomicBool::new(false);
+        let hashes = AtomicU64::new(0);
+        let solution = Mutex::new(None);
+        let step = self.threads as u64;
+
+        thread::scope(|scope| {
+            for worker in 0..step {
+                let (found, hashes, solution, hash_nonce) =
+                    (&found, &hashes, &solution, &hash_nonce);
+                scope.spawn(move || {
+                    let mut nonce = worker;
+                    let mut tried = 0;
+                    while !found.load(Ordering::Relaxed) && !cancel.is_cancelled() {
+                        let hash = hash_nonce(nonce);
+                        tried += 1;
+                        if hash_meets_difficulty(&hash, difficulty) {
+                            // Only the first worker to find a solution gets to report it
+                            if !found.swap(true, Ordering::Relaxed) {
+                                *solution.lock().unwrap() = Some((nonce, hash));
+                            }
+                            break;
+                        }
+                        match nonce.checked_add(step) {
+                            Some(next) => nonce = next,
+                            None => break,
+                        }
+                    }
+                    hashes.fetch_add(tried, Ordering::Relaxed);
+                });
+            }
+        });
+
+        let hashes = hashes.into_inner();
+        let elapsed = start.elapsed();
+        {
+            let mut stats = self.stats.lock().unwrap();
+            stats.hashes += hashes;
+            stats.elapsed += elapsed;
+            stats.last_hash_rate = hashes as f64 / elapsed.as_secs_f64().max(f64::EPSILON);
+        }
+
+        let (nonce, hash) = solution.into_inner().unwrap()?;
+        Some(MiningResult {
+            nonce,
+            hash,
+            hashes,
+            elapsed,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::block::Block;
+
+    fn hash_for(nonce: u64) -> Hash {
+        Block::calculate_hash(2, 1_700_000_000, &[1; 32], &[2; 32], nonce, 8)
+    }
+
+    #[test]
+    fn parallel_miner_finds_valid_nonce() {
+        let result = Miner::new(4)
+            .mine(8, hash_for, &CancelToken::new())
+            .unwrap();
+        assert_eq!(result.hash, hash_for(result.nonce));
+        assert!(hash_meets_difficulty(&result.hash, 8));
+        assert!(result.hashes > 0);
+    }
+
+    #[test]
+    fn miner_stats_add_up_every_search() {
+        let miner = Miner::new(2);
+        let first = miner.mine(8, hash_for, &CancelToken::new()).unwrap();
+        let second = miner
+            .clone()
+            .mine(4, hash_for, &CancelToken::new())
+            .unwrap();
+        let stats = miner.stats();
+        assert_eq!(stats.hashes, first.hashes + second.hashes);
+        assert!(stats.last_hash_rate > 0.0);
+    }
+
+    #[test]
+    fn cancelled_miner_returns_none() {
+        let cancel = CancelToken::new();
+        cancel.cancel();
+        assert!(Miner::new(2).mine(8, hash_for, &cancel).is_none());
+    }
+
+    #[test]
+    fn miner_can_be_cancelled_from_another_thread() {
+        let cancel = CancelToken::new();
+        let canceller = cancel.clone();
+        let handle = thread::spawn(move || {
+            thread::sleep(Duration::from_millis(50));
+            canceller.cancel();
+        });
+
+        // No hash has 256 leading zero bits, so only cancellation can end this search
+        assert!(Miner::new(2).mine(256, hash_for, &cancel).is_none());
+        handle.join().unwrap();
+    }
+}
@@ -0,0 +1,445 @@
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::net::{Shutdown, SocketAddr, TcpListener, TcpStream, ToSocketAddrs};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+
+use serde::{Deserialize, Serialize};
+
+use crate::block::Block;
+use crate::blockchain::BlockChain;
+use crate::error::{ChainError, MempoolError};
+use crate::hash::Hash;
+use crate::mempool::Mempool;
+use crate::metrics::{Event, EventLog, Gauges, Metrics};
+use crate::miner::CancelToken;
+use crate::protocol::{self, Outcome, SyncState};
+use crate::transaction::Transaction;
+
+pub use crate::protocol::Message;
+
+const PEER_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Longest message line read from a peer, twice what a `Blocks` reply is filled up to, so a
+/// peer cannot make the node buffer an endless line
+const MAX_MESSAGE_LEN: u64 = 2 * protocol::MAX_REPLY_BYTES as u64;
+
+/// Most connections from peers handled at once, each on its own thread. Further connections
+/// are closed right away, their messages are lost like any other.
+const MAX_PEER_CONNECTIONS: usize = 32;
+
+/// Outcome of validating every block of the best chain, as of `height`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Validation {
+    pub height: u64,
+    pub result: Result<(), ChainError>,
+}
+
+/// A blockchain node listening on a TCP port. It gossips new blocks and pending
+/// transactions to its peers and syncs its chain from them, as `protocol::handle_message`
+/// has it do. Each message is one JSON line, and a connection carries a single message,
+/// optionally answered by a single reply.
+///
+/// What the node does is written to its `event_log` and counted in its `metrics`.
+#[derive(Debug)]
+pub struct Node {
+    address: SocketAddr,
+    chain: Mutex<BlockChain>,
+    mempool: Mutex<Mempool>,
+    peers: Mutex<Vec<SocketAddr>>,
+    mining: Mutex<CancelToken>,
+    sync: Mutex<SyncState>,
+    /// Connections from peers being handled
+    connections: AtomicUsize,
+    /// The last full validation of the chain. Blocks are validated as they are added, so this
+    /// is only redone on request (see `validate_chain`).
+    validation: Mutex<Validation>,
+    events: EventLog,
+    metrics: Metrics,
+}
+
+impl Node {
+    /// Bind to `address` (port 0 picks a free port) and start serving peers in the background
+    pub fn start<A: ToSocketAddrs>(address: A, chain: BlockChain) -> io::Result<Arc<Node>> {
+        Node::start_with_log(address, chain, EventLog::default())
+    }
+
+    /// Same as `start`, writing events to `events` from the first one on
+    pub fn start_with_log<A: ToSocketAddrs>(
+        address: A,
+        mut chain: BlockChain,
+        events: EventLog,
+    ) -> io::Result<Arc<Node>> {
+        let listener = TcpListener::bind(address)?;
+        let validation = validate(&chain);
+        let opened = chain.take_events();
+        let node = Arc::new(Node {
+            address: listener.local_addr()?,
+            chain: Mutex::new(chain),
+            mempool: Mutex::new(Mempool::default()),
+            peers: Mutex::new(vec![]),
+            mining: Mutex::new(CancelToken::new()),
+            sync: Mutex::new(SyncState::default()),
+            connections: AtomicUsize::new(0),
+            validation: Mutex::new(validation),
+            events,
+            metrics: Metrics::default(),
+        });
+
+        let server = Arc::clone(&node);
+        thread::spawn(move || {
+            for stream in listener.incoming() {
+                let Ok(stream) = stream else { continue };
+                if server.connections.fetch_add(1, Ordering::SeqCst) >= MAX_PEER_CONNECTIONS {
+                    server.connections.fetch_sub(1, Ordering::SeqCst);
+                    continue;
+                }
+                let server = Arc::clone(&server);
+                thread::spawn(move || {
+                    let _slot = ConnectionSlot(&server.connections);
+                    if let Err(e) = server.handle_connection(stream) {
+                        server.log(Event::ConnectionError {
+                            error: e.to_string(),
+                        });
+                    }
+                });
+            }
+        });
+
+        node.log(Event::NodeStarted);
+        for event in opened {
+            node.log(event);
+        }
+        Ok(node)
+    }
+
+    pub fn address(&self) -> SocketAddr {
+        self.address
+    }
+
+    /// Where events are written, stdout unless redirected with `EventLog::set_output`
+    pub fn event_log(&self) -> &EventLog {
+        &self.events
+    }
+
+    /// Count `event` in the metrics and write it to the event log
+    pub fn log(&self, event: Event) {
+        self.metrics.record(&event);
+        self.events.write(self.address, &event);
+    }
+
+    /// The node's metrics in the Prometheus text exposition format
+    pub fn metrics(&self) -> String {
+        let (height, miner) = self.with_chain(|chain| {
+            (
+                chain.blocks.last().map_or(0, |block| block.id),
+                chain.consensus().miner().map(|miner| miner.stats()),
+            )
+        });
+        self.metrics.render(&Gauges {
+            height,
+            peers: self.peers.lock().unwrap().len(),
+            mempool_transactions: self.mempool.lock().unwrap().len(),
+            miner,
+        })
+    }
+
+    pub fn add_peer(&self, peer: SocketAddr) {
+        let mut peers = self.peers.lock().unwrap();
+        if peer != self.address && !peers.contains(&peer) {
+            peers.push(peer);
+        }
+    }
+
+    pub fn peers(&self) -> Vec<SocketAddr> {
+        self.peers.lock().unwrap().clone()
+    }
+
+    /// Run `f` with read access to the node's chain
+    pub fn with_chain<R>(&self, f: impl FnOnce(&BlockChain) -> R) -> R {
+        f(&self.chain.lock().unwrap())
+    }
+
+    /// Run `f` with write access to the node's chain
+    pub fn with_chain_mut<R>(&self, f: impl FnOnce(&mut BlockChain) -> R) -> R {
+        f(&mut self.chain.lock().unwrap())
+    }
+
+    /// Result of the last full validation of the chain, done when the node started or by
+    /// `validate_chain`
+    pub fn validation(&self) -> Validation {
+        self.validation.lock().unwrap().clone()
+    }
+
+    /// Validate every block of the chain again and remember the result. This re-hashes and
+    /// re-verifies the whole chain while holding it, so mining and gossip wait until it is done.
+    pub fn validate_chain(&self) -> Validation {
+        let validation = self.with_chain(validate);
+        *self.validation.lock().unwrap() = validation.clone();
+        validation
+    }
+
+    /// The last block of the node's chain
+    pub fn tip(&self) -> Option<Block> {
+        self.with_chain(|chain| chain.blocks.last().cloned())
+    }
+
+    /// Pending transactions, highest fee per byte first
+    pub fn pending_transactions(&self) -> Vec<Transaction> {
+        self.mempool.lock().unwrap().transactions()
+    }
+
+    /// Add a transaction to the mempool and gossip it. Returns the transaction id, or why
+    /// the mempool refused it.
+    pub fn submit_transaction(&self, transaction: Transaction) -> Result<Hash, MempoolError> {
+        let hash = self.with_chain(|chain| {
+            protocol::add_transaction(
+                chain,
+                &mut self.mempool.lock().unwrap(),
+                transaction.clone(),
+            )
+        })?;
+        self.broadcast(Message::NewTransaction(transaction));
+        Ok(hash)
+    }
+
+    /// Mine one block on top of the current tip with the pending transactions that pay the
+    /// highest fee per byte, paying the reward and fees to `miner_address`. Mining is cancelled
+    /// (returning `None`) if another block arrives first, or the chain's consensus engine may not
+    /// seal the block.
+    pub fn mine_block(&self, miner_address: &str) -> Option<Block> {
+        let cancel = CancelToken::new();
+        *self.mining.lock().unwrap() = cancel.clone();
+
+        let (template, consensus) = {
+            let chain = self.chain.lock().unwrap();
+            chain.blocks.last()?;
+            let pending = {
+                let mut mempool = self.mempool.lock().unwrap();
+                mempool.set_next_block(chain.next_block_context());
+                mempool.select(chain.ledger())
+            };
+            (
+                chain.block_template(miner_address, pending),
+                Arc::clone(chain.consensus()),
+            )
+        };
+
+        let block = consensus.seal(template, &cancel)?;
+        self.log(Event::BlockMined {
+            id: block.id,
+            hash: hex::encode(block.hash),
+            hash_rate: consensus.miner().map(|miner| miner.stats().last_hash_rate),
+        });
+        let mut outcome = Outcome::default();
+        {
+            let mut chain = self.chain.lock().unwrap();
+            protocol::receive_block(
+                &mut chain,
+                &mut self.mempool.lock().unwrap(),
+                &mut self.sync.lock().unwrap(),
+                block.clone(),
+                None,
+                &mut outcome,
+            );
+        }
+        let added = outcome.tip_changed;
+        self.carry_out(outcome);
+        added.then_some(block)
+    }
+
+    /// Ask every peer for the blocks after our tip, one peer after the other. Blocks that
+    /// connect are added to the block tree, which switches to a fork with more work.
+    pub fn sync(&self) {
+        for peer in self.peers() {
+            let request = self.with_chain(|chain| {
+                protocol::start_sync(chain, &mut self.sync.lock().unwrap(), peer)
+            });
+            self.exchange(vec![(peer, request)]);
+        }
+    }
+
+    /// Handle `message` from `from` with the node's chain, mempool and syncs, and carry out
+    /// what that leads to. Returns the reply to `from`, if any.
+    fn handle(&self, from: SocketAddr, message: Message) -> Option<Message> {
+        let mut outcome = {
+            let mut chain = self.chain.lock().unwrap();
+            protocol::handle_message(
+                &mut chain,
+                &mut self.mempool.lock().unwrap(),
+                &mut self.sync.lock().unwrap(),
+                from,
+                message,
+            )
+        };
+        let reply = outcome.reply.take();
+        self.carry_out(outcome);
+        reply
+    }
+
+    /// Log the events of `outcome`, stop mining on a tip that was replaced, gossip and send
+    /// the requests of `outcome`
+    fn carry_out(&self, outcome: Outcome) {
+        for event in outcome.events {
+            self.log(event);
+        }
+        if outcome.tip_changed {
+            self.mining.lock().unwrap().cancel();
+        }
+        for message in outcome.broadcast {
+            self.broadcast(message);
+        }
+        self.exchange(outcome.requests);
+    }
+
+    /// Send each request and handle the reply, until no more requests follow
+    fn exchange(&self, mut requests: Vec<(SocketAddr, Message)>) {
+        while let Some((peer, message)) = requests.pop() {
+            let Ok(Some(reply)) = request(peer, self.address, &message) else {
+                self.sync.lock().unwrap().cancel(peer);
+                continue;
+            };
+            let mut outcome = {
+                let mut chain = self.chain.lock().unwrap();
+                protocol::handle_message(
+                    &mut chain,
+                    &mut self.mempool.lock().unwrap(),
+                    &mut self.sync.lock().unwrap(),
+                    peer,
+                    reply,
+                )
+            };
+            requests.append(&mut outcome.requests);
+            self.carry_out(outcome);
+        }
+    }
+
+    fn handle_connection(&self, mut stream: TcpStream) -> io::Result<()> {
+        stream.set_read_timeout(Some(PEER_TIMEOUT))?;
+        let line = read_message(&stream)?;
+        let envelope: Envelope = serde_json::from_str(&line)?;
+        // The address a peer claims to listen on is only believed from its own host
+        let peer = stream.peer_addr()?;
+        let from = envelope
+            .from
+            .filter(|from| from.ip() == peer.ip())
+            .unwrap_or(peer);
+
+        // Blocks are only taken as the reply to our own request
+        if matches!(envelope.message, Message::Blocks(_)) {
+            return Ok(());
+        }
+        if let Some(reply) = self.handle(from, envelope.message) {
+            write_message(&mut stream, None, &reply)?;
+        }
+        Ok(())
+    }
+
+    /// Send `message` to every peer in the background
+    fn broadcast(&self, message: Message) {
+        let from = self.address;
+        for peer in self.peers() {
+            let message = message.clone();
+            thread::spawn(move || {
+                let _ = send_from(peer, Some(from), &message);
+            });
+        }
+    }
+}
+
+/// A message as sent over TCP, with the address the sending node listens on. Without it, a
+/// block with an unknown parent could not be followed by a sync with the node that sent it.
+#[derive(Debug, Serialize, Deserialize)]
+struct Envelope {
+    from: Option<SocketAddr>,
+    message: Message,
+}
+
+/// Frees a connection slot when the connection is done
+pub(crate) struct ConnectionSlot<'a>(pub(crate) &'a AtomicUsize);
+
+impl Drop for ConnectionSlot<'_> {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+fn write_message(
+    stream: &mut TcpStream,
+    from: Option<SocketAddr>,
+    message: &Message,
+) -> io::Result<()> {
+    let mut line = serde_json::to_vec(&Envelope {
+        from,
+        message: message.clone(),
+    })?;
+    line.push(b'\n');
+    stream.write_all(&line)?;
+    stream.flush()
+}
+
+/// Read one message line from `stream`, refusing one longer than `MAX_MESSAGE_LEN`
+fn read_message(stream: &TcpStream) -> io::Result<String> {
+    read_line_within(stream, MAX_MESSAGE_LEN)
+}
+
+/// Read a line of at most `max_len` bytes, newline included, without reading past it
+fn read_line_within(reader: impl Read, max_len: u64) -> io::Result<String> {
+    let mut line = String::new();
+    BufReader::new(reader.take(max_len)).read_line(&mut line)?;
+    if line.len() as u64 == max_len && !line.ends_with('\n') {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("Message longer than {} bytes", max_len),
+        ));
+    }
+    Ok(line)
+}
+
+/// Send a message without waiting for a reply
+pub fn send(peer: SocketAddr, message: &Message) -> io::Result<()> {
+    send_from(peer, None, message)
+}
+
+/// Send a message from the node listening on `from`, without waiting for a reply
+fn send_from(peer: SocketAddr, from: Option<SocketAddr>, message: &Message) -> io::Result<()> {
+    let mut stream = TcpStream::connect_timeout(&peer, PEER_TIMEOUT)?;
+    write_message(&mut stream, from, message)
+}
+
+/// Send a message from the node listening on `from` and wait for the peer's reply, if it
+/// sends one
+fn request(peer: SocketAddr, from: SocketAddr, message: &Message) -> io::Result<Option<Message>> {
+    let mut stream = TcpStream::connect_timeout(&peer, PEER_TIMEOUT)?;
+    stream.set_read_timeout(Some(PEER_TIMEOUT))?;
+    write_message(&mut stream, Some(from), message)?;
+    stream.shutdown(Shutdown::Write)?;
+
+    let line = read_message(&stream)?;
+    if line.trim().is_empty() {
+        return Ok(None);
+    }
+    let envelope: Envelope = serde_json::from_str(&line)?;
+    Ok(Some(envelope.message))
+}
+
+fn validate(chain: &BlockChain) -> Validation {
+    Validation {
+        height: chain.blocks.last().map_or(0, |block| block.id),
+        result: chain.is_chain_valid(&chain.blocks),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn message_lines_are_read_up_to_the_limit() {
+        assert_eq!(read_line_within(&b"{}\nnext"[..], 3).unwrap(), "{}\n");
+        assert_eq!(read_line_within(&b"{}"[..], 3).unwrap(), "{}");
+        let error = read_line_within(&b"{\"a\":1}\n"[..], 3).unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+    }
+}
@@ -0,0 +1,374 @@
+use std::collections::{HashMap, HashSet};
+use std::net::SocketAddr;
+
+use serde::{Deserialize, Serialize};
+
+use crate::block::Block;
+use crate::blockchain::{BlockChain, BlockStatus};
+use crate::error::{BlockError, MempoolError};
+use crate::hash::Hash;
+use crate::mempool::Mempool;
+use crate::metrics::Event;
+use crate::transaction::Transaction;
+
+/// Most bytes of encoded blocks sent in one `Blocks` reply. A peer further behind asks again
+/// from the last block it got.
+pub const MAX_REPLY_BYTES: usize = 4 * 1024 * 1024;
+
+/// Seconds before a block with an unknown parent from the same peer starts another sync with it
+pub const SYNC_COOLDOWN: i64 = 5;
+
+/// Messages exchanged between nodes
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum Message {
+    /// Gossip: a block that was just mined or accepted
+    NewBlock(Block),
+    /// Gossip: a transaction waiting to be mined
+    NewTransaction(Transaction),
+    /// Ask for the blocks with `id >= from_id`, answered with `Blocks`
+    GetBlocks { from_id: u64 },
+    /// The best chain's blocks from the id asked for, as many as fit in `MAX_REPLY_BYTES`.
+    /// Empty if there are none.
+    Blocks(Vec<Block>),
+}
+
+/// The syncs a node has going with its peers
+#[derive(Debug, Default)]
+pub struct SyncState {
+    /// When each peer was last asked for blocks because of an orphan it sent
+    orphan_syncs: HashMap<SocketAddr, i64>,
+    /// Peers asked for blocks that have not answered yet. `Blocks` from anyone else is ignored.
+    awaiting: HashSet<SocketAddr>,
+}
+
+impl SyncState {
+    /// The request to `peer` got no reply
+    pub fn cancel(&mut self, peer: SocketAddr) {
+        self.awaiting.remove(&peer);
+    }
+}
+
+/// What handling a message leaves the node to do, through whatever carries its messages
+#[derive(Debug, Default)]
+pub struct Outcome {
+    /// Answer to the peer the message came from
+    pub reply: Option<Message>,
+    /// Requests to send to one peer each, whose replies are handled as messages from that peer
+    pub requests: Vec<(SocketAddr, Message)>,
+    /// Messages for every peer
+    pub broadcast: Vec<Message>,
+    pub events: Vec<Event>,
+    /// The best chain has a new tip, a block mined on the old one is wasted
+    pub tip_changed: bool,
+}
+
+/// Handle `message` from the node at `from`, updating the node's chain, mempool and syncs.
+///
+/// This is all a node does with its peers' messages: `Node` carries them over TCP and the
+/// `Simulation` through its queue. Blocks are only ever added through `try_add_block`, which
+/// validates each block once against the branch it extends, so forks are followed through the
+/// block tree. Sync asks one peer for the blocks after our tip; if they do not connect, the peer
+/// is on another fork and is asked again from further back, never from before our oldest block.
+pub fn handle_message(
+    chain: &mut BlockChain,
+    mempool: &mut Mempool,
+    sync: &mut SyncState,
+    from: SocketAddr,
+    message: Message,
+) -> Outcome {
+    let mut outcome = Outcome::default();
+    match message {
+        Message::NewBlock(block) => {
+            receive_block(chain, mempool, sync, block, Some(from), &mut outcome);
+        }
+        Message::NewTransaction(transaction) => {
+            // Already known or invalid transactions are not gossiped further
+            if add_transaction(chain, mempool, transaction.clone()).is_ok() {
+                outcome.broadcast.push(Message::NewTransaction(transaction));
+            }
+        }
+        Message::GetBlocks { from_id } => {
+            outcome.reply = Some(Message::Blocks(blocks_from(chain, from_id)));
+        }
+        Message::Blocks(blocks) => {
+            if sync.awaiting.remove(&from) {
+                receive_blocks(chain, mempool, sync, from, blocks, &mut outcome);
+            }
+        }
+    }
+    outcome
+}
+
+/// Add a block mined by this node (`from` is `None`) or gossiped by the peer at `from`. A block
+/// that joins the block tree is gossiped on. One whose parent is unknown starts a sync with the
+/// peer that sent it, unless that peer was asked less than `SYNC_COOLDOWN` seconds ago.
+pub fn receive_block(
+    chain: &mut BlockChain,
+    mempool: &mut Mempool,
+    sync: &mut SyncState,
+    block: Block,
+    from: Option<SocketAddr>,
+    outcome: &mut Outcome,
+) {
+    let Ok(status) = add_block(chain, mempool, block.clone(), from, outcome) else {
+        return;
+    };
+    if status.is_connected() {
+        outcome.broadcast.push(Message::NewBlock(block));
+    } else if let (BlockStatus::Orphan, Some(peer)) = (status, from) {
+        // We are missing the blocks between our chain and this one
+        let now = chain.clock().now();
+        let due = sync
+            .orphan_syncs
+            .get(&peer)
+            .is_none_or(|last| now - last >= SYNC_COOLDOWN);
+        if due {
+            sync.orphan_syncs.insert(peer, now);
+            outcome.requests.push((peer, start_sync(chain, sync, peer)));
+        }
+    }
+}
+
+/// The request that asks `peer` for the blocks after our tip, or for its whole chain if we do
+/// not have a genesis block yet
+pub fn start_sync(chain: &BlockChain, sync: &mut SyncState, peer: SocketAddr) -> Message {
+    sync.awaiting.insert(peer);
+    Message::GetBlocks {
+        from_id: chain.blocks.last().map_or(1, |tip| tip.id + 1),
+    }
+}
+
+/// Add a transaction to the mempool, checked against the chain's tip
+pub fn add_transaction(
+    chain: &BlockChain,
+    mempool: &mut Mempool,
+    transaction: Transaction,
+) -> Result<Hash, MempoolError> {
+    mempool.set_next_block(chain.next_block_context());
+    mempool.add(transaction, chain.ledger())
+}
+
+/// Blocks of the best chain from `from_id` on, up to `MAX_REPLY_BYTES` but at least one
+fn blocks_from(chain: &BlockChain, from_id: u64) -> Vec<Block> {
+    let mut bytes = 0;
+    chain
+        .blocks
+        .iter()
+        .filter(|block| block.id >= from_id)
+        .take_while(|block| {
+            let first = bytes == 0;
+            bytes += serde_json::to_vec(block).map_or(MAX_REPLY_BYTES, |json| json.len());
+            first || bytes <= MAX_REPLY_BYTES
+        })
+        .cloned()
+        .collect()
+}
+
+/// Handle the blocks `from` sent in reply to `GetBlocks`
+fn receive_blocks(
+    chain: &mut BlockChain,
+    mempool: &mut Mempool,
+    sync: &mut SyncState,
+    from: SocketAddr,
+    blocks: Vec<Block>,
+    outcome: &mut Outcome,
+) {
+    let Some(first) = blocks.first() else {
+        return;
+    };
+
+    if chain.blocks.is_empty() {
+        // Waiting for a genesis block: take the peer's
+        let genesis = first.clone();
+        if genesis.id != 1 {
+            return;
+        }
+        if let Err(e) = chain
+            .is_genesis_valid(&genesis)
+            .and_then(|()| chain.replace_chain(vec![genesis]))
+        {
+            outcome.events.push(sync_failed(&e));
+            return;
+        }
+        outcome.tip_changed = true;
+    }
+
+    let connects =
+        chain.tree().contains(&first.hash) || chain.tree().contains(&first.previous_hash);
+    if !connects {
+        // The peer is on another fork: ask from twice as far back, down to our oldest block
+        let (oldest, tip) = match (chain.blocks.first(), chain.blocks.last()) {
+            (Some(oldest), Some(tip)) => (oldest.id, tip.id),
+            _ => return,
+        };
+        if first.id <= oldest + 1 {
+            outcome.events.push(Event::SyncFailed {
+                error: format!("The peer's chain forks before block {}.", oldest),
+            });
+            return;
+        }
+        let behind = (tip + 1).saturating_sub(first.id).max(1);
+        let from_id = first.id.saturating_sub(behind).max(oldest + 1);
+        sync.awaiting.insert(from);
+        outcome
+            .requests
+            .push((from, Message::GetBlocks { from_id }));
+        return;
+    }
+
+    let old_tip = chain.blocks.last().map(|block| block.hash);
+    let next_id = blocks.last().map_or(1, |block| block.id + 1);
+    for block in blocks {
+        if let Err(e) = add_block(chain, mempool, block, Some(from), outcome) {
+            outcome.events.push(sync_failed(&e));
+            return;
+        }
+    }
+    if let Some(tip) = chain.blocks.last().filter(|tip| Some(tip.hash) != old_tip) {
+        outcome.events.push(Event::ChainSwitched {
+            height: tip.id,
+            tip: hex::encode(tip.hash),
+        });
+    }
+    // The reply may have been cut short, ask for what follows until the peer has nothing more
+    sync.awaiting.insert(from);
+    outcome
+        .requests
+        .push((from, Message::GetBlocks { from_id: next_id }));
+}
+
+/// Add `block` to the chain and bring the mempool up to date with the new tip, if any
+fn add_block(
+    chain: &mut BlockChain,
+    mempool: &mut Mempool,
+    block: Block,
+    from: Option<SocketAddr>,
+    outcome: &mut Outcome,
+) -> Result<BlockStatus, BlockError> {
+    let old_tip = chain.blocks.last().map(|b| b.hash);
+    let added = chain.try_add_block_from(block.clone(), from.map(|peer| peer.ip()));
+    // What the chain did on its own, e.g. a prune that failed while adding the block
+    outcome.events.extend(chain.take_events());
+    let status = match added {
+        Ok(status) => status,
+        Err(e) => {
+            outcome.events.push(Event::BlockRejected {
+                id: block.id,
+                hash: hex::encode(block.hash),
+                reason: e.reason(),
+                error: e.to_string(),
+            });
+            return Err(e);
+        }
+    };
+    // Gossip and sync bring known blocks back again, those are not worth an event
+    if status != BlockStatus::Duplicate {
+        outcome.events.push(Event::BlockAdded {
+            id: block.id,
+            hash: hex::encode(block.hash),
+            status: status.name(),
+        });
+    }
+    if matches!(status, BlockStatus::Added | BlockStatus::Reorganized) {
+        outcome.tip_changed = true;
+        // Transactions of blocks that were dropped are pending again, mined ones are not
+        mempool.set_next_block(chain.next_block_context());
+        if let Some(old_tip) = old_tip {
+            mempool.return_transactions(&chain.blocks_off_chain(&old_tip), chain.ledger());
+        }
+        mempool.prune(chain.ledger());
+    }
+    Ok(status)
+}
+
+fn sync_failed(error: &BlockError) -> Event {
+    Event::SyncFailed {
+        error: error.to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::ChainConfig;
+    use crate::transaction::{address, generate_keypair};
+
+    fn chain_with(blocks: &[Block]) -> BlockChain {
+        let mut chain = BlockChain::with_config(ChainConfig {
+            initial_difficulty: 1,
+            ..ChainConfig::default()
+        });
+        chain.replace_chain(blocks.to_vec()).unwrap();
+        chain
+    }
+
+    fn mine(chain: &mut BlockChain, count: usize) {
+        let miner = address(&generate_keypair().verifying_key());
+        for _ in 0..count {
+            let block = chain.mine_next_block(&miner, vec![]).unwrap();
+            chain.try_add_block(block).unwrap();
+        }
+    }
+
+    /// Deliver `message` from `from` to `to`, then the requests it leads to and their replies,
+    /// until no more follow. Returns the number of requests sent.
+    fn exchange(to: &mut BlockChain, from: &BlockChain, message: Message) -> usize {
+        let peer = "10.0.0.1:8000".parse().unwrap();
+        let (mut mempool, mut sync) = (Mempool::default(), SyncState::default());
+        let mut outcome = handle_message(to, &mut mempool, &mut sync, peer, message);
+        let mut sent = 0;
+        while let Some((to_peer, request)) = outcome.requests.pop() {
+            assert_eq!(to_peer, peer);
+            sent += 1;
+            let Message::GetBlocks { from_id } = request else {
+                panic!("Only blocks are asked for");
+            };
+            let reply = Message::Blocks(blocks_from(from, from_id));
+            let mut next = handle_message(to, &mut mempool, &mut sync, peer, reply);
+            outcome.requests.append(&mut next.requests);
+        }
+        sent
+    }
+
+    #[test]
+    fn an_orphan_syncs_with_the_peer_that_sent_it_once() {
+        let mut source = chain_with(&[]);
+        source.generate_genesis_block().unwrap();
+        mine(&mut source, 3);
+        let mut chain = chain_with(&source.blocks[..1]);
+
+        let peer = "10.0.0.1:8000".parse().unwrap();
+        let (mut mempool, mut sync) = (Mempool::default(), SyncState::default());
+        let orphan = Message::NewBlock(source.blocks[3].clone());
+        let outcome = handle_message(&mut chain, &mut mempool, &mut sync, peer, orphan.clone());
+        assert_eq!(
+            outcome.requests,
+            vec![(peer, Message::GetBlocks { from_id: 2 })]
+        );
+        // Another orphan right away does not start a second sync
+        let outcome = handle_message(&mut chain, &mut mempool, &mut sync, peer, orphan);
+        assert!(outcome.requests.is_empty());
+
+        // Blocks nobody asked for are ignored
+        let other = "10.0.0.2:8000".parse().unwrap();
+        let blocks = Message::Blocks(source.blocks[1..].to_vec());
+        handle_message(&mut chain, &mut mempool, &mut sync, other, blocks);
+        assert_eq!(chain.blocks.len(), 1);
+    }
+
+    #[test]
+    fn a_fork_is_followed_from_where_it_branches_off() {
+        let mut source = chain_with(&[]);
+        source.generate_genesis_block().unwrap();
+        mine(&mut source, 4);
+        let mut chain = chain_with(&source.blocks[..3]);
+        mine(&mut chain, 1);
+        mine(&mut source, 2);
+
+        // The blocks after our tip do not connect, so the peer is asked from further back
+        let orphan = Message::NewBlock(source.blocks.last().unwrap().clone());
+        let requests = exchange(&mut chain, &source, orphan);
+        assert_eq!(chain.blocks, source.blocks);
+        assert_eq!(requests, 3);
+    }
+}
@@ -0,0 +1,595 @@
+//----------------------------------------------------------------
+//         Transaction scripts
+//----------------------------------------------------------------
+
+use std::fmt;
+
+use ed25519_dalek::{Signature, Signer, SigningKey, Verifier, VerifyingKey};
+
+use crate::error::ScriptError;
+use crate::hash::{sha3, Hash};
+use crate::transaction::Transaction;
+
+/// Addresses of locking scripts start with this, so they cannot clash with hex key addresses
+pub const SCRIPT_ADDRESS_PREFIX: &str = "script-";
+/// Most items the stack holds at once
+pub const MAX_STACK: usize = 100;
+/// Most steps a script runs: one per operation, plus one per signature checked
+pub const MAX_STEPS: usize = 200;
+/// Most keys a CHECKMULTISIG checks
+pub const MAX_KEYS: usize = 16;
+
+/*
+ * Scripts lock funds sent to a script address, in the spirit of Bitcoin's pay-to-script-hash.
+ *
+ * 1. The locking script is hashed into an address, `script-<hex SHA3-256 of its text>`.
+ *    Funds are sent to it like to any other address.
+ *
+ * 2. A transaction spending from the address carries a witness in its `signature` field: an
+ *    unlocking script made of literals only, whose last literal is the text of the locking script.
+ *
+ * 3. The unlocking script's literals are pushed on a stack, then the locking script runs on
+ *    that stack like a postfix expression: literals are pushed, operations pop their operands
+ *    and push their result. The spend is valid if the top of the stack is then true.
+ *
+ * Operations:
+ *   DUP, DROP, SWAP
+ *   EQUAL, VERIFY, EQUALVERIFY       (a false VERIFY fails the script)
+ *   SHA3                             (bytes -> their SHA3-256)
+ *   CHECKSIG, CHECKSIGVERIFY         (signature key -> whether the key signed the transaction)
+ *   CHECKMULTISIG                    (sig1..sigM M key1..keyN N, signatures in key order)
+ *   CHECKHEIGHTVERIFY                (height -> fails below that block height)
+ *   CHECKTIMEVERIFY                  (time -> fails before that median time past)
+ *
+ * Literals are decimal numbers or `0x` hex bytes. There are no loops, and the interpreter
+ * stops after `MAX_STEPS`, so a script always ends and gives the same result on every node.
+ */
+
+/// An item on the stack
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Value {
+    Number(i64),
+    Bytes(Vec<u8>),
+}
+
+impl Value {
+    /// Zero and empty bytes are false, everything else is true
+    fn is_true(&self) -> bool {
+        match self {
+            Value::Number(number) => *number != 0,
+            Value::Bytes(bytes) => !bytes.is_empty(),
+        }
+    }
+}
+
+impl fmt::Display for Value {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Value::Number(number) => write!(f, "{}", number),
+            Value::Bytes(bytes) => write!(f, "0x{}", hex::encode(bytes)),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Operation {
+    Dup,
+    Drop,
+    Swap,
+    Equal,
+    Verify,
+    EqualVerify,
+    Sha3,
+    CheckSig,
+    CheckSigVerify,
+    CheckMultiSig,
+    CheckHeightVerify,
+    CheckTimeVerify,
+}
+
+const OPERATIONS: [(Operation, &str); 12] = [
+    (Operation::Dup, "DUP"),
+    (Operation::Drop, "DROP"),
+    (Operation::Swap, "SWAP"),
+    (Operation::Equal, "EQUAL"),
+    (Operation::Verify, "VERIFY"),
+    (Operation::EqualVerify, "EQUALVERIFY"),
+    (Operation::Sha3, "SHA3"),
+    (Operation::CheckSig, "CHECKSIG"),
+    (Operation::CheckSigVerify, "CHECKSIGVERIFY"),
+    (Operation::CheckMultiSig, "CHECKMULTISIG"),
+    (Operation::CheckHeightVerify, "CHECKHEIGHTVERIFY"),
+    (Operation::CheckTimeVerify, "CHECKTIMEVERIFY"),
+];
+
+impl Operation {
+    fn name(self) -> &'static str {
+        OPERATIONS
+            .iter()
+            .find(|(operation, _)| *operation == self)
+            .map(|(_, name)| *name)
+            .unwrap()
+    }
+}
+
+/// A literal to push, or an operation
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Symbol {
+    Push(Value),
+    Op(Operation),
+}
+
+impl fmt::Display for Symbol {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Symbol::Push(value) => value.fmt(f),
+            Symbol::Op(operation) => write!(f, "{}", operation.name()),
+        }
+    }
+}
+
+/// A script, written as its symbols separated by spaces
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Script(pub Vec<Symbol>);
+
+impl Script {
+    pub fn parse(text: &str) -> Result<Self, ScriptError> {
+        text.split_whitespace()
+            .map(parse_symbol)
+            .collect::<Result<Vec<Symbol>, ScriptError>>()
+            .map(Script)
+    }
+
+    /// Address that funds locked by this script are sent to
+    pub fn address(&self) -> String {
+        format!(
+            "{}{}",
+            SCRIPT_ADDRESS_PREFIX,
+            hex::encode(sha3(self.to_string()))
+        )
+    }
+
+    /// Spendable with signatures of `required` of `keys`, given in the order of `keys`
+    pub fn multisig(required: usize, keys: &[VerifyingKey]) -> Self {
+        let mut symbols = vec![Symbol::Push(Value::Number(required as i64))];
+        symbols.extend(keys.iter().map(push_key));
+        symbols.push(Symbol::Push(Value::Number(keys.len() as i64)));
+        symbols.push(Symbol::Op(Operation::CheckMultiSig));
+        Script(symbols)
+    }
+
+    /// Spendable by `key` with the preimage of `hash`, unlocked with the signature and then
+    /// the preimage
+    pub fn hash_lock(hash: &Hash, key: &VerifyingKey) -> Self {
+        Script(vec![
+            Symbol::Op(Operation::Sha3),
+            Symbol::Push(Value::Bytes(hash.to_vec())),
+            Symbol::Op(Operation::EqualVerify),
+            push_key(key),
+            Symbol::Op(Operation::CheckSig),
+        ])
+    }
+
+    /// Spendable by `key` from block `height` on
+    pub fn height_lock(height: u64, key: &VerifyingKey) -> Self {
+        Script(vec![
+            Symbol::Push(Value::Number(height as i64)),
+            Symbol::Op(Operation::CheckHeightVerify),
+            push_key(key),
+            Symbol::Op(Operation::CheckSig),
+        ])
+    }
+
+    fn is_push_only(&self) -> bool {
+        self.0
+            .iter()
+            .all(|symbol| matches!(symbol, Symbol::Push(_)))
+    }
+}
+
+impl fmt::Display for Script {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for (i, symbol) in self.0.iter().enumerate() {
+            if i > 0 {
+                write!(f, " ")?;
+            }
+            symbol.fmt(f)?;
+        }
+        Ok(())
+    }
+}
+
+fn push_key(key: &VerifyingKey) -> Symbol {
+    Symbol::Push(Value::Bytes(key.as_bytes().to_vec()))
+}
+
+fn parse_symbol(token: &str) -> Result<Symbol, ScriptError> {
+    if let Some(digits) = token.strip_prefix("0x") {
+        return hex::decode(digits)
+            .map(|bytes| Symbol::Push(Value::Bytes(bytes)))
+            .map_err(|_| ScriptError::Parse(format!("{} is not hex", token)));
+    }
+    if let Ok(number) = token.parse::<i64>() {
+        return Ok(Symbol::Push(Value::Number(number)));
+    }
+    OPERATIONS
+        .iter()
+        .find(|(_, name)| *name == token)
+        .map(|(operation, _)| Symbol::Op(*operation))
+        .ok_or_else(|| ScriptError::Parse(format!("unknown operation {}", token)))
+}
+
+/// Block the spending transaction is in, which timelocks are checked against. `time` is the
+/// median time past of the blocks before it, not the timestamp its miner chose.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Context {
+    pub height: u64,
+    pub time: i64,
+}
+
+/// Signature of `key` over `transaction`, to push in an unlocking script
+pub fn sign(key: &SigningKey, transaction: &Transaction) -> Value {
+    Value::Bytes(key.sign(&transaction.signing_bytes()).to_bytes().to_vec())
+}
+
+/// Witness of a spend from the address of `lock`: the `unlock` literals followed by the text
+/// of `lock`. It goes in the `signature` field of the transaction.
+pub fn witness(unlock: Vec<Value>, lock: &Script) -> String {
+    let mut symbols = unlock
+        .into_iter()
+        .map(Symbol::Push)
+        .collect::<Vec<Symbol>>();
+    symbols.push(Symbol::Push(Value::Bytes(lock.to_string().into_bytes())));
+    Script(symbols).to_string()
+}
+
+/// Check that `transaction`, spending from a script address, satisfies the script when
+/// included in the block described by `context`
+pub fn verify_spend(transaction: &Transaction, context: &Context) -> Result<(), ScriptError> {
+    let mut unlock = Script::parse(&transaction.signature)?;
+    if !unlock.is_push_only() {
+        return Err(ScriptError::BadWitness(
+            "the unlocking script may only push literals".to_string(),
+        ));
+    }
+    let Some(Symbol::Push(Value::Bytes(lock))) = unlock.0.pop() else {
+        return Err(ScriptError::BadWitness(
+            "the locking script is missing".to_string(),
+        ));
+    };
+    let lock = String::from_utf8(lock)
+        .map_err(|_| ScriptError::BadWitness("the locking script is not text".to_string()))
+        .and_then(|lock| Script::parse(&lock))?;
+    if lock.address() != transaction.sender {
+        return Err(ScriptError::WrongScript);
+    }
+
+    let message = transaction.signing_bytes();
+    let mut machine = Machine::new(&message, context);
+    machine.run(&unlock)?;
+    machine.run(&lock)?;
+    match machine.pop()? {
+        result if result.is_true() => Ok(()),
+        _ => Err(ScriptError::VerifyFailed {
+            op: "The script".to_string(),
+        }),
+    }
+}
+
+/// The stack machine running a script
+struct Machine<'a> {
+    stack: Vec<Value>,
+    steps: usize,
+    /// Bytes covered by the signatures the script checks
+    message: &'a [u8],
+    context: &'a Context,
+}
+
+impl<'a> Machine<'a> {
+    fn new(message: &'a [u8], context: &'a Context) -> Self {
+        Self {
+            stack: Vec::with_capacity(MAX_STACK),
+            steps: 0,
+            message,
+            context,
+        }
+    }
+
+    fn push(&mut self, value: Value) -> Result<(), ScriptError> {
+        if self.stack.len() == MAX_STACK {
+            return Err(ScriptError::StackOverflow);
+        }
+        self.stack.push(value);
+        Ok(())
+    }
+
+    fn pop(&mut self) -> Result<Value, ScriptError> {
+        self.stack.pop().ok_or(ScriptError::StackUnderflow)
+    }
+
+    fn pop_number(&mut self, operation: Operation) -> Result<i64, ScriptError> {
+        match self.pop()? {
+            Value::Number(number) => Ok(number),
+            Value::Bytes(_) => Err(mismatch(operation)),
+        }
+    }
+
+    fn pop_bytes(&mut self, operation: Operation) -> Result<Vec<u8>, ScriptError> {
+        match self.pop()? {
+            Value::Bytes(bytes) => Ok(bytes),
+            Value::Number(_) => Err(mismatch(operation)),
+        }
+    }
+
+    /// Pop a count for CHECKMULTISIG: a number between 0 and `max`
+    fn pop_count(&mut self, max: usize) -> Result<usize, ScriptError> {
+        let count = self.pop_number(Operation::CheckMultiSig)?;
+        usize::try_from(count)
+            .ok()
+            .filter(|count| *count <= max)
+            .ok_or(ScriptError::VerifyFailed {
+                op: Operation::CheckMultiSig.name().to_string(),
+            })
+    }
+
+    fn step(&mut self) -> Result<(), ScriptError> {
+        self.steps += 1;
+        if self.steps > MAX_STEPS {
+            return Err(ScriptError::StepLimit);
+        }
+        Ok(())
+    }
+
+    fn run(&mut self, script: &Script) -> Result<(), ScriptError> {
+        for symbol in &script.0 {
+            self.step()?;
+            match symbol {
+                Symbol::Push(value) => self.push(value.clone())?,
+                Symbol::Op(operation) => self.execute(*operation)?,
+            }
+        }
+        Ok(())
+    }
+
+    fn execute(&mut self, operation: Operation) -> Result<(), ScriptError> {
+        match operation {
+            Operation::Dup => {
+                let top = self
+                    .stack
+                    .last()
+                    .cloned()
+                    .ok_or(ScriptError::StackUnderflow)?;
+                self.push(top)?;
+            }
+            Operation::Drop => {
+                self.pop()?;
+            }
+            Operation::Swap => {
+                let top = self.pop()?;
+                let below = self.pop()?;
+                self.push(top)?;
+                self.push(below)?;
+            }
+            Operation::Equal => {
+                let operand2 = self.pop()?;
+                let operand1 = self.pop()?;
+                self.push_bool(operand1 == operand2)?;
+            }
+            Operation::Verify => self.verify(operation)?,
+            Operation::EqualVerify => {
+                self.execute(Operation::Equal)?;
+                self.verify(operation)?;
+            }
+            Operation::Sha3 => {
+                let bytes = self.pop_bytes(operation)?;
+                self.push(Value::Bytes(sha3(bytes).to_vec()))?;
+            }
+            Operation::CheckSig => {
+                let key = self.pop_bytes(operation)?;
+                let signature = self.pop_bytes(operation)?;
+                let valid = self.check_signature(&key, &signature);
+                self.push_bool(valid)?;
+            }
+            Operation::CheckSigVerify => {
+                self.execute(Operation::CheckSig)?;
+                self.verify(operation)?;
+            }
+            Operation::CheckMultiSig => {
+                let key_count = self.pop_count(MAX_KEYS)?;
+                let keys = (0..key_count)
+                    .map(|_| self.pop_bytes(operation))
+                    .collect::<Result<Vec<Vec<u8>>, ScriptError>>()?;
+                let required = self.pop_count(key_count)?;
+                let signatures = (0..required)
+                    .map(|_| self.pop_bytes(operation))
+                    .collect::<Result<Vec<Vec<u8>>, ScriptError>>()?;
+
+                // Both were pushed in order and popped in reverse. Each signature must match
+                // a key after the key of the signature before it.
+                let mut keys = keys.iter().rev();
+                let mut valid = true;
+                for signature in signatures.iter().rev() {
+                    let mut matched = false;
+                    for key in keys.by_ref() {
+                        self.step()?;
+                        if self.check_signature(key, signature) {
+                            matched = true;
+                            break;
+                        }
+                    }
+                    if !matched {
+                        valid = false;
+                        break;
+                    }
+                }
+                self.push_bool(valid)?;
+            }
+            Operation::CheckHeightVerify => {
+                let until = self.pop_number(operation)?;
+                let height = self.context.height;
+                if u64::try_from(until).is_ok_and(|until| height < until) {
+                    return Err(ScriptError::HeightLocked {
+                        height,
+                        until: until as u64,
+                    });
+                }
+            }
+            Operation::CheckTimeVerify => {
+                let until = self.pop_number(operation)?;
+                let time = self.context.time;
+                if time < until {
+                    return Err(ScriptError::TimeLocked { time, until });
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn push_bool(&mut self, value: bool) -> Result<(), ScriptError> {
+        self.push(Value::Number(value as i64))
+    }
+
+    fn verify(&mut self, operation: Operation) -> Result<(), ScriptError> {
+        if !self.pop()?.is_true() {
+            return Err(ScriptError::VerifyFailed {
+                op: operation.name().to_string(),
+            });
+        }
+        Ok(())
+    }
+
+    fn check_signature(&self, key: &[u8], signature: &[u8]) -> bool {
+        let Some(key) = <[u8; 32]>::try_from(key)
+            .ok()
+            .and_then(|bytes| VerifyingKey::from_bytes(&bytes).ok())
+        else {
+            return false;
+        };
+        Signature::from_slice(signature)
+            .is_ok_and(|signature| key.verify(self.message, &signature).is_ok())
+    }
+}
+
+fn mismatch(operation: Operation) -> ScriptError {
+    ScriptError::TypeMismatch {
+        op: operation.name().to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::transaction::generate_keypair;
+
+    const CONTEXT: Context = Context {
+        height: 10,
+        time: 1_700_000_000,
+    };
+
+    /// A transfer out of the address of `lock`, unlocked with what `unlock` returns
+    fn spend(lock: &Script, unlock: impl Fn(&Transaction) -> Vec<Value>) -> Transaction {
+        let mut transaction = Transaction::unsigned(lock.address(), "bob".to_string(), 5, 0, 0);
+        transaction.signature = witness(unlock(&transaction), lock);
+        transaction
+    }
+
+    #[test]
+    fn scripts_round_trip_through_their_text() {
+        let key = generate_keypair().verifying_key();
+        let script = Script::hash_lock(&sha3("secret"), &key);
+        assert_eq!(Script::parse(&script.to_string()), Ok(script.clone()));
+        assert!(script.address().starts_with(SCRIPT_ADDRESS_PREFIX));
+        assert_eq!(
+            Script::parse("1 NOPE"),
+            Err(ScriptError::Parse("unknown operation NOPE".to_string()))
+        );
+    }
+
+    #[test]
+    fn multisig_needs_enough_signatures_in_key_order() {
+        let keys = [generate_keypair(), generate_keypair(), generate_keypair()];
+        let public = keys
+            .iter()
+            .map(SigningKey::verifying_key)
+            .collect::<Vec<_>>();
+        let lock = Script::multisig(2, &public);
+
+        let two = spend(&lock, |t| vec![sign(&keys[0], t), sign(&keys[2], t)]);
+        assert_eq!(verify_spend(&two, &CONTEXT), Ok(()));
+
+        let out_of_order = spend(&lock, |t| vec![sign(&keys[2], t), sign(&keys[0], t)]);
+        let same_twice = spend(&lock, |t| vec![sign(&keys[1], t), sign(&keys[1], t)]);
+        for transaction in [out_of_order, same_twice] {
+            assert_eq!(
+                verify_spend(&transaction, &CONTEXT),
+                Err(ScriptError::VerifyFailed {
+                    op: "The script".to_string()
+                })
+            );
+        }
+
+        let one = spend(&lock, |t| vec![sign(&keys[0], t)]);
+        assert!(verify_spend(&one, &CONTEXT).is_err());
+    }
+
+    #[test]
+    fn hash_lock_needs_the_preimage() {
+        let key = generate_keypair();
+        let lock = Script::hash_lock(&sha3("secret"), &key.verifying_key());
+
+        let preimage = |bytes: &str| Value::Bytes(bytes.as_bytes().to_vec());
+        let unlocked = spend(&lock, |t| vec![sign(&key, t), preimage("secret")]);
+        assert_eq!(verify_spend(&unlocked, &CONTEXT), Ok(()));
+
+        let guessed = spend(&lock, |t| vec![sign(&key, t), preimage("guess")]);
+        assert_eq!(
+            verify_spend(&guessed, &CONTEXT),
+            Err(ScriptError::VerifyFailed {
+                op: "EQUALVERIFY".to_string()
+            })
+        );
+    }
+
+    #[test]
+    fn height_lock_opens_at_its_height() {
+        let key = generate_keypair();
+        let lock = Script::height_lock(11, &key.verifying_key());
+        let transaction = spend(&lock, |t| vec![sign(&key, t)]);
+
+        assert_eq!(
+            verify_spend(&transaction, &CONTEXT),
+            Err(ScriptError::HeightLocked {
+                height: 10,
+                until: 11
+            })
+        );
+        let next_block = Context {
+            height: 11,
+            ..CONTEXT
+        };
+        assert_eq!(verify_spend(&transaction, &next_block), Ok(()));
+    }
+
+    #[test]
+    fn witness_must_reveal_the_script_of_the_address() {
+        let key = generate_keypair();
+        let lock = Script::height_lock(1, &key.verifying_key());
+        let other = Script::height_lock(2, &key.verifying_key());
+        let mut transaction = spend(&lock, |t| vec![sign(&key, t)]);
+        transaction.signature = witness(vec![sign(&key, &transaction)], &other);
+        assert_eq!(
+            verify_spend(&transaction, &CONTEXT),
+            Err(ScriptError::WrongScript)
+        );
+    }
+
+    #[test]
+    fn long_scripts_hit_the_step_limit() {
+        let lock = Script::parse(&"1 DROP ".repeat(MAX_STEPS)).unwrap();
+        let transaction = spend(&lock, |_| vec![]);
+        assert_eq!(
+            verify_spend(&transaction, &CONTEXT),
+            Err(ScriptError::StepLimit)
+        );
+    }
+}
@@ -0,0 +1,401 @@
+use std::cmp::Ordering;
+use std::collections::BinaryHeap;
+use std::net::{Ipv4Addr, SocketAddr};
+use std::sync::Arc;
+
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+
+use crate::blockchain::BlockChain;
+use crate::clock::{Clock, ManualClock};
+use crate::config::ChainConfig;
+use crate::consensus::ProofOfWork;
+use crate::hash::{sha3_hex, Hash};
+use crate::mempool::Mempool;
+use crate::miner::Miner;
+use crate::protocol::{self, Message, Outcome, SyncState};
+
+/// Parameters of a simulated network
+#[derive(Debug, Clone)]
+pub struct SimulationConfig {
+    pub nodes: usize,
+    /// Seed of every random choice: which messages are dropped, how long they take and
+    /// which nodes find blocks. The same seed replays the same run.
+    pub seed: u64,
+    /// Virtual time the simulation starts at, in Unix seconds
+    pub start_time: i64,
+    /// Messages take between `min_delay` and `max_delay` seconds, inclusive
+    pub min_delay: i64,
+    pub max_delay: i64,
+    /// Chance that a message is lost
+    pub drop_rate: f64,
+    /// Chance that a node finds a block in any one second of `run`
+    pub block_rate: f64,
+    /// Every this many seconds of `run`, each node asks its peers for their chain. 0 disables it.
+    pub sync_interval: i64,
+    pub chain: ChainConfig,
+}
+
+impl Default for SimulationConfig {
+    fn default() -> Self {
+        Self {
+            nodes: 3,
+            seed: 0,
+            start_time: 1_700_000_000,
+            min_delay: 0,
+            max_delay: 2,
+            drop_rate: 0.0,
+            block_rate: 0.05,
+            sync_interval: 30,
+            chain: ChainConfig {
+                initial_difficulty: 1,
+                ..ChainConfig::default()
+            },
+        }
+    }
+}
+
+/// A message on its way from one simulated node to another
+#[derive(Debug)]
+struct InFlight {
+    deliver_at: i64,
+    /// Order of sending, so messages due at the same time are delivered in a fixed order
+    sequence: u64,
+    from: usize,
+    to: usize,
+    message: Message,
+}
+
+impl PartialEq for InFlight {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl Eq for InFlight {}
+
+impl PartialOrd for InFlight {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for InFlight {
+    /// Reversed, so the max-heap pops the earliest message first
+    fn cmp(&self, other: &Self) -> Ordering {
+        (other.deliver_at, other.sequence).cmp(&(self.deliver_at, self.sequence))
+    }
+}
+
+/// Deterministic in-process simulation of a network of blockchain nodes.
+///
+/// Nodes exchange the same `Message`s as TCP nodes, but through a queue ordered by virtual
+/// delivery time. Time only moves in `run`, on a `ManualClock` shared by every chain, and
+/// every random choice comes from one seeded RNG, so a failing run can be replayed exactly.
+/// Messages are handled by `protocol::handle_message`, the same code `Node` runs, so the
+/// simulation exercises the node's own gossip and sync. Node `n` has the address
+/// `10.0.0.<n + 1>:8000`.
+#[derive(Debug)]
+pub struct Simulation {
+    config: SimulationConfig,
+    clock: ManualClock,
+    rng: StdRng,
+    chains: Vec<BlockChain>,
+    mempools: Vec<Mempool>,
+    syncs: Vec<SyncState>,
+    /// Nodes can only reach nodes of the same group
+    groups: Vec<usize>,
+    in_flight: BinaryHeap<InFlight>,
+    sent: u64,
+    dropped: u64,
+}
+
+impl Simulation {
+    /// Start `config.nodes` connected nodes that share a genesis block
+    pub fn new(config: SimulationConfig) -> Self {
+        let clock = ManualClock::new(config.start_time);
+        let mut chains = (0..config.nodes)
+            .map(|_| {
+                let mut chain = BlockChain::with_config(config.chain.clone());
+                chain.set_clock(Arc::new(clock.clone()));
+                // One mining thread, so the nonce found is always the same
+                chain.set_consensus(Arc::new(ProofOfWork::new(Miner::new(1))));
+                chain
+            })
+            .collect::<Vec<BlockChain>>();
+
+        if let Some((first, others)) = chains.split_first_mut() {
+            first
+                .generate_genesis_block()
+                .expect("Proof-of-work can always seal the genesis block");
+            for chain in others {
+                chain
+                    .replace_chain(first.blocks.clone())
+                    .expect("The genesis block is valid");
+            }
+        }
+
+        Self {
+            rng: StdRng::seed_from_u64(config.seed),
+            mempools: (0..config.nodes).map(|_| Mempool::default()).collect(),
+            syncs: (0..config.nodes).map(|_| SyncState::default()).collect(),
+            groups: vec![0; config.nodes],
+            config,
+            clock,
+            chains,
+            in_flight: BinaryHeap::new(),
+            sent: 0,
+            dropped: 0,
+        }
+    }
+
+    /// Current virtual time
+    pub fn now(&self) -> i64 {
+        self.clock.now()
+    }
+
+    pub fn chain(&self, node: usize) -> &BlockChain {
+        &self.chains[node]
+    }
+
+    /// Hash of every node's tip, by node
+    pub fn tips(&self) -> Vec<Hash> {
+        self.chains
+            .iter()
+            .map(|chain| {
+                chain
+                    .blocks
+                    .last()
+                    .expect("Every node has a genesis block")
+                    .hash
+            })
+            .collect()
+    }
+
+    /// Do all nodes have the same tip?
+    pub fn converged(&self) -> bool {
+        self.tips().windows(2).all(|pair| pair[0] == pair[1])
+    }
+
+    /// Number of messages sent and number of those lost, by drops or partitions
+    pub fn message_counts(&self) -> (u64, u64) {
+        (self.sent, self.dropped)
+    }
+
+    /// Split the network: nodes only reach the nodes in the same group. A node in no group
+    /// is cut off from every other node. Messages already in flight across groups are lost.
+    pub fn partition(&mut self, groups: &[&[usize]]) {
+        let isolated = groups.len();
+        self.groups = (0..self.chains.len()).map(|node| isolated + node).collect();
+        for (group, nodes) in groups.iter().enumerate() {
+            for &node in *nodes {
+                self.groups[node] = group;
+            }
+        }
+    }
+
+    /// Reconnect every node, and have every node sync from its peers
+    pub fn heal(&mut self) {
+        self.groups = vec![0; self.chains.len()];
+        self.sync_all();
+    }
+
+    /// Have `node` seal a block on its tip now and gossip it. Returns the block's hash.
+    pub fn mine(&mut self, node: usize) -> Hash {
+        // Any lowercase hex of 32 bytes is a valid address, and stays the same between runs
+        let miner = sha3_hex(format!("node-{}", node));
+        let block = self.chains[node]
+            .mine_next_block(&miner, vec![])
+            .expect("Proof-of-work always seals a block");
+        let hash = block.hash;
+        let mut outcome = Outcome::default();
+        protocol::receive_block(
+            &mut self.chains[node],
+            &mut self.mempools[node],
+            &mut self.syncs[node],
+            block,
+            None,
+            &mut outcome,
+        );
+        assert!(outcome.tip_changed, "A block sealed on the tip is valid");
+        self.carry_out(node, None, outcome);
+        hash
+    }
+
+    /// Advance the virtual clock by `seconds`, one second at a time. Every second, the
+    /// messages due are delivered, each node finds a block with chance `block_rate`, and
+    /// every `sync_interval` seconds each node syncs.
+    pub fn run(&mut self, seconds: i64) {
+        for _ in 0..seconds {
+            self.clock.advance(1);
+            self.deliver_due();
+            for node in 0..self.chains.len() {
+                if self.rng.gen_bool(self.config.block_rate) {
+                    self.mine(node);
+                }
+            }
+            let interval = self.config.sync_interval;
+            if interval > 0 && (self.now() - self.config.start_time) % interval == 0 {
+                self.sync_all();
+            }
+        }
+    }
+
+    /// Run until every node has the same tip and no message is in flight, for at most
+    /// `max_seconds`. Returns whether the nodes converged.
+    pub fn run_until_converged(&mut self, max_seconds: i64) -> bool {
+        for _ in 0..max_seconds {
+            if self.converged() && self.in_flight.is_empty() {
+                return true;
+            }
+            self.run(1);
+        }
+        self.converged()
+    }
+
+    fn sync_all(&mut self) {
+        for node in 0..self.chains.len() {
+            for peer in (0..self.chains.len()).filter(|&peer| peer != node) {
+                let request =
+                    protocol::start_sync(&self.chains[node], &mut self.syncs[node], address(peer));
+                self.send(node, peer, request);
+            }
+        }
+    }
+
+    fn broadcast(&mut self, from: usize, message: Message) {
+        for to in (0..self.chains.len()).filter(|&to| to != from) {
+            self.send(from, to, message.clone());
+        }
+    }
+
+    fn send(&mut self, from: usize, to: usize, message: Message) {
+        self.sent += 1;
+        if !self.reachable(from, to) || self.rng.gen_bool(self.config.drop_rate) {
+            self.dropped += 1;
+            return;
+        }
+        let delay = self
+            .rng
+            .gen_range(self.config.min_delay..=self.config.max_delay);
+        self.in_flight.push(InFlight {
+            deliver_at: self.now() + delay,
+            sequence: self.sent,
+            from,
+            to,
+            message,
+        });
+    }
+
+    fn reachable(&self, from: usize, to: usize) -> bool {
+        self.groups[from] == self.groups[to]
+    }
+
+    /// Deliver every message due by now, including replies due at once
+    fn deliver_due(&mut self) {
+        while self
+            .in_flight
+            .peek()
+            .is_some_and(|next| next.deliver_at <= self.now())
+        {
+            let InFlight {
+                from, to, message, ..
+            } = self.in_flight.pop().unwrap();
+            if !self.reachable(from, to) {
+                self.dropped += 1;
+                continue;
+            }
+            self.deliver(from, to, message);
+        }
+    }
+
+    fn deliver(&mut self, from: usize, to: usize, message: Message) {
+        let outcome = protocol::handle_message(
+            &mut self.chains[to],
+            &mut self.mempools[to],
+            &mut self.syncs[to],
+            address(from),
+            message,
+        );
+        self.carry_out(to, Some(from), outcome);
+    }
+
+    /// Send what handling a message at `node` led to: the reply to `from`, requests to single
+    /// peers and gossip to every peer
+    fn carry_out(&mut self, node: usize, from: Option<usize>, outcome: Outcome) {
+        if let (Some(reply), Some(from)) = (outcome.reply, from) {
+            self.send(node, from, reply);
+        }
+        for (peer, request) in outcome.requests {
+            if let Some(peer) = node_at(peer) {
+                self.send(node, peer, request);
+            }
+        }
+        for message in outcome.broadcast {
+            self.broadcast(node, message);
+        }
+    }
+}
+
+/// Address of simulated node `node`
+fn address(node: usize) -> SocketAddr {
+    let host = u32::from(Ipv4Addr::new(10, 0, 0, 1)) + node as u32;
+    SocketAddr::from((Ipv4Addr::from(host), 8000))
+}
+
+/// Simulated node with `address`
+fn node_at(address: SocketAddr) -> Option<usize> {
+    let SocketAddr::V4(address) = address else {
+        return None;
+    };
+    let first = u32::from(Ipv4Addr::new(10, 0, 0, 1));
+    u32::from(*address.ip())
+        .checked_sub(first)
+        .map(|node| node as usize)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn same_seed_replays_the_same_run() {
+        let config = SimulationConfig {
+            seed: 7,
+            drop_rate: 0.2,
+            ..SimulationConfig::default()
+        };
+        let mut first = Simulation::new(config.clone());
+        let mut second = Simulation::new(config);
+        first.run(120);
+        second.run(120);
+        assert_eq!(first.tips(), second.tips());
+        assert_eq!(first.message_counts(), second.message_counts());
+    }
+
+    #[test]
+    fn nodes_converge_after_a_partition_heals() {
+        let mut simulation = Simulation::new(SimulationConfig {
+            nodes: 4,
+            seed: 42,
+            drop_rate: 0.1,
+            block_rate: 0.0,
+            ..SimulationConfig::default()
+        });
+
+        // Both sides of the partition build their own chain, the larger side a longer one
+        simulation.partition(&[&[0, 1, 2], &[3]]);
+        for _ in 0..3 {
+            simulation.mine(0);
+            simulation.run(5);
+        }
+        simulation.mine(3);
+        simulation.run(5);
+        assert!(!simulation.converged());
+
+        simulation.heal();
+        assert!(simulation.run_until_converged(120));
+        assert_eq!(simulation.chain(3).blocks.len(), 4);
+        assert_eq!(simulation.chain(3).blocks, simulation.chain(0).blocks);
+    }
+}
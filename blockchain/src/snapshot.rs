@@ -0,0 +1,111 @@
+use serde::{Deserialize, Serialize};
+
+use crate::block::{Block, BlockHeader};
+use crate::error::{BlockError, ChainError};
+use crate::hash::{self, Hash};
+use crate::ledger::Ledger;
+
+/// State of a chain at a checkpoint block, enough to carry on validating the blocks after it
+/// without the blocks before it.
+///
+/// `recent_blocks` ends with the checkpoint block and holds as many blocks before it as
+/// difficulty retargeting and the median time past look back at.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Snapshot {
+    /// Id of the checkpoint block
+    pub height: u64,
+    /// Hash of the checkpoint block
+    #[serde(with = "hash::hex_hash")]
+    pub hash: Hash,
+    /// Balances and nonces after the checkpoint block
+    pub ledger: Ledger,
+    /// Cumulative work of the blocks before `recent_blocks`
+    pub work_before: u128,
+    pub recent_blocks: Vec<Block>,
+}
+
+impl Snapshot {
+    /// Check that `recent_blocks` link up to the checkpoint and that every one of them has the
+    /// hash of its header. The seals and the ledger are trusted, they come from a trusted source.
+    pub fn check(&self) -> Result<(), ChainError> {
+        let fail = |index, error| Err(ChainError { index, error });
+        let Some(last) = self.recent_blocks.last() else {
+            return fail(0, BlockError::NoGenesis);
+        };
+
+        for (index, block) in self.recent_blocks.iter().enumerate() {
+            if block.compute_hash() != block.hash {
+                return fail(index, BlockError::HashMismatch);
+            }
+            if let Some(previous) = index.checked_sub(1).map(|i| &self.recent_blocks[i]) {
+                if block.previous_hash != previous.hash {
+                    return fail(
+                        index,
+                        BlockError::BadPreviousHash {
+                            expected: previous.hash,
+                            found: block.previous_hash,
+                        },
+                    );
+                }
+                if block.id != previous.id + 1 {
+                    return fail(
+                        index,
+                        BlockError::BadId {
+                            expected: previous.id + 1,
+                            found: block.id,
+                        },
+                    );
+                }
+            }
+        }
+
+        let index = self.recent_blocks.len() - 1;
+        if last.id != self.height {
+            return fail(
+                index,
+                BlockError::BadId {
+                    expected: self.height,
+                    found: last.id,
+                },
+            );
+        }
+        if last.hash != self.hash {
+            return fail(index, BlockError::HashMismatch);
+        }
+        Ok(())
+    }
+}
+
+/// Check the headers of pruned blocks: each one must extend the one before it and carry the
+/// work it claims, and the last one must be the parent of `next`, the first block still kept.
+/// Blocks sealed by proof-of-authority are only checked for their links, the signatures
+/// were pruned with the bodies.
+pub fn verify_headers(headers: &[BlockHeader], next: &Block) -> Result<(), ChainError> {
+    for (index, header) in headers.iter().enumerate() {
+        let fail = |error| Err(ChainError { index, error });
+        let hash = header.hash();
+        if !header.has_valid_work() {
+            return fail(BlockError::InsufficientWork {
+                difficulty: header.difficulty,
+            });
+        }
+
+        let (expected_id, expected_hash) = match headers.get(index + 1) {
+            Some(child) => (child.id, child.previous_hash),
+            None => (next.id, next.previous_hash),
+        };
+        if expected_hash != hash {
+            return fail(BlockError::BadPreviousHash {
+                expected: hash,
+                found: expected_hash,
+            });
+        }
+        if expected_id != header.id + 1 {
+            return fail(BlockError::BadId {
+                expected: header.id + 1,
+                found: expected_id,
+            });
+        }
+    }
+    Ok(())
+}
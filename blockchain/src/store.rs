@@ -0,0 +1,397 @@
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Read, Write};
+use std::path::{Path, PathBuf};
+
+use crate::block::{Block, BlockHeader, HEADER_LEN};
+use crate::export::{decode_block, encode_block};
+use crate::legacy::{self, LegacyBlock};
+use crate::metrics::Event;
+use crate::snapshot::Snapshot;
+
+/// Every record starts with the payload length and the CRC32 of the payload (both u32, little endian)
+const RECORD_HEADER_LEN: usize = 8;
+
+/// Append-only on-disk block store.
+///
+/// Each block is written as one record: `[payload length][crc32 of payload][payload]`,
+/// where the payload is the binary encoded block (see `encode_block`).
+/// Stores written before header version 1 hold JSON payloads; they are migrated when opened.
+///
+/// Once the chain is pruned, the store only holds the blocks after the last snapshot. The
+/// snapshot is kept next to it as JSON (`<store>.snapshot`), and the headers of the pruned
+/// blocks as consecutive encoded headers (`<store>.headers`).
+#[derive(Debug)]
+pub struct BlockStore {
+    path: PathBuf,
+    file: File,
+    /// What opening the store repaired or migrated, until taken with `take_events`
+    events: Vec<Event>,
+}
+
+impl BlockStore {
+    /// Open (or create) the store at `path` and read back every complete record.
+    /// A torn or corrupted trailing record, left behind by a crash in the middle of a write,
+    /// is truncated so that the next append starts on a clean record boundary. A corrupted
+    /// record with valid records after it is an `InvalidData` error instead, since truncating
+    /// would throw those blocks away.
+    pub fn open<P: AsRef<Path>>(path: P) -> io::Result<(Self, Vec<Block>)> {
+        let path = path.as_ref().to_path_buf();
+        let mut file = OpenOptions::new()
+            .read(true)
+            .append(true)
+            .create(true)
+            .open(&path)?;
+
+        let mut bytes = Vec::new();
+        file.read_to_end(&mut bytes)?;
+
+        let (records, valid_len) = decode_records(&bytes)?;
+        let mut events = vec![];
+        if valid_len < bytes.len() {
+            events.push(Event::StoreTruncated {
+                offset: valid_len as u64,
+                bytes: (bytes.len() - valid_len) as u64,
+            });
+            file.set_len(valid_len as u64)?;
+            file.sync_all()?;
+        }
+
+        let mut store = Self { path, file, events };
+        let blocks = match records {
+            Records::Blocks(blocks) => blocks,
+            Records::Legacy(legacy) => {
+                store.events.push(Event::StoreMigrated {
+                    blocks: legacy.len(),
+                });
+                let blocks = legacy::migrate(&legacy)
+                    .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+                store.rewrite(&blocks)?;
+                blocks
+            }
+        };
+        Ok((store, blocks))
+    }
+
+    /// What opening the store repaired or migrated, for the caller to log
+    pub fn take_events(&mut self) -> Vec<Event> {
+        std::mem::take(&mut self.events)
+    }
+
+    /// Append one block to the end of the store and flush it to disk
+    pub fn append(&mut self, block: &Block) -> io::Result<()> {
+        let record = encode_record(block)?;
+        self.file.write_all(&record)?;
+        self.file.sync_data()
+    }
+
+    /// Replace the whole store with `blocks`. The new contents are written to a temporary file
+    /// which is then renamed over the store, so a crash leaves either the old or the new chain.
+    pub fn rewrite(&mut self, blocks: &[Block]) -> io::Result<()> {
+        let tmp_path = self.path.with_extension("tmp");
+        {
+            let mut tmp = File::create(&tmp_path)?;
+            for block in blocks {
+                tmp.write_all(&encode_record(block)?)?;
+            }
+            tmp.sync_all()?;
+        }
+        fs::rename(&tmp_path, &self.path)?;
+
+        self.file = OpenOptions::new()
+            .read(true)
+            .append(true)
+            .open(&self.path)?;
+        Ok(())
+    }
+
+    /// The snapshot written by the last `prune`, if the chain was ever pruned
+    pub fn snapshot(&self) -> io::Result<Option<Snapshot>> {
+        match fs::read(self.path.with_extension("snapshot")) {
+            Ok(bytes) => Ok(Some(serde_json::from_slice(&bytes)?)),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(e),
+        }
+    }
+
+    /// Headers of every pruned block, oldest first
+    pub fn pruned_headers(&self) -> io::Result<Vec<BlockHeader>> {
+        let bytes = match fs::read(self.path.with_extension("headers")) {
+            Ok(bytes) => bytes,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(vec![]),
+            Err(e) => return Err(e),
+        };
+        Ok(bytes
+            .chunks_exact(HEADER_LEN)
+            .map(|header| BlockHeader::from_bytes(header.try_into().unwrap()))
+            .collect())
+    }
+
+    /// Record that the chain was pruned at `snapshot`: append the headers of the `pruned`
+    /// blocks, write the snapshot and rewrite the store with `blocks`, the blocks after it.
+    ///
+    /// Each step can be repeated after a crash: headers already written are skipped, and
+    /// blocks the snapshot covers are ignored when the store is opened.
+    pub fn prune(
+        &mut self,
+        snapshot: &Snapshot,
+        pruned: &[BlockHeader],
+        blocks: &[Block],
+    ) -> io::Result<()> {
+        let headers_path = self.path.with_extension("headers");
+        let last_id = self.pruned_headers()?.last().map_or(0, |header| header.id);
+        let mut headers = OpenOptions::new()
+            .append(true)
+            .create(true)
+            .open(&headers_path)?;
+        for header in pruned.iter().filter(|header| header.id > last_id) {
+            headers.write_all(&header.to_bytes())?;
+        }
+        headers.sync_all()?;
+
+        let tmp_path = self.path.with_extension("snapshot.tmp");
+        fs::write(&tmp_path, serde_json::to_vec(snapshot)?)?;
+        File::open(&tmp_path)?.sync_all()?;
+        fs::rename(&tmp_path, self.path.with_extension("snapshot"))?;
+
+        self.rewrite(blocks)
+    }
+
+    /// Forget the snapshot and the pruned headers, when a whole chain from genesis replaces
+    /// the pruned one
+    pub fn remove_snapshot(&mut self) -> io::Result<()> {
+        for extension in ["snapshot", "headers"] {
+            match fs::remove_file(self.path.with_extension(extension)) {
+                Err(e) if e.kind() != io::ErrorKind::NotFound => return Err(e),
+                _ => {}
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Contents of a store: every record is in the current binary format, or every record is
+/// a legacy JSON block
+enum Records {
+    Blocks(Vec<Block>),
+    Legacy(Vec<LegacyBlock>),
+}
+
+fn encode_record(block: &Block) -> io::Result<Vec<u8>> {
+    let payload = encode_block(block)?;
+    let mut record = Vec::with_capacity(RECORD_HEADER_LEN + payload.len());
+    record.extend_from_slice(&(payload.len() as u32).to_le_bytes());
+    record.extend_from_slice(&crc32fast::hash(&payload).to_le_bytes());
+    record.extend_from_slice(&payload);
+    Ok(record)
+}
+
+/// Decode records until the first incomplete or corrupted one, which must be the last one.
+/// Returns the decoded blocks and the number of bytes they occupy.
+/// A record with a good checksum that does not decode is an error rather than a torn write,
+/// so a store written by an incompatible version is never truncated away. So is a corrupted
+/// record with a valid one anywhere after it: a crash only ever tears the last record.
+fn decode_records(bytes: &[u8]) -> io::Result<(Records, usize)> {
+    let mut blocks = Vec::new();
+    let mut legacy = Vec::new();
+    let mut offset = 0;
+
+    while bytes.len() - offset >= RECORD_HEADER_LEN {
+        let len = u32::from_le_bytes(bytes[offset..offset + 4].try_into().unwrap()) as usize;
+        let checksum = u32::from_le_bytes(bytes[offset + 4..offset + 8].try_into().unwrap());
+        let start = offset + RECORD_HEADER_LEN;
+
+        let payload = bytes
+            .get(start..start + len)
+            .filter(|payload| crc32fast::hash(payload) == checksum);
+        let Some(payload) = payload else {
+            if has_record_after(bytes, offset) {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!(
+                        "Block store has a corrupted record at byte {} followed by valid records",
+                        offset
+                    ),
+                ));
+            }
+            break;
+        };
+        // A binary payload starts with the header version, a JSON one with '{'
+        if payload.first() == Some(&b'{') {
+            legacy.push(serde_json::from_slice(payload)?);
+        } else {
+            blocks.push(decode_block(payload)?);
+        }
+        offset = start + len;
+    }
+
+    match (blocks.is_empty(), legacy.is_empty()) {
+        (_, true) => Ok((Records::Blocks(blocks), offset)),
+        (true, false) => Ok((Records::Legacy(legacy), offset)),
+        (false, false) => Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "Block store mixes the old and the new block format",
+        )),
+    }
+}
+
+/// Is there a complete record with a good checksum anywhere after `offset`? Its length may be
+/// what is corrupted, so every later byte is tried as the start of a record.
+fn has_record_after(bytes: &[u8], offset: usize) -> bool {
+    (offset + 1..bytes.len().saturating_sub(RECORD_HEADER_LEN)).any(|start| {
+        let len = u32::from_le_bytes(bytes[start..start + 4].try_into().unwrap()) as usize;
+        let checksum = u32::from_le_bytes(bytes[start + 4..start + 8].try_into().unwrap());
+        let payload_start = start + RECORD_HEADER_LEN;
+        // An empty payload has a checksum of 0, which any run of zero bytes would match
+        len > 0
+            && bytes
+                .get(payload_start..payload_start + len)
+                .is_some_and(|payload| crc32fast::hash(payload) == checksum)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn temp_store_path(name: &str) -> PathBuf {
+        let path = std::env::temp_dir().join(format!(
+            "blockchain-store-{}-{}.db",
+            std::process::id(),
+            name
+        ));
+        let _ = fs::remove_file(&path);
+        path
+    }
+
+    fn sample_block(id: u64) -> Block {
+        let mut block = Block {
+            version: crate::block::HEADER_VERSION,
+            id,
+            nonce: id * 7,
+            transactions: vec![],
+            merkle_root: crate::merkle::EMPTY_MERKLE_ROOT,
+            hash: [0; 32],
+            previous_hash: [id as u8 - 1; 32],
+            timestamp: 1_700_000_000 + id as i64,
+            difficulty: 16,
+            signature: String::new(),
+        };
+        block.hash = block.compute_hash();
+        block
+    }
+
+    #[test]
+    fn reopened_store_returns_appended_blocks() {
+        let path = temp_store_path("reopen");
+        {
+            let (mut store, blocks) = BlockStore::open(&path).unwrap();
+            assert!(blocks.is_empty());
+            store.append(&sample_block(1)).unwrap();
+            store.append(&sample_block(2)).unwrap();
+        }
+
+        let (_, blocks) = BlockStore::open(&path).unwrap();
+        assert_eq!(blocks, vec![sample_block(1), sample_block(2)]);
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn torn_trailing_record_is_truncated() {
+        let path = temp_store_path("torn");
+        {
+            let (mut store, _) = BlockStore::open(&path).unwrap();
+            store.append(&sample_block(1)).unwrap();
+        }
+        let good_len = fs::metadata(&path).unwrap().len();
+
+        // Simulate a crash half way through writing the second record
+        let record = encode_record(&sample_block(2)).unwrap();
+        let mut file = OpenOptions::new().append(true).open(&path).unwrap();
+        file.write_all(&record[..record.len() / 2]).unwrap();
+        drop(file);
+
+        let (mut store, blocks) = BlockStore::open(&path).unwrap();
+        assert_eq!(blocks, vec![sample_block(1)]);
+        assert_eq!(fs::metadata(&path).unwrap().len(), good_len);
+        assert_eq!(
+            store.take_events(),
+            [Event::StoreTruncated {
+                offset: good_len,
+                bytes: (record.len() / 2) as u64,
+            }]
+        );
+
+        // Appending after recovery starts on a clean record boundary
+        store.append(&sample_block(2)).unwrap();
+        let (_, blocks) = BlockStore::open(&path).unwrap();
+        assert_eq!(blocks, vec![sample_block(1), sample_block(2)]);
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn corrupted_checksum_is_truncated() {
+        let path = temp_store_path("checksum");
+        {
+            let (mut store, _) = BlockStore::open(&path).unwrap();
+            store.append(&sample_block(1)).unwrap();
+            store.append(&sample_block(2)).unwrap();
+        }
+
+        // Flip the last byte of the file, which belongs to the second record's payload
+        let mut bytes = fs::read(&path).unwrap();
+        *bytes.last_mut().unwrap() ^= 0xff;
+        fs::write(&path, &bytes).unwrap();
+
+        let (_, blocks) = BlockStore::open(&path).unwrap();
+        assert_eq!(blocks, vec![sample_block(1)]);
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn corrupted_middle_record_is_an_error() {
+        let path = temp_store_path("middle");
+        {
+            let (mut store, _) = BlockStore::open(&path).unwrap();
+            for id in 1..=3 {
+                store.append(&sample_block(id)).unwrap();
+            }
+        }
+
+        // Flip a byte of the second record's payload, and then of its length
+        let good = fs::read(&path).unwrap();
+        let second = encode_record(&sample_block(1)).unwrap().len();
+        for corrupted in [second + RECORD_HEADER_LEN + 10, second] {
+            let mut bytes = good.clone();
+            bytes[corrupted] ^= 0xff;
+            fs::write(&path, &bytes).unwrap();
+
+            let error = BlockStore::open(&path).unwrap_err();
+            assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+            // Nothing was truncated
+            assert_eq!(fs::read(&path).unwrap(), bytes);
+        }
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn legacy_json_store_is_migrated() {
+        let path = temp_store_path("legacy");
+        let legacy = crate::legacy::tests::legacy_chain(3);
+        let mut bytes = Vec::new();
+        for block in &legacy {
+            let payload = serde_json::to_vec(block).unwrap();
+            bytes.extend_from_slice(&(payload.len() as u32).to_le_bytes());
+            bytes.extend_from_slice(&crc32fast::hash(&payload).to_le_bytes());
+            bytes.extend_from_slice(&payload);
+        }
+        fs::write(&path, &bytes).unwrap();
+
+        let (_, blocks) = BlockStore::open(&path).unwrap();
+        assert_eq!(blocks, crate::legacy::migrate(&legacy).unwrap()[..]);
+
+        // The store was rewritten in the new format, so it opens without migrating again
+        let (_, reopened) = BlockStore::open(&path).unwrap();
+        assert_eq!(reopened, blocks);
+        fs::remove_file(&path).unwrap();
+    }
+}
@@ -0,0 +1,235 @@
+use ed25519_dalek::{Signature, Signer, SigningKey, Verifier, VerifyingKey};
+use rand::rngs::OsRng;
+use serde::{Deserialize, Serialize};
+
+use crate::hash::{sha3, Hash};
+use crate::script::SCRIPT_ADDRESS_PREFIX;
+
+/// Sender of the coinbase transaction that pays the block reward. Not a valid hex address,
+/// so it cannot clash with a real account.
+pub const COINBASE_SENDER: &str = "coinbase";
+
+/// Longest sender, recipient or signature, in bytes. The binary encoding of a block writes
+/// their lengths as a u16, so a longer one could be validated but never stored.
+pub const MAX_FIELD_LEN: usize = u16::MAX as usize;
+
+/// First bytes of every signed message, so a transaction signature cannot be replayed as the
+/// signature of anything else signed with the same key
+const SIGNING_DOMAIN: &[u8] = b"blockchain/transaction/v1";
+
+/// Generate a new random Ed25519 keypair
+pub fn generate_keypair() -> SigningKey {
+    SigningKey::generate(&mut OsRng)
+}
+
+/// Address of a key on the chain: the hex encoded Ed25519 public key
+pub fn address(key: &VerifyingKey) -> String {
+    hex::encode(key.as_bytes())
+}
+
+/// Can funds be sent to `address`? It must be a hex key address or a script address, both
+/// lowercase hex of a 32 byte key or hash, so every account has exactly one spelling.
+pub fn is_valid_address(address: &str) -> bool {
+    let hex = address
+        .strip_prefix(SCRIPT_ADDRESS_PREFIX)
+        .unwrap_or(address);
+    hex.len() == 64 && hex.bytes().all(|b| matches!(b, b'0'..=b'9' | b'a'..=b'f'))
+}
+
+/// Transfer of `amount` from `sender` to `recipient`, signed by the sender's key.
+/// `nonce` is the number of transactions the sender has sent before this one.
+/// The sender also pays `fee`, which the miner of the block collects through the coinbase.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Transaction {
+    pub sender: String,
+    pub recipient: String,
+    pub amount: u64,
+    #[serde(default)]
+    pub fee: u64,
+    pub nonce: u64,
+    pub signature: String,
+}
+
+impl Transaction {
+    /// Create a transaction without a fee from the owner of `signing_key` and sign it
+    pub fn new(signing_key: &SigningKey, recipient: String, amount: u64, nonce: u64) -> Self {
+        Transaction::with_fee(signing_key, recipient, amount, 0, nonce)
+    }
+
+    /// Create a transaction paying `fee` to the miner, and sign it
+    pub fn with_fee(
+        signing_key: &SigningKey,
+        recipient: String,
+        amount: u64,
+        fee: u64,
+        nonce: u64,
+    ) -> Self {
+        let sender = address(&signing_key.verifying_key());
+        let mut transaction = Transaction::unsigned(sender, recipient, amount, fee, nonce);
+        let signature = signing_key.sign(&transaction.signing_bytes());
+        transaction.signature = hex::encode(signature.to_bytes());
+        transaction
+    }
+
+    /// Transaction without a signature yet. A spend from a script address is built this way,
+    /// and its `signature` set to the witness (see `script::witness`).
+    pub fn unsigned(sender: String, recipient: String, amount: u64, fee: u64, nonce: u64) -> Self {
+        Self {
+            sender,
+            recipient,
+            amount,
+            fee,
+            nonce,
+            signature: String::new(),
+        }
+    }
+
+    /// Block reward paid to `recipient`, the miner of block `block_id`. It has no signature;
+    /// the block id is used as nonce so every coinbase transaction has a distinct id.
+    pub fn coinbase(recipient: String, amount: u64, block_id: u64) -> Self {
+        Self {
+            sender: COINBASE_SENDER.to_string(),
+            recipient,
+            amount,
+            fee: 0,
+            nonce: block_id,
+            signature: String::new(),
+        }
+    }
+
+    pub fn is_coinbase(&self) -> bool {
+        self.sender == COINBASE_SENDER
+    }
+
+    /// Is the sender a script address? Its `signature` then holds the script's witness.
+    pub fn is_script_spend(&self) -> bool {
+        self.sender.starts_with(SCRIPT_ADDRESS_PREFIX)
+    }
+
+    /// The bytes a signature covers, also for the signatures checked by a script.
+    ///
+    /// `domain | sender | recipient | amount (u64) | fee (u64) | nonce (u64)`, where the strings
+    /// are written as a u64 byte length followed by their UTF-8 bytes and integers are little
+    /// endian. Every field is always present at a fixed place, so no two transactions share
+    /// the same message.
+    pub fn signing_bytes(&self) -> Vec<u8> {
+        let mut bytes = SIGNING_DOMAIN.to_vec();
+        write_field(&mut bytes, self.sender.as_bytes());
+        write_field(&mut bytes, self.recipient.as_bytes());
+        bytes.extend_from_slice(&self.amount.to_le_bytes());
+        bytes.extend_from_slice(&self.fee.to_le_bytes());
+        bytes.extend_from_slice(&self.nonce.to_le_bytes());
+        bytes
+    }
+
+    /// Transaction id, used as the Merkle leaf of the transaction
+    pub fn hash(&self) -> Hash {
+        let mut bytes = self.signing_bytes();
+        write_field(&mut bytes, self.signature.as_bytes());
+        sha3(bytes)
+    }
+
+    /// Size of the transaction in a block's binary encoding, the basis of its fee rate
+    pub fn size(&self) -> usize {
+        // Three u16 length prefixes and three u64 fields
+        3 * 2 + 3 * 8 + self.sender.len() + self.recipient.len() + self.signature.len()
+    }
+
+    /// Length of the longest of the sender, recipient and signature, see `MAX_FIELD_LEN`
+    pub fn longest_field(&self) -> usize {
+        self.sender
+            .len()
+            .max(self.recipient.len())
+            .max(self.signature.len())
+    }
+
+    /// Check that the signature was made by the key behind `sender`
+    pub fn verify_signature(&self) -> bool {
+        let Some(key) = hex::decode(&self.sender)
+            .ok()
+            .and_then(|bytes| bytes.try_into().ok())
+            .and_then(|bytes: [u8; 32]| VerifyingKey::from_bytes(&bytes).ok())
+        else {
+            return false;
+        };
+        let Some(signature) = hex::decode(&self.signature)
+            .ok()
+            .and_then(|bytes| Signature::from_slice(&bytes).ok())
+        else {
+            return false;
+        };
+
+        key.verify(&self.signing_bytes(), &signature).is_ok()
+    }
+}
+
+fn write_field(bytes: &mut Vec<u8>, field: &[u8]) {
+    bytes.extend_from_slice(&(field.len() as u64).to_le_bytes());
+    bytes.extend_from_slice(field);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn signed_transaction_verifies() {
+        let alice = generate_keypair();
+        let bob = generate_keypair();
+        let transaction = Transaction::new(&alice, address(&bob.verifying_key()), 10, 0);
+        assert_eq!(transaction.sender, address(&alice.verifying_key()));
+        assert!(transaction.verify_signature());
+    }
+
+    #[test]
+    fn tampered_transaction_fails_verification() {
+        let alice = generate_keypair();
+        let bob = generate_keypair();
+        let mut transaction = Transaction::new(&alice, address(&bob.verifying_key()), 10, 0);
+        transaction.amount = 1_000;
+        assert!(!transaction.verify_signature());
+
+        // Claiming to be someone else does not work either
+        let mut transaction = Transaction::new(&alice, address(&bob.verifying_key()), 10, 0);
+        transaction.sender = address(&bob.verifying_key());
+        assert!(!transaction.verify_signature());
+    }
+
+    #[test]
+    fn fields_cannot_be_shifted_into_each_other() {
+        let alice = generate_keypair();
+        let recipient = address(&generate_keypair().verifying_key());
+        let transaction = Transaction::with_fee(&alice, recipient.clone(), 10, 3, 7);
+
+        // Moving the amount into the recipient and the other fields down one place
+        let mut shifted = transaction.clone();
+        shifted.recipient = format!("{}:10", recipient);
+        shifted.amount = 7;
+        shifted.nonce = 3;
+        shifted.fee = 0;
+        assert!(!shifted.verify_signature());
+        assert_ne!(shifted.hash(), transaction.hash());
+    }
+
+    #[test]
+    fn only_key_and_script_addresses_are_valid() {
+        let key = address(&generate_keypair().verifying_key());
+        assert!(is_valid_address(&key));
+        assert!(is_valid_address(&format!(
+            "{}{}",
+            SCRIPT_ADDRESS_PREFIX, key
+        )));
+
+        for invalid in [
+            String::new(),
+            "bob".to_string(),
+            COINBASE_SENDER.to_string(),
+            key.to_uppercase(),
+            format!("{}:10", key),
+            key[..62].to_string(),
+            format!("{}{}", SCRIPT_ADDRESS_PREFIX, &key[..10]),
+        ] {
+            assert!(!is_valid_address(&invalid), "{}", invalid);
+        }
+    }
+}
@@ -0,0 +1,269 @@
+use std::collections::HashMap;
+use std::net::IpAddr;
+
+use crate::block::Block;
+use crate::difficulty::block_work;
+use crate::hash::Hash;
+
+/// Upper bound on blocks held while waiting for their parent, so a peer cannot fill our memory.
+/// When full, the orphan that has waited longest makes room for the new one.
+pub const MAX_ORPHANS: usize = 256;
+
+/// Upper bound on orphans held from one peer. A peer at its limit only pushes out its own
+/// orphans, not those of other peers.
+pub const MAX_ORPHANS_PER_PEER: usize = 32;
+
+/// A block waiting for its parent
+#[derive(Debug)]
+struct Orphan {
+    block: Block,
+    /// Order of arrival
+    received: u64,
+    /// Address of the peer that sent it, `None` for a block from this node
+    peer: Option<IpAddr>,
+}
+
+/// Every known block, including those on competing branches, indexed by hash.
+/// Each block carries the cumulative proof-of-work of the branch it ends.
+#[derive(Debug, Default)]
+pub struct BlockTree {
+    blocks: HashMap<Hash, (Block, u128)>,
+    /// Blocks whose parent has not arrived yet, keyed by their hash
+    orphans: HashMap<Hash, Orphan>,
+    orphans_received: u64,
+}
+
+impl BlockTree {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Add a block whose parent is already in the tree (or a genesis block).
+    /// Returns the cumulative work of the branch ending with it.
+    pub fn insert(&mut self, block: Block) -> u128 {
+        let parent_work = self.cumulative_work(&block.previous_hash).unwrap_or(0);
+        let work = parent_work.saturating_add(block_work(block.difficulty));
+        self.blocks.insert(block.hash, (block, work));
+        work
+    }
+
+    pub fn remove(&mut self, hash: &Hash) {
+        self.blocks.remove(hash);
+    }
+
+    pub fn contains(&self, hash: &Hash) -> bool {
+        self.blocks.contains_key(hash)
+    }
+
+    pub fn get(&self, hash: &Hash) -> Option<&Block> {
+        self.blocks.get(hash).map(|(block, _)| block)
+    }
+
+    pub fn cumulative_work(&self, hash: &Hash) -> Option<u128> {
+        self.blocks.get(hash).map(|(_, work)| *work)
+    }
+
+    pub fn len(&self) -> usize {
+        self.blocks.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.blocks.is_empty()
+    }
+
+    /// Blocks that no other known block builds on: the tips of all competing branches
+    pub fn tips(&self) -> Vec<&Block> {
+        let parents = self
+            .blocks
+            .values()
+            .map(|(block, _)| &block.previous_hash)
+            .collect::<std::collections::HashSet<&Hash>>();
+        self.blocks
+            .values()
+            .map(|(block, _)| block)
+            .filter(|block| !parents.contains(&block.hash))
+            .collect()
+    }
+
+    /// The chain from the first block of `active` (genesis, or the oldest block kept after
+    /// pruning) to the block with `hash`. `active` is the current best chain; the walk back
+    /// through parents stops as soon as it joins it.
+    pub fn chain_to(&self, hash: &Hash, active: &[Block]) -> Option<Vec<Block>> {
+        let first_id = active.first()?.id;
+        let mut branch = vec![];
+        let mut current = self.get(hash)?;
+        loop {
+            let index = current.id.checked_sub(first_id)? as usize;
+            if active.get(index).is_some_and(|b| b.hash == current.hash) {
+                let mut chain = active[..=index].to_vec();
+                chain.extend(branch.into_iter().rev());
+                return Some(chain);
+            }
+            branch.push(current.clone());
+            current = self.get(&current.previous_hash)?;
+        }
+    }
+
+    /// Hold a block sent by `peer` until its parent arrives. If `peer` already has
+    /// `MAX_ORPHANS_PER_PEER` orphans its oldest one is evicted, otherwise the oldest of all if
+    /// the pool is full. The block is keyed by the hash of its header, not the hash it claims.
+    pub fn add_orphan(&mut self, block: Block, peer: Option<IpAddr>) {
+        let hash = block.compute_hash();
+        if self.orphans.contains_key(&hash) {
+            return;
+        }
+        let from_peer = self
+            .orphans
+            .values()
+            .filter(|orphan| peer.is_some() && orphan.peer == peer)
+            .count();
+        if from_peer >= MAX_ORPHANS_PER_PEER {
+            self.evict_oldest(|orphan| orphan.peer == peer);
+        } else if self.orphans.len() >= MAX_ORPHANS {
+            self.evict_oldest(|_| true);
+        }
+        self.orphans_received += 1;
+        self.orphans.insert(
+            hash,
+            Orphan {
+                block,
+                received: self.orphans_received,
+                peer,
+            },
+        );
+    }
+
+    fn evict_oldest(&mut self, filter: impl Fn(&Orphan) -> bool) {
+        let oldest = self
+            .orphans
+            .iter()
+            .filter(|(_, orphan)| filter(orphan))
+            .min_by_key(|(_, orphan)| orphan.received)
+            .map(|(hash, _)| *hash);
+        if let Some(oldest) = oldest {
+            self.orphans.remove(&oldest);
+        }
+    }
+
+    pub fn is_orphan(&self, hash: &Hash) -> bool {
+        self.orphans.contains_key(hash)
+    }
+
+    pub fn orphan_count(&self) -> usize {
+        self.orphans.len()
+    }
+
+    /// Remove and return the orphans waiting for the block with `parent_hash`, in the order
+    /// they arrived
+    pub fn take_orphans(&mut self, parent_hash: &Hash) -> Vec<Block> {
+        let hashes = self
+            .orphans
+            .iter()
+            .filter(|(_, orphan)| orphan.block.previous_hash == *parent_hash)
+            .map(|(hash, _)| *hash)
+            .collect::<Vec<Hash>>();
+        let mut orphans = hashes
+            .iter()
+            .filter_map(|hash| self.orphans.remove(hash))
+            .collect::<Vec<Orphan>>();
+        orphans.sort_by_key(|orphan| orphan.received);
+        orphans.into_iter().map(|orphan| orphan.block).collect()
+    }
+
+    /// Forget every block that is neither in `keep` (a run of the best chain) nor on a branch
+    /// forking off after its first block. Orphans are kept.
+    pub fn prune(&mut self, keep: &[Block]) {
+        let Some(first) = keep.first() else {
+            return;
+        };
+        let mut by_id = self
+            .blocks
+            .values()
+            .map(|(block, _)| (block.id, block.hash, block.previous_hash))
+            .collect::<Vec<(u64, Hash, Hash)>>();
+        by_id.sort_unstable_by_key(|(id, _, _)| *id);
+
+        let mut kept = keep
+            .iter()
+            .map(|block| block.hash)
+            .collect::<std::collections::HashSet<Hash>>();
+        for (id, hash, previous_hash) in by_id {
+            if id > first.id && kept.contains(&previous_hash) {
+                kept.insert(hash);
+            }
+        }
+        self.blocks.retain(|hash, _| kept.contains(hash));
+    }
+
+    /// Known blocks of every branch with an id above `id`, in id order
+    pub fn blocks_after(&self, id: u64) -> Vec<Block> {
+        let mut blocks = self
+            .blocks
+            .values()
+            .map(|(block, _)| block)
+            .filter(|block| block.id > id)
+            .cloned()
+            .collect::<Vec<Block>>();
+        blocks.sort_by_key(|block| block.id);
+        blocks
+    }
+
+    pub fn clear(&mut self) {
+        self.blocks.clear();
+        self.orphans.clear();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::hash::ZERO_HASH;
+
+    fn orphan(id: u64, parent: Hash) -> Block {
+        Block::new(id, parent, vec![], 0)
+    }
+
+    #[test]
+    fn full_orphan_pool_evicts_the_oldest() {
+        let mut tree = BlockTree::new();
+        let first = orphan(10, [1; 32]);
+        tree.add_orphan(first.clone(), None);
+        for id in 11..10 + MAX_ORPHANS as u64 {
+            tree.add_orphan(orphan(id, [2; 32]), None);
+        }
+        assert_eq!(tree.orphan_count(), MAX_ORPHANS);
+
+        let newest = orphan(1_000, ZERO_HASH);
+        tree.add_orphan(newest.clone(), None);
+        assert_eq!(tree.orphan_count(), MAX_ORPHANS);
+        assert!(!tree.is_orphan(&first.hash));
+        assert_eq!(tree.take_orphans(&ZERO_HASH), vec![newest]);
+    }
+
+    #[test]
+    fn orphans_are_keyed_by_their_real_hash() {
+        let mut tree = BlockTree::new();
+        let honest = orphan(5, [1; 32]);
+        let mut junk = orphan(5, [1; 32]);
+        junk.nonce += 1;
+        junk.hash = honest.hash;
+
+        tree.add_orphan(junk.clone(), None);
+        assert!(!tree.is_orphan(&honest.hash));
+        tree.add_orphan(honest.clone(), None);
+        assert_eq!(tree.take_orphans(&[1; 32]).len(), 2);
+    }
+
+    #[test]
+    fn a_peer_only_pushes_out_its_own_orphans() {
+        let mut tree = BlockTree::new();
+        let honest = orphan(10, [1; 32]);
+        tree.add_orphan(honest.clone(), Some([10, 0, 0, 1].into()));
+        let spammer = Some([10, 0, 0, 2].into());
+        for id in 0..MAX_ORPHANS as u64 * 2 {
+            tree.add_orphan(orphan(100 + id, [2; 32]), spammer);
+        }
+        assert_eq!(tree.orphan_count(), 1 + MAX_ORPHANS_PER_PEER);
+        assert!(tree.is_orphan(&honest.hash));
+    }
+}
@@ -0,0 +1,541 @@
+use std::fs;
+use std::io::{Read, Write};
+use std::net::TcpStream;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use argon2::{Algorithm, Argon2, Params, Version};
+use chacha20poly1305::aead::{Aead, KeyInit, Payload};
+use chacha20poly1305::{ChaCha20Poly1305, Key, Nonce};
+use ed25519_dalek::SigningKey;
+use rand::rngs::OsRng;
+use rand::RngCore;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+use crate::block::Block;
+use crate::error::WalletError;
+use crate::hash::{hash_from_hex, Hash};
+use crate::transaction::{address, generate_keypair, Transaction};
+
+const SALT_LEN: usize = 16;
+const NONCE_LEN: usize = 12;
+const NODE_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Cost of deriving the keystore's encryption key from its password (Argon2id)
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct KdfParams {
+    pub memory_kib: u32,
+    pub iterations: u32,
+}
+
+impl Default for KdfParams {
+    /// The parameters recommended by OWASP for Argon2id
+    fn default() -> Self {
+        Self {
+            memory_kib: 19 * 1024,
+            iterations: 2,
+        }
+    }
+}
+
+/// A key of the wallet and the name it was given
+#[derive(Debug, Clone)]
+pub struct WalletKey {
+    pub name: String,
+    pub signing_key: SigningKey,
+}
+
+impl WalletKey {
+    pub fn address(&self) -> String {
+        address(&self.signing_key.verifying_key())
+    }
+}
+
+/// The keystore file: every secret key is encrypted on its own with ChaCha20-Poly1305,
+/// under a key derived from the password and the salt. Names and addresses are in clear
+/// text, so the keys can be listed, but are authenticated with the secret key.
+#[derive(Serialize, Deserialize)]
+struct KeystoreFile {
+    kdf: KdfParams,
+    salt: String,
+    keys: Vec<EncryptedKey>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct EncryptedKey {
+    name: String,
+    address: String,
+    nonce: String,
+    ciphertext: String,
+}
+
+/// Keypairs stored in an encrypted file, decrypted in memory while the keystore is open
+pub struct Keystore {
+    path: PathBuf,
+    file: KeystoreFile,
+    cipher: ChaCha20Poly1305,
+    keys: Vec<WalletKey>,
+}
+
+impl Keystore {
+    /// Create an empty keystore at `path` protected by `password`
+    pub fn create<P: AsRef<Path>>(path: P, password: &str) -> Result<Self, WalletError> {
+        Keystore::create_with_kdf(path, password, KdfParams::default())
+    }
+
+    pub fn create_with_kdf<P: AsRef<Path>>(
+        path: P,
+        password: &str,
+        kdf: KdfParams,
+    ) -> Result<Self, WalletError> {
+        let path = path.as_ref().to_path_buf();
+        if path.exists() {
+            return Err(WalletError::KeystoreExists);
+        }
+        let mut salt = [0u8; SALT_LEN];
+        OsRng.fill_bytes(&mut salt);
+
+        let keystore = Self {
+            cipher: derive_cipher(password, &salt, kdf)?,
+            path,
+            file: KeystoreFile {
+                kdf,
+                salt: hex::encode(salt),
+                keys: vec![],
+            },
+            keys: vec![],
+        };
+        keystore.save()?;
+        Ok(keystore)
+    }
+
+    /// Open the keystore at `path` and decrypt its keys
+    pub fn open<P: AsRef<Path>>(path: P, password: &str) -> Result<Self, WalletError> {
+        let path = path.as_ref().to_path_buf();
+        let file: KeystoreFile = serde_json::from_slice(&fs::read(&path)?)
+            .map_err(|e| WalletError::BadKeystore(e.to_string()))?;
+        let salt = decode_hex(&file.salt)?;
+        let cipher = derive_cipher(password, &salt, file.kdf)?;
+
+        let mut keys = Vec::new();
+        for encrypted in &file.keys {
+            let nonce = decode_hex(&encrypted.nonce)?;
+            if nonce.len() != NONCE_LEN {
+                return Err(WalletError::BadKeystore(format!(
+                    "nonce of key {} has {} bytes",
+                    encrypted.name,
+                    nonce.len()
+                )));
+            }
+            let secret = cipher
+                .decrypt(
+                    Nonce::from_slice(&nonce),
+                    Payload {
+                        msg: &decode_hex(&encrypted.ciphertext)?,
+                        aad: associated_data(&encrypted.name, &encrypted.address).as_bytes(),
+                    },
+                )
+                .map_err(|_| WalletError::WrongPassword)?;
+            let secret: [u8; 32] = secret.try_into().map_err(|_| {
+                WalletError::BadKeystore(format!("key {} is not 32 bytes", encrypted.name))
+            })?;
+            keys.push(WalletKey {
+                name: encrypted.name.clone(),
+                signing_key: SigningKey::from_bytes(&secret),
+            });
+        }
+
+        Ok(Self {
+            path,
+            file,
+            cipher,
+            keys,
+        })
+    }
+
+    /// Open the keystore at `path`, or create it if there is none
+    pub fn open_or_create<P: AsRef<Path>>(path: P, password: &str) -> Result<Self, WalletError> {
+        if path.as_ref().exists() {
+            Keystore::open(path, password)
+        } else {
+            Keystore::create(path, password)
+        }
+    }
+
+    pub fn keys(&self) -> &[WalletKey] {
+        &self.keys
+    }
+
+    /// The key with this name or address
+    pub fn find(&self, name_or_address: &str) -> Result<&WalletKey, WalletError> {
+        self.keys
+            .iter()
+            .find(|key| key.name == name_or_address || key.address() == name_or_address)
+            .ok_or_else(|| WalletError::UnknownKey(name_or_address.to_string()))
+    }
+
+    /// Generate a new keypair named `name`, store it and return it
+    pub fn generate(&mut self, name: &str) -> Result<&WalletKey, WalletError> {
+        self.add(name, generate_keypair())
+    }
+
+    /// Store an existing key under `name`
+    pub fn add(&mut self, name: &str, signing_key: SigningKey) -> Result<&WalletKey, WalletError> {
+        if self.keys.iter().any(|key| key.name == name) {
+            return Err(WalletError::DuplicateName(name.to_string()));
+        }
+        let key = WalletKey {
+            name: name.to_string(),
+            signing_key,
+        };
+
+        let mut nonce = [0u8; NONCE_LEN];
+        OsRng.fill_bytes(&mut nonce);
+        let ciphertext = self
+            .cipher
+            .encrypt(
+                Nonce::from_slice(&nonce),
+                Payload {
+                    msg: key.signing_key.as_bytes(),
+                    aad: associated_data(&key.name, &key.address()).as_bytes(),
+                },
+            )
+            .expect("encrypting 32 bytes cannot fail");
+        self.file.keys.push(EncryptedKey {
+            name: key.name.clone(),
+            address: key.address(),
+            nonce: hex::encode(nonce),
+            ciphertext: hex::encode(ciphertext),
+        });
+        self.keys.push(key);
+        self.save()?;
+        Ok(self.keys.last().unwrap())
+    }
+
+    /// Write the keystore to a temporary file first, so a crash never leaves half a keystore
+    fn save(&self) -> Result<(), WalletError> {
+        let json = serde_json::to_vec_pretty(&self.file)
+            .map_err(|e| WalletError::BadKeystore(e.to_string()))?;
+        let temporary = self.path.with_extension("tmp");
+        fs::write(&temporary, json)?;
+        fs::rename(&temporary, &self.path)?;
+        Ok(())
+    }
+}
+
+fn derive_cipher(
+    password: &str,
+    salt: &[u8],
+    kdf: KdfParams,
+) -> Result<ChaCha20Poly1305, WalletError> {
+    let params = Params::new(kdf.memory_kib, kdf.iterations, 1, Some(32))
+        .map_err(|e| WalletError::BadKeystore(e.to_string()))?;
+    let mut key = [0u8; 32];
+    Argon2::new(Algorithm::Argon2id, Version::V0x13, params)
+        .hash_password_into(password.as_bytes(), salt, &mut key)
+        .map_err(|e| WalletError::BadKeystore(e.to_string()))?;
+    Ok(ChaCha20Poly1305::new(Key::from_slice(&key)))
+}
+
+fn associated_data(name: &str, address: &str) -> String {
+    format!("{}:{}", name, address)
+}
+
+fn decode_hex(value: &str) -> Result<Vec<u8>, WalletError> {
+    hex::decode(value).map_err(|e| WalletError::BadKeystore(e.to_string()))
+}
+
+/// What a transaction did to an account
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EntryKind {
+    /// Block reward and fees paid to the account as miner
+    Mined,
+    Received,
+    /// Sent by the account, which also paid the fee
+    Sent,
+}
+
+/// One transaction of an account's history
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct HistoryEntry {
+    pub block_id: u64,
+    pub kind: EntryKind,
+    pub transaction: Transaction,
+}
+
+impl HistoryEntry {
+    /// Entries that `transaction`, in block `block_id`, makes in the history of `address`: one
+    /// for each side of the transaction the address is on
+    pub fn of(address: &str, block_id: u64, transaction: &Transaction) -> Vec<HistoryEntry> {
+        let mut entries = vec![];
+        let mut record = |kind| {
+            entries.push(HistoryEntry {
+                block_id,
+                kind,
+                transaction: transaction.clone(),
+            })
+        };
+        if transaction.sender == address {
+            record(EntryKind::Sent);
+        }
+        if transaction.recipient == address {
+            record(if transaction.is_coinbase() {
+                EntryKind::Mined
+            } else {
+                EntryKind::Received
+            });
+        }
+        entries
+    }
+}
+
+/// Balance, next nonce and history of an address, found by replaying the chain
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Account {
+    pub address: String,
+    pub balance: u64,
+    pub next_nonce: u64,
+    pub history: Vec<HistoryEntry>,
+}
+
+impl Account {
+    /// Replay `blocks`, a valid chain starting at the genesis block, for `address`
+    pub fn replay(address: &str, blocks: &[Block]) -> Self {
+        let mut account = Account {
+            address: address.to_string(),
+            balance: 0,
+            next_nonce: 0,
+            history: vec![],
+        };
+
+        for block in blocks {
+            for transaction in &block.transactions {
+                let entries = HistoryEntry::of(address, block.id, transaction);
+                account.history.extend(entries);
+            }
+        }
+
+        for entry in &account.history {
+            let transaction = &entry.transaction;
+            match entry.kind {
+                EntryKind::Mined | EntryKind::Received => {
+                    account.balance = account.balance.saturating_add(transaction.amount)
+                }
+                EntryKind::Sent => {
+                    let cost = transaction.amount.saturating_add(transaction.fee);
+                    account.balance = account.balance.saturating_sub(cost);
+                    account.next_nonce = transaction.nonce + 1;
+                }
+            }
+        }
+        account
+    }
+}
+
+/// Balance and next nonce of an address, as the node's ledger has them
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+pub struct Balance {
+    pub balance: u64,
+    pub next_nonce: u64,
+}
+
+/// Client of a node's HTTP API (see `api::start`)
+pub struct NodeClient {
+    api: String,
+}
+
+impl NodeClient {
+    /// `api` is the address the node's API listens on, e.g. `127.0.0.1:8080`
+    pub fn new(api: &str) -> Self {
+        Self {
+            api: api.to_string(),
+        }
+    }
+
+    /// Balance and next nonce of `address`, from the node's ledger in one request
+    pub fn account(&self, address: &str) -> Result<Balance, WalletError> {
+        let (status, body) = self.request("GET", &format!("/accounts/{}", address), "")?;
+        if status != 200 {
+            return Err(error_of(&body));
+        }
+        from_value(body)
+    }
+
+    /// The best chain of the node, fetched block by block back from the tip. A pruned node
+    /// only has the blocks from its snapshot on, so they may not start at the genesis block.
+    pub fn blocks(&self) -> Result<Vec<Block>, WalletError> {
+        let (status, body) = self.request("GET", "/tip", "")?;
+        match status {
+            200 => {}
+            404 => return Ok(vec![]),
+            _ => return Err(error_of(&body)),
+        }
+        let tip: Block = from_value(body)?;
+        let mut blocks = vec![];
+        for height in (1..tip.id).rev() {
+            let (status, body) = self.request("GET", &format!("/blocks/height/{}", height), "")?;
+            match status {
+                200 => blocks.push(from_value(body)?),
+                404 => break,
+                _ => return Err(error_of(&body)),
+            }
+        }
+        blocks.reverse();
+        blocks.push(tip);
+        Ok(blocks)
+    }
+
+    /// Submit a signed transaction. Returns its id.
+    pub fn submit(&self, transaction: &Transaction) -> Result<Hash, WalletError> {
+        let body =
+            serde_json::to_string(transaction).map_err(|e| WalletError::Node(e.to_string()))?;
+        let (status, body) = self.request("POST", "/transactions", &body)?;
+        if status != 200 {
+            return Err(error_of(&body));
+        }
+        body["id"]
+            .as_str()
+            .and_then(hash_from_hex)
+            .ok_or_else(|| WalletError::Node(format!("No transaction id in {}", body)))
+    }
+
+    fn request(&self, method: &str, path: &str, body: &str) -> Result<(u16, Value), WalletError> {
+        let mut stream = TcpStream::connect(&self.api)?;
+        stream.set_read_timeout(Some(NODE_TIMEOUT))?;
+        write!(
+            stream,
+            "{} {} HTTP/1.1\r\nHost: {}\r\nContent-Type: application/json\r\nContent-Length: {}\r\n\r\n{}",
+            method,
+            path,
+            self.api,
+            body.len(),
+            body
+        )?;
+        // The API closes the connection after each response
+        let mut response = String::new();
+        stream.read_to_string(&mut response)?;
+
+        let bad_response = || WalletError::Node("Malformed HTTP response".to_string());
+        let (head, body) = response.split_once("\r\n\r\n").ok_or_else(bad_response)?;
+        let status = head
+            .split_whitespace()
+            .nth(1)
+            .and_then(|status| status.parse().ok())
+            .ok_or_else(bad_response)?;
+        Ok((
+            status,
+            serde_json::from_str(body).map_err(|_| bad_response())?,
+        ))
+    }
+}
+
+fn from_value<T: for<'de> Deserialize<'de>>(value: Value) -> Result<T, WalletError> {
+    serde_json::from_value(value).map_err(|e| WalletError::Node(e.to_string()))
+}
+
+fn error_of(body: &Value) -> WalletError {
+    WalletError::Node(
+        body["error"]
+            .as_str()
+            .unwrap_or("unknown error")
+            .to_string(),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::hash::ZERO_HASH;
+
+    /// Cheap key derivation, the default takes a noticeable time in debug builds
+    const TEST_KDF: KdfParams = KdfParams {
+        memory_kib: 64,
+        iterations: 1,
+    };
+
+    fn keystore_path(name: &str) -> PathBuf {
+        let path = std::env::temp_dir().join(format!(
+            "wallet-{}-{}-{}.json",
+            name,
+            std::process::id(),
+            rand::random::<u32>()
+        ));
+        let _ = fs::remove_file(&path);
+        path
+    }
+
+    #[test]
+    fn keys_survive_reopening() {
+        let path = keystore_path("reopen");
+        let mut keystore = Keystore::create_with_kdf(&path, "secret", TEST_KDF).unwrap();
+        let alice = keystore.generate("alice").unwrap().address();
+        keystore.generate("bob").unwrap();
+
+        let keystore = Keystore::open(&path, "secret").unwrap();
+        assert_eq!(keystore.keys().len(), 2);
+        assert_eq!(keystore.find("alice").unwrap().address(), alice);
+        assert_eq!(keystore.find(&alice).unwrap().name, "alice");
+        // The secret key is not written in clear text
+        let file = fs::read_to_string(&path).unwrap();
+        let secret = hex::encode(keystore.find("alice").unwrap().signing_key.as_bytes());
+        assert!(!file.contains(&secret));
+        fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn wrong_password_is_rejected() {
+        let path = keystore_path("password");
+        let mut keystore = Keystore::create_with_kdf(&path, "secret", TEST_KDF).unwrap();
+        keystore.generate("alice").unwrap();
+        assert!(matches!(
+            Keystore::open(&path, "guess"),
+            Err(WalletError::WrongPassword)
+        ));
+        assert!(matches!(
+            keystore.generate("alice"),
+            Err(WalletError::DuplicateName(_))
+        ));
+        assert!(matches!(
+            Keystore::create_with_kdf(&path, "secret", TEST_KDF),
+            Err(WalletError::KeystoreExists)
+        ));
+        fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn account_is_replayed_from_the_chain() {
+        let alice = generate_keypair();
+        let alice_address = address(&alice.verifying_key());
+        let bob = address(&generate_keypair().verifying_key());
+        let blocks = vec![
+            Block::new(1, ZERO_HASH, vec![], 1),
+            Block::new(
+                2,
+                ZERO_HASH,
+                vec![Transaction::coinbase(alice_address.clone(), 50, 2)],
+                1,
+            ),
+            Block::new(
+                3,
+                ZERO_HASH,
+                vec![
+                    Transaction::coinbase(bob.clone(), 52, 3),
+                    Transaction::with_fee(&alice, bob.clone(), 10, 2, 0),
+                    Transaction::new(&alice, bob, 5, 1),
+                ],
+                1,
+            ),
+        ];
+
+        let account = Account::replay(&alice_address, &blocks);
+        assert_eq!(account.balance, 50 - 12 - 5);
+        assert_eq!(account.next_nonce, 2);
+        let kinds = account
+            .history
+            .iter()
+            .map(|entry| entry.kind)
+            .collect::<Vec<EntryKind>>();
+        assert_eq!(kinds, [EntryKind::Mined, EntryKind::Sent, EntryKind::Sent]);
+    }
+}
@@ -0,0 +1,232 @@
+use std::io::{Read, Write};
+use std::net::{SocketAddr, TcpStream};
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+
+use blockchain::merkle::MerkleProof;
+use blockchain::node::{self, Message};
+use blockchain::{
+    address, api, generate_keypair, BlockChain, BlockHeader, ChainConfig, Node, Transaction,
+};
+use ed25519_dalek::SigningKey;
+use serde_json::{json, Value};
+
+/// A node with a genesis block and one mined block, its API address and the key of the miner
+fn start_node() -> (Arc<Node>, SocketAddr, SigningKey) {
+    let mut chain = BlockChain::with_config(ChainConfig {
+        initial_difficulty: 1,
+        ..ChainConfig::default()
+    });
+    chain.generate_genesis_block().unwrap();
+    let node = Node::start("127.0.0.1:0", chain).unwrap();
+    let miner = generate_keypair();
+    node.mine_block(&address(&miner.verifying_key())).unwrap();
+    let api_address = api::start("127.0.0.1:0", Arc::clone(&node)).unwrap();
+    (node, api_address, miner)
+}
+
+/// Send one HTTP request and return the status code and the JSON body
+fn http(api: SocketAddr, method: &str, path: &str, body: &str) -> (u16, Value) {
+    let mut stream = TcpStream::connect(api).unwrap();
+    write!(
+        stream,
+        "{} {} HTTP/1.1\r\nHost: localhost\r\nContent-Length: {}\r\n\r\n{}",
+        method,
+        path,
+        body.len(),
+        body
+    )
+    .unwrap();
+    let mut response = String::new();
+    stream.read_to_string(&mut response).unwrap();
+
+    let (head, body) = response.split_once("\r\n\r\n").unwrap();
+    let status = head.split_whitespace().nth(1).unwrap().parse().unwrap();
+    (status, serde_json::from_str(body).unwrap())
+}
+
+#[test]
+fn blocks_can_be_queried_by_height_and_hash() {
+    let (node, api, _) = start_node();
+    let tip = node.tip().unwrap();
+
+    let (status, body) = http(api, "GET", "/tip", "");
+    assert_eq!(status, 200);
+    assert_eq!(body["hash"], hex::encode(tip.hash));
+
+    let (status, body) = http(api, "GET", "/blocks/height/1", "");
+    assert_eq!(status, 200);
+    assert_eq!(body["hash"], hex::encode(tip.previous_hash));
+
+    let path = format!("/blocks/hash/{}", hex::encode(tip.hash));
+    let (status, body) = http(api, "GET", &path, "");
+    assert_eq!(status, 200);
+    assert_eq!(body["id"], 2);
+
+    assert_eq!(http(api, "GET", "/blocks/height/3", "").0, 404);
+    assert_eq!(http(api, "GET", "/blocks/hash/not-hex", "").0, 400);
+    assert_eq!(http(api, "GET", "/nowhere", "").0, 404);
+}
+
+#[test]
+fn status_reports_a_valid_chain() {
+    let (_node, api, _) = start_node();
+    let (status, body) = http(api, "GET", "/status", "");
+    assert_eq!(status, 200);
+    assert_eq!(body["height"], 2);
+    // The validation done when the node started, before the second block was mined
+    assert_eq!(body["validation"]["valid"], true);
+    assert_eq!(body["validation"]["height"], 1);
+
+    let (status, body) = http(api, "POST", "/validate", "");
+    assert_eq!(status, 200);
+    assert_eq!(body, serde_json::json!({ "valid": true, "height": 2 }));
+    let (_, body) = http(api, "GET", "/status", "");
+    assert_eq!(body["validation"]["height"], 2);
+}
+
+#[test]
+fn submitted_transactions_wait_to_be_mined() {
+    let (node, api, miner) = start_node();
+    let recipient = address(&generate_keypair().verifying_key());
+    let transaction = Transaction::with_fee(&miner, recipient.clone(), 5, 1, 0);
+
+    let body = serde_json::to_string(&transaction).unwrap();
+    let (status, response) = http(api, "POST", "/transactions", &body);
+    assert_eq!(status, 200);
+    assert_eq!(response["id"], hex::encode(transaction.hash()));
+    assert_eq!(node.pending_transactions(), vec![transaction]);
+
+    // The same transaction again is refused, and so is one the sender cannot pay for
+    assert_eq!(http(api, "POST", "/transactions", &body).0, 400);
+    let overdraft = Transaction::new(&miner, recipient, 1_000, 1);
+    let body = serde_json::to_string(&overdraft).unwrap();
+    let (status, response) = http(api, "POST", "/transactions", &body);
+    assert_eq!(status, 400);
+    assert!(response["error"].as_str().unwrap().contains("only has"));
+}
+
+#[test]
+fn json_rpc_answers_queries_and_reports_errors() {
+    let (node, api, _) = start_node();
+
+    let request = json!({
+        "jsonrpc": "2.0",
+        "method": "get_block_by_height",
+        "params": { "height": 2 },
+        "id": 7,
+    });
+    let (status, body) = http(api, "POST", "/rpc", &request.to_string());
+    assert_eq!(status, 200);
+    assert_eq!(body["id"], 7);
+    assert_eq!(
+        body["result"]["hash"],
+        hex::encode(node.tip().unwrap().hash)
+    );
+
+    let request = json!({ "jsonrpc": "2.0", "method": "mine_everything", "id": 8 });
+    let (_, body) = http(api, "POST", "/rpc", &request.to_string());
+    assert_eq!(body["error"]["code"], -32601);
+
+    let request = json!({ "jsonrpc": "2.0", "method": "get_block_by_hash", "id": 9 });
+    let (_, body) = http(api, "POST", "/rpc", &request.to_string());
+    assert_eq!(body["error"]["code"], -32602);
+}
+
+#[test]
+fn light_client_checks_a_payment_against_the_header() {
+    let (node, api, miner) = start_node();
+    let recipient = address(&generate_keypair().verifying_key());
+    let payment = Transaction::with_fee(&miner, recipient, 5, 1, 0);
+    node.submit_transaction(payment.clone()).unwrap();
+    let block = node.mine_block(&address(&miner.verifying_key())).unwrap();
+
+    let path = format!(
+        "/blocks/hash/{}/proofs/{}",
+        hex::encode(block.hash),
+        hex::encode(payment.hash())
+    );
+    let (status, body) = http(api, "GET", &path, "");
+    assert_eq!(status, 200);
+    let header: BlockHeader = serde_json::from_value(body["header"].clone()).unwrap();
+    let proof: MerkleProof = serde_json::from_value(body["proof"].clone()).unwrap();
+    assert_eq!(header.hash(), block.hash);
+    assert!(header.has_valid_work());
+    assert!(header.contains_transaction(&payment.hash(), &proof));
+
+    let unknown = format!(
+        "/blocks/hash/{}/proofs/{}",
+        hex::encode(block.hash),
+        "00".repeat(32)
+    );
+    assert_eq!(http(api, "GET", &unknown, "").0, 404);
+}
+
+#[test]
+fn explorer_pages_are_served_as_html() {
+    let (node, api, _) = start_node();
+    let tip = node.tip().unwrap();
+
+    let mut stream = TcpStream::connect(api).unwrap();
+    write!(stream, "GET /explorer HTTP/1.1\r\nHost: localhost\r\n\r\n").unwrap();
+    let mut response = String::new();
+    stream.read_to_string(&mut response).unwrap();
+
+    let (head, body) = response.split_once("\r\n\r\n").unwrap();
+    assert!(head.starts_with("HTTP/1.1 200 OK"));
+    assert!(head.contains("Content-Type: text/html; charset=utf-8"));
+    assert!(body.contains(&format!(
+        "<a href=\"/explorer/blocks/{0}\">{0}</a>",
+        hex::encode(tip.hash)
+    )));
+    assert!(body.contains("<form action=\"/explorer/search\""));
+}
+
+#[test]
+fn metrics_count_rejected_blocks_by_reason() {
+    let (node, api, _) = start_node();
+    let mut forged = node.tip().unwrap();
+    forged.hash = [0; 32];
+    node::send(node.address(), &Message::NewBlock(forged)).unwrap();
+
+    let rejected = "blockchain_blocks_rejected_total{reason=\"hash_mismatch\"} 1\n";
+    let mut response = String::new();
+    for _ in 0..50 {
+        response.clear();
+        let mut stream = TcpStream::connect(api).unwrap();
+        write!(stream, "GET /metrics HTTP/1.1\r\nHost: localhost\r\n\r\n").unwrap();
+        stream.read_to_string(&mut response).unwrap();
+        if response.contains(rejected) {
+            break;
+        }
+        thread::sleep(Duration::from_millis(20));
+    }
+
+    let (head, body) = response.split_once("\r\n\r\n").unwrap();
+    assert!(head.contains("Content-Type: text/plain; version=0.0.4"));
+    assert!(body.contains(rejected));
+    assert!(body.contains("blockchain_height 2\n"));
+    assert!(body.contains("blockchain_blocks_mined_total 1\n"));
+    assert!(body.contains("blockchain_peers 0\n"));
+    assert!(body.contains("blockchain_mempool_transactions 0\n"));
+    assert!(body.contains("# TYPE blockchain_hash_rate gauge\n"));
+}
+
+#[test]
+fn oversized_request_heads_are_refused() {
+    let (_node, api, _) = start_node();
+    let long_line = format!(
+        "GET /tip HTTP/1.1\r\nX-Padding: {}",
+        "a".repeat(8 * 1024 - 11)
+    );
+    let many_headers = format!("GET /tip HTTP/1.1\r\n{}", "X-Padding: a\r\n".repeat(65));
+
+    for head in [long_line, many_headers] {
+        let mut stream = TcpStream::connect(api).unwrap();
+        stream.write_all(head.as_bytes()).unwrap();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).unwrap();
+        assert!(response.starts_with("HTTP/1.1 431 Request Header Fields Too Large"));
+    }
+}
@@ -0,0 +1,113 @@
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use blockchain::{address, generate_keypair, BlockChain, ChainConfig, Hash, Node, Transaction};
+
+fn test_config() -> ChainConfig {
+    ChainConfig {
+        initial_difficulty: 1,
+        ..ChainConfig::default()
+    }
+}
+
+/// Start `count` unconnected nodes on localhost that all share the same genesis block
+fn start_nodes(count: usize) -> Vec<Arc<Node>> {
+    let mut genesis_chain = BlockChain::with_config(test_config());
+    genesis_chain.generate_genesis_block().unwrap();
+
+    (0..count)
+        .map(|_| {
+            let mut chain = BlockChain::with_config(test_config());
+            chain.replace_chain(genesis_chain.blocks.clone()).unwrap();
+            Node::start("127.0.0.1:0", chain).unwrap()
+        })
+        .collect()
+}
+
+fn connect_all(nodes: &[Arc<Node>]) {
+    for node in nodes {
+        for peer in nodes {
+            node.add_peer(peer.address());
+        }
+    }
+}
+
+fn tip_hash(node: &Node) -> Hash {
+    node.tip().unwrap().hash
+}
+
+/// Wait until every node reports the same tip as `expected`
+fn wait_for_tip(nodes: &[Arc<Node>], expected: &Hash) {
+    let deadline = Instant::now() + Duration::from_secs(20);
+    while nodes.iter().any(|node| tip_hash(node) != *expected) {
+        assert!(
+            Instant::now() < deadline,
+            "Nodes did not converge on {}",
+            hex::encode(expected)
+        );
+        thread::sleep(Duration::from_millis(50));
+    }
+}
+
+#[test]
+fn nodes_converge_on_the_longest_chain() {
+    let nodes = start_nodes(3);
+    let miner = address(&generate_keypair().verifying_key());
+
+    // While disconnected, node 0 mines three blocks and node 1 mines a competing block
+    for _ in 0..3 {
+        nodes[0].mine_block(&miner).unwrap();
+    }
+    nodes[1].mine_block(&miner).unwrap();
+    assert_ne!(tip_hash(&nodes[0]), tip_hash(&nodes[1]));
+
+    // Once connected, syncing brings everyone onto node 0's longer chain
+    connect_all(&nodes);
+    for node in &nodes {
+        node.sync();
+    }
+    let longest = tip_hash(&nodes[0]);
+    wait_for_tip(&nodes, &longest);
+
+    // New blocks are gossiped to every peer
+    let block = nodes[2].mine_block(&miner).unwrap();
+    wait_for_tip(&nodes, &block.hash);
+    for node in &nodes {
+        assert_eq!(node.with_chain(|chain| chain.blocks.len()), 5);
+    }
+}
+
+#[test]
+fn pending_transactions_are_gossiped_and_mined() {
+    let nodes = start_nodes(2);
+    connect_all(&nodes);
+
+    let alice = generate_keypair();
+    let alice_address = address(&alice.verifying_key());
+    let bob_address = address(&generate_keypair().verifying_key());
+
+    let funding_block = nodes[0].mine_block(&alice_address).unwrap();
+    wait_for_tip(&nodes, &funding_block.hash);
+
+    let transfer = Transaction::new(&alice, bob_address.clone(), 20, 0);
+    nodes[0].submit_transaction(transfer.clone()).unwrap();
+
+    // Node 1 hears about the transaction and mines it
+    let deadline = Instant::now() + Duration::from_secs(20);
+    while !nodes[1].pending_transactions().contains(&transfer) {
+        assert!(Instant::now() < deadline, "Transaction was not gossiped");
+        thread::sleep(Duration::from_millis(50));
+    }
+    let block = nodes[1].mine_block(&bob_address).unwrap();
+    assert!(block.transactions.contains(&transfer));
+
+    wait_for_tip(&nodes, &block.hash);
+    for node in &nodes {
+        assert_eq!(
+            node.with_chain(|chain| chain.ledger().balance(&bob_address)),
+            70
+        );
+        assert!(node.pending_transactions().is_empty());
+    }
+}
@@ -0,0 +1,32 @@
+use blockchain::simulation::{Simulation, SimulationConfig};
+
+#[test]
+fn simulated_networks_converge_after_partitions_for_many_seeds() {
+    for seed in 0..10 {
+        let mut simulation = Simulation::new(SimulationConfig {
+            nodes: 5,
+            seed,
+            max_delay: 3,
+            drop_rate: 0.1,
+            ..SimulationConfig::default()
+        });
+        simulation.run(60);
+
+        // Both sides keep mining while split
+        simulation.partition(&[&[0, 1], &[2, 3, 4]]);
+        simulation.run(120);
+
+        // Mining goes on: branches with the same work only resolve with the next block
+        simulation.heal();
+        assert!(
+            simulation.run_until_converged(300),
+            "Seed {} did not converge: {:?}",
+            seed,
+            simulation.tips()
+        );
+        for node in 0..5 {
+            let chain = simulation.chain(node);
+            assert_eq!(chain.is_chain_valid(&chain.blocks), Ok(()));
+        }
+    }
+}
@@ -0,0 +1,87 @@
+use std::sync::Arc;
+
+use blockchain::wallet::{Account, Balance, EntryKind, NodeClient};
+use blockchain::{address, api, generate_keypair, BlockChain, ChainConfig, Node, Transaction};
+
+#[test]
+fn wallet_sends_a_transfer_and_replays_its_balance() {
+    let mut chain = BlockChain::with_config(ChainConfig {
+        initial_difficulty: 1,
+        ..ChainConfig::default()
+    });
+    chain.generate_genesis_block().unwrap();
+    let node = Node::start("127.0.0.1:0", chain).unwrap();
+    let api_address = api::start("127.0.0.1:0", Arc::clone(&node)).unwrap();
+    let client = NodeClient::new(&api_address.to_string());
+
+    let alice = generate_keypair();
+    let alice_address = address(&alice.verifying_key());
+    let bob = address(&generate_keypair().verifying_key());
+    node.mine_block(&alice_address).unwrap();
+
+    let account = Account::replay(&alice_address, &client.blocks().unwrap());
+    let reward = account.balance;
+    assert!(reward > 0);
+    assert_eq!(account.next_nonce, 0);
+
+    let transfer = Transaction::with_fee(&alice, bob.clone(), 5, 1, account.next_nonce);
+    assert_eq!(client.submit(&transfer).unwrap(), transfer.hash());
+    // The same nonce again is a conflict the node reports
+    let conflict = Transaction::with_fee(&alice, bob.clone(), 6, 1, 0);
+    assert!(client.submit(&conflict).is_err());
+    node.mine_block(&bob).unwrap();
+
+    let blocks = client.blocks().unwrap();
+    assert_eq!(blocks.len(), 3);
+    let account = Account::replay(&alice_address, &blocks);
+    assert_eq!(account.balance, reward - 6);
+    assert_eq!(account.next_nonce, 1);
+    assert_eq!(account.history.last().unwrap().kind, EntryKind::Sent);
+    assert_eq!(Account::replay(&bob, &blocks).history.len(), 2);
+}
+
+#[test]
+fn wallet_reads_the_account_of_a_pruned_node() {
+    let mut chain = BlockChain::with_config(ChainConfig {
+        initial_difficulty: 1,
+        retarget_interval: 0,
+        median_time_span: 3,
+        ..ChainConfig::default()
+    });
+    chain.generate_genesis_block().unwrap();
+    let alice = generate_keypair();
+    let alice_address = address(&alice.verifying_key());
+    let bob = address(&generate_keypair().verifying_key());
+    let mine = |chain: &mut BlockChain, transactions| {
+        let block = chain.mine_next_block(&alice_address, transactions).unwrap();
+        chain.try_add_block(block).unwrap();
+    };
+    mine(&mut chain, vec![]);
+    mine(&mut chain, vec![Transaction::new(&alice, bob.clone(), 5, 0)]);
+    for _ in 0..6 {
+        mine(&mut chain, vec![]);
+    }
+    chain.prune(7).unwrap();
+    let reward = chain.config.block_reward;
+
+    let node = Node::start("127.0.0.1:0", chain).unwrap();
+    let api_address = api::start("127.0.0.1:0", Arc::clone(&node)).unwrap();
+    let client = NodeClient::new(&api_address.to_string());
+
+    let blocks = client.blocks().unwrap();
+    assert!(blocks[0].id > 1);
+    assert_eq!(blocks.last().unwrap().id, 9);
+    let account = client.account(&alice_address).unwrap();
+    assert_eq!(
+        account,
+        Balance {
+            balance: 8 * reward - 5,
+            next_nonce: 1,
+        }
+    );
+
+    // The nonce from the node is the one the next transfer needs
+    let transfer = Transaction::new(&alice, bob, 5, account.next_nonce);
+    assert_eq!(client.submit(&transfer).unwrap(), transfer.hash());
+    assert!(client.account("not-an-address").is_err());
+}
@@ -0,0 +1,17 @@
+//----------------------------------------------
+//      Web Programming Basics
+//----------------------------------------------
+mod date;
+pub mod headers;
+pub mod request;
+pub mod response;
+pub mod router;
+pub mod static_files;
+pub mod thread_pool;
+
+pub use headers::Headers;
+pub use request::{Limits, Method, ParseError, Request, Version};
+pub use response::{Body, Response, Status};
+pub use router::{Params, Router};
+pub use static_files::StaticFiles;
+pub use thread_pool::ThreadPool;
@@ -2,43 +2,90 @@
 //      Web Programming Basics
 //----------------------------------------------
 
-use std::io::prelude::*;
-use std::io::BufReader;
+use std::io::{self, BufReader, Read, Write};
 use std::net::{TcpListener, TcpStream};
-use std::sync::{Arc, Mutex};
-use std::time::Duration;
-use std::{fs, thread};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use std::{env, fs, thread};
+
+use web_programming::{Limits, Request, Response, Router, StaticFiles, Status, ThreadPool};
+
+// Usage: web_programming [workers] [queue]
+const DEFAULT_WORKERS: usize = 4;
+/// Connections accepted while every worker is busy. More than that are answered with a 503.
+const DEFAULT_QUEUE: usize = 16;
+/// Directory of the stylesheets, images and other files served under /static/
+const STATIC_DIR: &str = "static";
+/// Longest a client may leave the connection idle while sending the request or reading the
+/// response, so a silent client cannot keep a worker forever
+const CLIENT_TIMEOUT: Duration = Duration::from_secs(5);
+/// Longest a whole connection may take, so a client sending or reading a byte at a time
+/// cannot keep a worker forever either
+const CONNECTION_TIMEOUT: Duration = Duration::from_secs(30);
 
 fn main() {
+    let mut args = env::args().skip(1);
+    let workers = args.next().map_or(DEFAULT_WORKERS, |n| {
+        n.parse().expect("workers must be a number")
+    });
+    let queue = args.next().map_or(DEFAULT_QUEUE, |n| {
+        n.parse().expect("queue must be a number")
+    });
+
     // Create a tcp listener which is ready to accept connections on port 8000 of localhost (127.0.0.1)
     let listener = TcpListener::bind("127.0.0.1:8000").unwrap();
 
-    let mut active_requests = Arc::new(Mutex::new(0));
-    for stream in listener.incoming() {
-        let active_requests = active_requests.clone();
-        let stream = stream.unwrap();
-
-        thread::spawn(move || {
-            {
-                let mut connection = active_requests.lock().unwrap();
-                *connection -= 1;
-                if *connection > 3 {
-                    thread::sleep(Duration::from_secs(2));
-                }
-            }
-            handle_connection(stream);
+    // New pages are added here, each with the handler that answers it
+    let files = StaticFiles::new(STATIC_DIR);
+    let router = Arc::new(
+        Router::new()
+            .get("/", |_, _| page(Status::Ok, "index.html"))
+            .get("/page1", |_, _| {
+                thread::sleep(Duration::from_secs(20));
+                page(Status::Ok, "page1.html")
+            })
+            .get("/page2", |_, _| page(Status::Ok, "page2.html"))
+            .get("/static/*path", move |request, params| {
+                files.serve(request, params.get("path").unwrap_or_default())
+            })
+            .not_found(|_, _| page(Status::NotFound, "404.html")),
+    );
 
-            {
-                let mut connection = active_requests.lock().unwrap();
-                *connection -= 1;
+    // A fixed number of threads serve the connections, instead of a new thread for each one
+    let pool = ThreadPool::new(workers, queue);
+    for stream in listener.incoming() {
+        // A failed accept, e.g. out of file descriptors, only loses that connection
+        let stream = match stream {
+            Ok(stream) => stream,
+            Err(e) => {
+                eprintln!("Failed to accept a connection: {}", e);
+                continue;
             }
-        });
+        };
+        // Keep a handle to answer on if the pool has no room for the connection
+        let Ok(mut overflow) = stream.try_clone() else {
+            continue;
+        };
+
+        let router = Arc::clone(&router);
+        if pool
+            .try_execute(move || handle_connection(stream, &router))
+            .is_err()
+        {
+            let response = Response::text(Status::ServiceUnavailable, "The server is busy.")
+                .header("Retry-After", "1");
+            // The listening thread answers this one itself, it must not wait on the client
+            let _ = overflow.set_write_timeout(Some(CLIENT_TIMEOUT));
+            let _ = response.write_to(&mut overflow, None);
+        }
     }
 }
 
-fn handle_connection(mut stream: TcpStream) {
+fn handle_connection(stream: TcpStream, router: &Router) {
+    let mut stream = Deadline::new(stream, CONNECTION_TIMEOUT);
+
     // Reading TcpStream data using BufReader
-    let buf_reader = BufReader::new(&mut stream);
+    let mut buf_reader = BufReader::new(&mut stream);
 
     // BufReader is std library implementation of buffered reader, which reads data from stream
 
@@ -60,25 +107,75 @@ fn handle_connection(mut stream: TcpStream) {
     // stream.write_all(response.as_bytes()).unwrap();
     // stream.flush().unwrap();
 
-    let request_line = buf_reader.lines().next();
-    let (status_line, file_name) = match request_line.unwrap().unwrap().as_str() {
-        "GET / HTTP/1.1" => (Some("HTTP/1.1 200 OK \r\n"), Some("index.html")),
-        "GET /page1 HTTP/1.1" => {
-            thread::sleep(Duration::from_secs(20));
-            (Some("HTTP/1.1 200 OK \r\n"), Some("page1.html"))
+    let request = match Request::read_from(&mut buf_reader, &Limits::default()) {
+        Ok(request) => request,
+        Err(e) => {
+            // Without a status the client is gone, there is nobody to answer
+            if let Some(status) = e.status() {
+                let _ = Response::text(status, e.to_string()).write_to(&mut stream, None);
+            }
+            return;
         }
-        "GET /page2 HTTP/1.1" => (Some("HTTP/1.1 200 OK \r\n"), Some("page2.html")),
-        _ => (Some("HTTP/1.1 404 NOT FOUND\r\n"), Some("404.html")),
     };
 
-    let contents = fs::read_to_string(file_name.unwrap()).unwrap();
-    let length = contents.len();
-    let response = format!(
-        "{}Contents-Length: {}\r\n\r\n{}",
-        status_line.unwrap(),
-        length,
-        contents
-    );
-    stream.write_all(response.as_bytes()).unwrap();
-    stream.flush().unwrap();
+    // A failed write means the client is gone, there is nobody left to tell
+    let _ = router
+        .dispatch(&request)
+        .write_to(&mut stream, Some(&request));
+}
+
+fn page(status: Status, file_name: &str) -> Response {
+    match fs::read_to_string(file_name) {
+        Ok(contents) => Response::html(status, contents),
+        Err(e) => Response::text(
+            Status::InternalServerError,
+            format!("Could not read {}: {}", file_name, e),
+        ),
+    }
+}
+
+/// A connection that fails every read and write once `deadline` has passed. Each read or
+/// write also fails after `CLIENT_TIMEOUT` without progress.
+struct Deadline {
+    stream: TcpStream,
+    deadline: Instant,
+}
+
+impl Deadline {
+    fn new(stream: TcpStream, timeout: Duration) -> Self {
+        Self {
+            stream,
+            deadline: Instant::now() + timeout,
+        }
+    }
+
+    /// Time the next read or write may take
+    fn timeout(&self) -> io::Result<Duration> {
+        let left = self.deadline.saturating_duration_since(Instant::now());
+        if left.is_zero() {
+            return Err(io::Error::new(
+                io::ErrorKind::TimedOut,
+                "The connection took too long",
+            ));
+        }
+        Ok(left.min(CLIENT_TIMEOUT))
+    }
+}
+
+impl Read for Deadline {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.stream.set_read_timeout(Some(self.timeout()?))?;
+        self.stream.read(buf)
+    }
+}
+
+impl Write for Deadline {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.stream.set_write_timeout(Some(self.timeout()?))?;
+        self.stream.write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.stream.flush()
+    }
 }
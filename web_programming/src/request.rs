@@ -0,0 +1,645 @@
+use std::fmt;
+use std::io::{self, BufRead, Read};
+
+use crate::headers::Headers;
+use crate::response::Status;
+
+/// Methods the server understands. Any other method is answered with 501 Not Implemented.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Method {
+    Get,
+    Head,
+    Post,
+    Put,
+    Delete,
+    Patch,
+    Options,
+}
+
+impl Method {
+    pub const ALL: [Method; 7] = [
+        Method::Get,
+        Method::Head,
+        Method::Post,
+        Method::Put,
+        Method::Delete,
+        Method::Patch,
+        Method::Options,
+    ];
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Method::Get => "GET",
+            Method::Head => "HEAD",
+            Method::Post => "POST",
+            Method::Put => "PUT",
+            Method::Delete => "DELETE",
+            Method::Patch => "PATCH",
+            Method::Options => "OPTIONS",
+        }
+    }
+
+    /// Method names are case-sensitive, `get` is not `GET`
+    pub fn parse(name: &str) -> Option<Self> {
+        Self::ALL.into_iter().find(|method| method.as_str() == name)
+    }
+}
+
+impl fmt::Display for Method {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Version {
+    Http10,
+    Http11,
+}
+
+impl Version {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Version::Http10 => "HTTP/1.0",
+            Version::Http11 => "HTTP/1.1",
+        }
+    }
+}
+
+impl fmt::Display for Version {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+/// How large a request may be. Anything larger is refused before it is read in full.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Limits {
+    /// Longest request line, in bytes
+    pub max_request_line: usize,
+    /// Most bytes of all header lines together, the trailer of a chunked body included
+    pub max_header_bytes: usize,
+    /// Most header fields
+    pub max_headers: usize,
+    /// Largest body, in bytes after removing the chunked encoding
+    pub max_body: usize,
+}
+
+impl Default for Limits {
+    fn default() -> Self {
+        Self {
+            max_request_line: 8 * 1024,
+            max_header_bytes: 16 * 1024,
+            max_headers: 100,
+            max_body: 1024 * 1024,
+        }
+    }
+}
+
+/// Longest line of a chunk size, with its extensions
+const MAX_CHUNK_LINE: usize = 1024;
+
+/// Empty lines accepted before a request line
+const MAX_EMPTY_LINES: usize = 2;
+
+/// Why a request could not be read
+#[derive(Debug)]
+pub enum ParseError {
+    /// The connection closed before a request started
+    Closed,
+    Io(io::Error),
+    /// The request does not follow the HTTP/1.1 syntax
+    Malformed(String),
+    RequestLineTooLong,
+    HeadersTooLarge,
+    BodyTooLarge,
+    UnsupportedMethod(String),
+    UnsupportedTransferEncoding(String),
+    UnsupportedVersion(String),
+}
+
+impl ParseError {
+    /// Status code to answer with, `None` if the connection is gone or broken. A read that
+    /// timed out is answered with 408.
+    pub fn status(&self) -> Option<Status> {
+        match self {
+            ParseError::Io(e)
+                if matches!(
+                    e.kind(),
+                    io::ErrorKind::TimedOut | io::ErrorKind::WouldBlock
+                ) =>
+            {
+                Some(Status::RequestTimeout)
+            }
+            ParseError::Closed | ParseError::Io(_) => None,
+            ParseError::Malformed(_) => Some(Status::BadRequest),
+            ParseError::BodyTooLarge => Some(Status::ContentTooLarge),
+            ParseError::RequestLineTooLong => Some(Status::UriTooLong),
+            ParseError::HeadersTooLarge => Some(Status::RequestHeaderFieldsTooLarge),
+            ParseError::UnsupportedMethod(_) | ParseError::UnsupportedTransferEncoding(_) => {
+                Some(Status::NotImplemented)
+            }
+            ParseError::UnsupportedVersion(_) => Some(Status::HttpVersionNotSupported),
+        }
+    }
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ParseError::Closed => write!(f, "The connection closed before a request was sent."),
+            ParseError::Io(e) => write!(f, "Could not read the request: {}", e),
+            ParseError::Malformed(reason) => write!(f, "Malformed request: {}.", reason),
+            ParseError::RequestLineTooLong => write!(f, "The request line is too long."),
+            ParseError::HeadersTooLarge => write!(f, "The request headers are too large."),
+            ParseError::BodyTooLarge => write!(f, "The request body is too large."),
+            ParseError::UnsupportedMethod(method) => {
+                write!(f, "Method {} is not supported.", method)
+            }
+            ParseError::UnsupportedTransferEncoding(encoding) => {
+                write!(f, "Transfer-Encoding {} is not supported.", encoding)
+            }
+            ParseError::UnsupportedVersion(version) => {
+                write!(f, "{} is not supported.", version)
+            }
+        }
+    }
+}
+
+impl std::error::Error for ParseError {}
+
+impl From<io::Error> for ParseError {
+    fn from(e: io::Error) -> Self {
+        match e.kind() {
+            io::ErrorKind::UnexpectedEof => malformed("the request ended early"),
+            _ => ParseError::Io(e),
+        }
+    }
+}
+
+fn malformed(reason: &str) -> ParseError {
+    ParseError::Malformed(reason.to_string())
+}
+
+/// An HTTP/1.x request, with its body read in full
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Request {
+    pub method: Method,
+    /// Request target as sent, e.g. `/search?q=rust%20book`
+    pub target: String,
+    /// Percent-decoded path of the target, e.g. `/search`
+    pub path: String,
+    /// Percent-decoded query parameters, in order, e.g. `[("q", "rust book")]`
+    pub query: Vec<(String, String)>,
+    pub version: Version,
+    pub headers: Headers,
+    /// The body, after removing the chunked encoding if it was chunked
+    pub body: Vec<u8>,
+}
+
+impl Request {
+    /// Path of the target as sent, still percent-encoded, e.g. `/rust%20book` where `path`
+    /// is `/rust book`
+    pub fn raw_path(&self) -> &str {
+        let origin = origin_form(&self.target);
+        origin.split_once('?').map_or(origin, |(path, _)| path)
+    }
+
+    /// Read one request from `reader`. The body is read as `Content-Length` or the chunked
+    /// `Transfer-Encoding` says, and nothing is read past its end.
+    pub fn read_from<R: BufRead>(reader: &mut R, limits: &Limits) -> Result<Self, ParseError> {
+        // A few empty lines before the request line are ignored, e.g. a CRLF left after a body
+        let mut empty_lines = 0;
+        let request_line = loop {
+            match read_line(
+                reader,
+                limits.max_request_line,
+                ParseError::RequestLineTooLong,
+            )? {
+                None => return Err(ParseError::Closed),
+                Some(line) if line.is_empty() && empty_lines < MAX_EMPTY_LINES => empty_lines += 1,
+                Some(line) if line.is_empty() => {
+                    return Err(malformed("too many empty lines before the request line"))
+                }
+                Some(line) => break line,
+            }
+        };
+        let (method, target, version) = parse_request_line(&request_line)?;
+        let (path, query) = parse_target(method, target)?;
+
+        let mut header_budget = limits.max_header_bytes;
+        let headers = read_fields(reader, &mut header_budget, limits.max_headers)?;
+        if version == Version::Http11 && headers.get_all("host").count() != 1 {
+            return Err(malformed(
+                "an HTTP/1.1 request needs exactly one Host header",
+            ));
+        }
+
+        let body = read_body(reader, &headers, &mut header_budget, limits)?;
+        Ok(Self {
+            method,
+            target: target.to_string(),
+            path,
+            query,
+            version,
+            headers,
+            body,
+        })
+    }
+
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers.get(name)
+    }
+
+    /// Value of the first query parameter called `name`
+    pub fn query_param(&self, name: &str) -> Option<&str> {
+        self.query
+            .iter()
+            .find(|(key, _)| key == name)
+            .map(|(_, value)| value.as_str())
+    }
+}
+
+/// Read a line of at most `max` bytes ending in CRLF, or a bare LF, and return it without the
+/// line ending. Returns `None` at the end of the input.
+fn read_line<R: BufRead>(
+    reader: &mut R,
+    max: usize,
+    too_long: ParseError,
+) -> Result<Option<String>, ParseError> {
+    let mut line = Vec::new();
+    let limit = max as u64 + 2;
+    if reader.by_ref().take(limit).read_until(b'\n', &mut line)? == 0 {
+        return Ok(None);
+    }
+    if line.pop() != Some(b'\n') {
+        return Err(if line.len() as u64 + 1 >= limit {
+            too_long
+        } else {
+            malformed("the request ended in the middle of a line")
+        });
+    }
+    if line.last() == Some(&b'\r') {
+        line.pop();
+    }
+    if line.len() > max {
+        return Err(too_long);
+    }
+    String::from_utf8(line)
+        .map(Some)
+        .map_err(|_| malformed("a line is not valid UTF-8"))
+}
+
+fn parse_request_line(line: &str) -> Result<(Method, &str, Version), ParseError> {
+    let [method, target, version] = line.split(' ').collect::<Vec<&str>>()[..] else {
+        return Err(malformed(
+            "the request line must be a method, a target and a version separated by single spaces",
+        ));
+    };
+
+    let version = match version {
+        "HTTP/1.1" => Version::Http11,
+        "HTTP/1.0" => Version::Http10,
+        _ if is_version(version) => {
+            return Err(ParseError::UnsupportedVersion(version.to_string()))
+        }
+        _ => return Err(malformed("the request line has no valid HTTP version")),
+    };
+    let method = match Method::parse(method) {
+        Some(method) => method,
+        None if is_token(method) => return Err(ParseError::UnsupportedMethod(method.to_string())),
+        None => return Err(malformed("the method is not a token")),
+    };
+    Ok((method, target, version))
+}
+
+/// `HTTP/<digit>.<digit>`
+fn is_version(version: &str) -> bool {
+    matches!(
+        version.strip_prefix("HTTP/").map(str::as_bytes),
+        Some([major, b'.', minor]) if major.is_ascii_digit() && minor.is_ascii_digit()
+    )
+}
+
+/// Method and header names are tokens: visible ASCII without separators
+fn is_token(name: &str) -> bool {
+    !name.is_empty()
+        && name
+            .bytes()
+            .all(|b| b.is_ascii_alphanumeric() || b"!#$%&'*+-.^_`|~".contains(&b))
+}
+
+/// Split the target into its decoded path and query parameters. Absolute targets
+/// (`http://host/path`), as sent to proxies, are reduced to their path and query.
+fn parse_target(
+    method: Method,
+    target: &str,
+) -> Result<(String, Vec<(String, String)>), ParseError> {
+    if target == "*" && method == Method::Options {
+        return Ok(("*".to_string(), vec![]));
+    }
+    let origin = origin_form(target);
+    if !origin.starts_with('/') || origin.bytes().any(|b| b.is_ascii_control()) {
+        return Err(malformed("the request target is not a path"));
+    }
+
+    let (path, query) = origin.split_once('?').unwrap_or((origin, ""));
+    let path =
+        percent_decode(path, false).ok_or_else(|| malformed("the path is not validly encoded"))?;
+    let query = query
+        .split('&')
+        .filter(|pair| !pair.is_empty())
+        .map(|pair| {
+            let (key, value) = pair.split_once('=').unwrap_or((pair, ""));
+            Some((percent_decode(key, true)?, percent_decode(value, true)?))
+        })
+        .collect::<Option<Vec<(String, String)>>>()
+        .ok_or_else(|| malformed("the query is not validly encoded"))?;
+    Ok((path, query))
+}
+
+/// `target` without the scheme and host of an absolute target, e.g. `/a?b` for
+/// `http://host/a?b`
+fn origin_form(target: &str) -> &str {
+    match target.split_once("://") {
+        Some((scheme, rest))
+            if scheme.eq_ignore_ascii_case("http") || scheme.eq_ignore_ascii_case("https") =>
+        {
+            rest.find('/').map_or("/", |start| &rest[start..])
+        }
+        _ => target,
+    }
+}
+
+/// Decode `%XX` escapes, and `+` as a space in query strings. `None` if an escape is invalid
+/// or the result is not UTF-8.
+pub(crate) fn percent_decode(text: &str, plus_as_space: bool) -> Option<String> {
+    let mut bytes = Vec::with_capacity(text.len());
+    let mut input = text.bytes();
+    while let Some(b) = input.next() {
+        match b {
+            b'%' => {
+                let high = (input.next()? as char).to_digit(16)?;
+                let low = (input.next()? as char).to_digit(16)?;
+                bytes.push((high * 16 + low) as u8);
+            }
+            b'+' if plus_as_space => bytes.push(b' '),
+            _ => bytes.push(b),
+        }
+    }
+    String::from_utf8(bytes).ok()
+}
+
+/// Read header fields up to the empty line that ends them. Every line, the empty one
+/// included, is taken from `budget`.
+fn read_fields<R: BufRead>(
+    reader: &mut R,
+    budget: &mut usize,
+    max_fields: usize,
+) -> Result<Headers, ParseError> {
+    let mut headers = Headers::new();
+    loop {
+        let Some(line) = read_line(reader, *budget, ParseError::HeadersTooLarge)? else {
+            return Err(malformed("the request ended before the end of the headers"));
+        };
+        *budget = budget.saturating_sub(line.len() + 2);
+        if line.is_empty() {
+            return Ok(headers);
+        }
+        if line.starts_with([' ', '\t']) {
+            return Err(malformed("header lines must not be folded"));
+        }
+        let Some((name, value)) = line.split_once(':') else {
+            return Err(malformed("a header line has no colon"));
+        };
+        if !is_token(name) {
+            return Err(malformed("a header name is not a token"));
+        }
+        if headers.len() == max_fields {
+            return Err(ParseError::HeadersTooLarge);
+        }
+        headers.append(name, value.trim_matches([' ', '\t']));
+    }
+}
+
+fn read_body<R: BufRead>(
+    reader: &mut R,
+    headers: &Headers,
+    header_budget: &mut usize,
+    limits: &Limits,
+) -> Result<Vec<u8>, ParseError> {
+    let encodings = headers
+        .get_all("transfer-encoding")
+        .flat_map(|value| value.split(','))
+        .map(|encoding| encoding.trim().to_ascii_lowercase())
+        .collect::<Vec<String>>();
+    let lengths = headers
+        .get_all("content-length")
+        .flat_map(|value| value.split(','))
+        .map(str::trim)
+        .collect::<Vec<&str>>();
+
+    if !encodings.is_empty() {
+        // A request with both could be framed differently by a proxy in front of us
+        if !lengths.is_empty() {
+            return Err(malformed(
+                "a request must not have both Content-Length and Transfer-Encoding",
+            ));
+        }
+        if encodings != ["chunked"] {
+            return Err(ParseError::UnsupportedTransferEncoding(
+                encodings.join(", "),
+            ));
+        }
+        return read_chunked(reader, header_budget, limits);
+    }
+
+    let Some(&length) = lengths.first() else {
+        return Ok(vec![]);
+    };
+    if lengths.iter().any(|&other| other != length)
+        || length.is_empty()
+        || !length.bytes().all(|b| b.is_ascii_digit())
+    {
+        return Err(malformed("Content-Length is not a single number"));
+    }
+    let length = match length.parse::<usize>() {
+        Ok(length) if length <= limits.max_body => length,
+        _ => return Err(ParseError::BodyTooLarge),
+    };
+    let mut body = vec![0; length];
+    reader.read_exact(&mut body)?;
+    Ok(body)
+}
+
+/// Read a chunked body: chunks of a hex size line and that many bytes, up to a chunk of size 0,
+/// then trailer fields, which are skipped
+fn read_chunked<R: BufRead>(
+    reader: &mut R,
+    header_budget: &mut usize,
+    limits: &Limits,
+) -> Result<Vec<u8>, ParseError> {
+    let too_long = || malformed("a chunk size line is too long");
+    let mut body = Vec::new();
+    loop {
+        let line = read_line(reader, MAX_CHUNK_LINE, too_long())?
+            .ok_or_else(|| malformed("the request ended before the last chunk"))?;
+        // Chunk extensions after a `;` carry nothing we use
+        let size = line.split(';').next().unwrap_or_default().trim();
+        if size.is_empty() || !size.bytes().all(|b| b.is_ascii_hexdigit()) {
+            return Err(malformed("a chunk size is not a hex number"));
+        }
+        let size = match usize::from_str_radix(size, 16) {
+            Ok(size) if size <= limits.max_body - body.len() => size,
+            _ => return Err(ParseError::BodyTooLarge),
+        };
+        if size == 0 {
+            break;
+        }
+
+        let start = body.len();
+        body.resize(start + size, 0);
+        reader.read_exact(&mut body[start..])?;
+        if read_line(reader, 0, malformed("a chunk is longer than its size"))?.is_none() {
+            return Err(malformed("the request ended before the last chunk"));
+        }
+    }
+
+    read_fields(reader, header_budget, limits.max_headers)?;
+    Ok(body)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(request: &str) -> Result<Request, ParseError> {
+        Request::read_from(&mut request.as_bytes(), &Limits::default())
+    }
+
+    #[test]
+    fn parses_method_target_headers_and_body() {
+        let request = parse(
+            "POST /search/rust%20book?q=a+b&page=2&flag HTTP/1.1\r\n\
+             Host: localhost\r\n\
+             Content-Type:  text/plain \r\n\
+             content-length: 5\r\n\
+             \r\n\
+             hello",
+        )
+        .unwrap();
+
+        assert_eq!(request.method, Method::Post);
+        assert_eq!(request.version, Version::Http11);
+        assert_eq!(request.target, "/search/rust%20book?q=a+b&page=2&flag");
+        assert_eq!(request.path, "/search/rust book");
+        assert_eq!(request.raw_path(), "/search/rust%20book");
+        assert_eq!(request.query_param("q"), Some("a b"));
+        assert_eq!(request.query_param("page"), Some("2"));
+        assert_eq!(request.query_param("flag"), Some(""));
+        assert_eq!(request.header("CONTENT-TYPE"), Some("text/plain"));
+        assert_eq!(request.body, b"hello");
+    }
+
+    #[test]
+    fn reads_a_chunked_body_and_stops_after_it() {
+        let mut input = "POST /upload HTTP/1.1\r\n\
+                         Host: localhost\r\n\
+                         Transfer-Encoding: chunked\r\n\
+                         \r\n\
+                         5;name=value\r\nhello\r\n\
+                         7\r\n, world\r\n\
+                         0\r\n\
+                         Checksum: none\r\n\
+                         \r\n\
+                         GET / HTTP/1.1\r\n"
+            .as_bytes();
+        let request = Request::read_from(&mut input, &Limits::default()).unwrap();
+        assert_eq!(request.body, b"hello, world");
+        assert_eq!(input, b"GET / HTTP/1.1\r\n");
+
+        // The CRLF some clients send after a body is skipped
+        assert!(parse("\r\n\r\nGET / HTTP/1.1\r\nHost: a\r\n\r\n").is_ok());
+    }
+
+    #[test]
+    fn malformed_requests_are_bad_requests() {
+        for request in [
+            "GET /\r\n\r\n",
+            "GET  / HTTP/1.1\r\nHost: a\r\n\r\n",
+            "GET / HTTP/1.1\r\n\r\n",
+            "GET / HTTP/1.1\r\nHost a\r\n\r\n",
+            "GET / HTTP/1.1\r\nHost: a\r\n  folded\r\n\r\n",
+            "GET /a%zz HTTP/1.1\r\nHost: a\r\n\r\n",
+            "GET relative HTTP/1.1\r\nHost: a\r\n\r\n",
+            "GET / HTTP/1.1\r\nHost: a\r\nContent-Length: 3, 4\r\n\r\nabcd",
+            "GET / HTTP/1.1\r\nHost: a\r\nContent-Length: -1\r\n\r\n",
+            "POST / HTTP/1.1\r\nHost: a\r\nContent-Length: 5\r\n\r\nabc",
+            "POST / HTTP/1.1\r\nHost: a\r\nContent-Length: 3\r\nTransfer-Encoding: chunked\r\n\r\n",
+            "POST / HTTP/1.1\r\nHost: a\r\nTransfer-Encoding: chunked\r\n\r\nxyz\r\n",
+            "POST / HTTP/1.1\r\nHost: a\r\nTransfer-Encoding: chunked\r\n\r\n3\r\nabcdef\r\n0\r\n\r\n",
+            "GET / HTTP/1.1\r\nHost: a",
+            "\r\n\r\n\r\nGET / HTTP/1.1\r\nHost: a\r\n\r\n",
+        ] {
+            let error = parse(request).unwrap_err();
+            assert_eq!(error.status(), Some(Status::BadRequest), "{:?}: {}", request, error);
+        }
+    }
+
+    #[test]
+    fn unsupported_requests_get_their_own_status() {
+        let status = |request: &str| parse(request).unwrap_err().status();
+        assert_eq!(
+            status("BREW /pot HTTP/1.1\r\nHost: a\r\n\r\n"),
+            Some(Status::NotImplemented)
+        );
+        assert_eq!(
+            status("GET / HTTP/2.0\r\nHost: a\r\n\r\n"),
+            Some(Status::HttpVersionNotSupported)
+        );
+        assert_eq!(
+            status("POST / HTTP/1.1\r\nHost: a\r\nTransfer-Encoding: gzip\r\n\r\n"),
+            Some(Status::NotImplemented)
+        );
+        assert_eq!(status(""), None);
+    }
+
+    #[test]
+    fn limits_are_enforced() {
+        let limits = Limits {
+            max_request_line: 32,
+            max_header_bytes: 64,
+            max_headers: 3,
+            max_body: 8,
+        };
+        let status = |request: String| {
+            Request::read_from(&mut request.as_bytes(), &limits)
+                .unwrap_err()
+                .status()
+        };
+
+        let long_path = format!("GET /{} HTTP/1.1\r\nHost: a\r\n\r\n", "a".repeat(32));
+        assert_eq!(status(long_path), Some(Status::UriTooLong));
+        let long_header = format!("GET / HTTP/1.1\r\nHost: {}\r\n\r\n", "a".repeat(64));
+        assert_eq!(
+            status(long_header),
+            Some(Status::RequestHeaderFieldsTooLarge)
+        );
+        let many_headers = "GET / HTTP/1.1\r\nHost: a\r\nA: 1\r\nB: 2\r\nC: 3\r\n\r\n";
+        assert_eq!(
+            status(many_headers.to_string()),
+            Some(Status::RequestHeaderFieldsTooLarge)
+        );
+        let large_body = "POST / HTTP/1.1\r\nHost: a\r\nContent-Length: 9\r\n\r\n123456789";
+        assert_eq!(
+            status(large_body.to_string()),
+            Some(Status::ContentTooLarge)
+        );
+        let large_chunks = "POST / HTTP/1.1\r\nHost: a\r\nTransfer-Encoding: chunked\r\n\r\n\
+                            5\r\n12345\r\n5\r\n67890\r\n0\r\n\r\n";
+        assert_eq!(
+            status(large_chunks.to_string()),
+            Some(Status::ContentTooLarge)
+        );
+    }
+}
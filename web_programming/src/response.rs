@@ -0,0 +1,376 @@
+use std::fmt;
+use std::io::{self, BufWriter, Read, Write};
+use std::time::SystemTime;
+
+use crate::date;
+use crate::headers::Headers;
+use crate::request::{Method, Request, Version};
+
+/// Status codes the server answers with
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Status {
+    Ok,
+    NoContent,
+    PartialContent,
+    MovedPermanently,
+    NotModified,
+    BadRequest,
+    Forbidden,
+    NotFound,
+    MethodNotAllowed,
+    RequestTimeout,
+    ContentTooLarge,
+    UriTooLong,
+    RangeNotSatisfiable,
+    RequestHeaderFieldsTooLarge,
+    InternalServerError,
+    NotImplemented,
+    ServiceUnavailable,
+    HttpVersionNotSupported,
+}
+
+impl Status {
+    pub fn code(self) -> u16 {
+        match self {
+            Status::Ok => 200,
+            Status::NoContent => 204,
+            Status::PartialContent => 206,
+            Status::MovedPermanently => 301,
+            Status::NotModified => 304,
+            Status::BadRequest => 400,
+            Status::Forbidden => 403,
+            Status::NotFound => 404,
+            Status::MethodNotAllowed => 405,
+            Status::RequestTimeout => 408,
+            Status::ContentTooLarge => 413,
+            Status::UriTooLong => 414,
+            Status::RangeNotSatisfiable => 416,
+            Status::RequestHeaderFieldsTooLarge => 431,
+            Status::InternalServerError => 500,
+            Status::NotImplemented => 501,
+            Status::ServiceUnavailable => 503,
+            Status::HttpVersionNotSupported => 505,
+        }
+    }
+
+    pub fn reason(self) -> &'static str {
+        match self {
+            Status::Ok => "OK",
+            Status::NoContent => "No Content",
+            Status::PartialContent => "Partial Content",
+            Status::MovedPermanently => "Moved Permanently",
+            Status::NotModified => "Not Modified",
+            Status::BadRequest => "Bad Request",
+            Status::Forbidden => "Forbidden",
+            Status::NotFound => "Not Found",
+            Status::MethodNotAllowed => "Method Not Allowed",
+            Status::RequestTimeout => "Request Timeout",
+            Status::ContentTooLarge => "Content Too Large",
+            Status::UriTooLong => "URI Too Long",
+            Status::RangeNotSatisfiable => "Range Not Satisfiable",
+            Status::RequestHeaderFieldsTooLarge => "Request Header Fields Too Large",
+            Status::InternalServerError => "Internal Server Error",
+            Status::NotImplemented => "Not Implemented",
+            Status::ServiceUnavailable => "Service Unavailable",
+            Status::HttpVersionNotSupported => "HTTP Version Not Supported",
+        }
+    }
+
+    /// 204 and 304 responses never have a body
+    pub fn allows_body(self) -> bool {
+        !matches!(self, Status::NoContent | Status::NotModified)
+    }
+}
+
+impl fmt::Display for Status {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} {}", self.code(), self.reason())
+    }
+}
+
+/// Body of a response
+pub enum Body {
+    Bytes(Vec<u8>),
+    /// Read while the response is written, so it is never held in memory in full. Without a
+    /// known length it is sent chunked to HTTP/1.1 clients.
+    Stream {
+        reader: Box<dyn Read + Send>,
+        length: Option<u64>,
+    },
+}
+
+impl fmt::Debug for Body {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Body::Bytes(bytes) => f.debug_tuple("Bytes").field(&bytes.len()).finish(),
+            Body::Stream { length, .. } => {
+                f.debug_struct("Stream").field("length", length).finish()
+            }
+        }
+    }
+}
+
+/// An HTTP response. `write_to` adds the headers every response needs: `Date`,
+/// `Content-Length` (or chunked `Transfer-Encoding`), `Content-Type` for a body without one,
+/// and `Connection: close`, since one request is served per connection.
+#[derive(Debug)]
+pub struct Response {
+    pub status: Status,
+    pub headers: Headers,
+    pub body: Body,
+}
+
+/// Size of the chunks a streamed body is read and sent in
+const CHUNK_SIZE: usize = 8 * 1024;
+
+impl Response {
+    /// A response without a body
+    pub fn new(status: Status) -> Self {
+        Self {
+            status,
+            headers: Headers::new(),
+            body: Body::Bytes(vec![]),
+        }
+    }
+
+    pub fn text(status: Status, text: impl Into<String>) -> Self {
+        Self::new(status)
+            .header("Content-Type", "text/plain; charset=utf-8")
+            .body(text.into().into_bytes())
+    }
+
+    pub fn html(status: Status, html: impl Into<String>) -> Self {
+        Self::new(status)
+            .header("Content-Type", "text/html; charset=utf-8")
+            .body(html.into().into_bytes())
+    }
+
+    /// Set header `name`, replacing any value it had. A value with a control character, e.g.
+    /// a CR or LF that would end the header early, is dropped and leaves the header unset.
+    pub fn header(mut self, name: &str, value: impl Into<String>) -> Self {
+        let value = value.into();
+        if is_field_value(&value) {
+            self.headers.insert(name, value);
+        } else {
+            self.headers.remove(name);
+        }
+        self
+    }
+
+    pub fn body(mut self, body: Vec<u8>) -> Self {
+        self.body = Body::Bytes(body);
+        self
+    }
+
+    /// Stream the body from `reader`. `length` must be the number of bytes it yields, if known.
+    pub fn stream(mut self, reader: impl Read + Send + 'static, length: Option<u64>) -> Self {
+        self.body = Body::Stream {
+            reader: Box::new(reader),
+            length,
+        };
+        self
+    }
+
+    /// Write the response to `request` to `out`. The body is left out for a HEAD request,
+    /// and for a status that has none. Without a request (it could not be read) the response
+    /// is written as to an HTTP/1.1 GET.
+    pub fn write_to(self, out: &mut dyn Write, request: Option<&Request>) -> io::Result<()> {
+        let (method, version) = request.map_or((Method::Get, Version::Http11), |request| {
+            (request.method, request.version)
+        });
+        let Response {
+            status,
+            mut headers,
+            body,
+        } = self;
+
+        headers.insert("Date", date::format(SystemTime::now()));
+        headers.insert("Connection", "close");
+        let mut chunked = false;
+        if status.allows_body() {
+            let length = match &body {
+                Body::Bytes(bytes) => Some(bytes.len() as u64),
+                Body::Stream { length, .. } => *length,
+            };
+            headers.remove("Transfer-Encoding");
+            match length {
+                Some(length) => headers.insert("Content-Length", length.to_string()),
+                None => {
+                    headers.remove("Content-Length");
+                    // HTTP/1.0 clients read a body of unknown length up to the end of the
+                    // connection instead
+                    if version == Version::Http11 {
+                        headers.insert("Transfer-Encoding", "chunked");
+                        chunked = true;
+                    }
+                }
+            }
+            if length != Some(0) && !headers.contains("Content-Type") {
+                headers.insert("Content-Type", "application/octet-stream");
+            }
+        }
+
+        // `headers` can be changed without `header`, nothing is sent if they cannot be written
+        if let Some((name, _)) = headers.iter().find(|(_, value)| !is_field_value(value)) {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("Header {} has control characters", name),
+            ));
+        }
+
+        let mut out = BufWriter::new(out);
+        write!(out, "HTTP/1.1 {}\r\n", status)?;
+        for (name, value) in headers.iter() {
+            write!(out, "{}: {}\r\n", name, value)?;
+        }
+        out.write_all(b"\r\n")?;
+
+        if status.allows_body() && method != Method::Head {
+            match body {
+                Body::Bytes(bytes) => out.write_all(&bytes)?,
+                Body::Stream { reader, length } => {
+                    write_stream(&mut out, reader, length, chunked)?;
+                }
+            }
+        }
+        out.flush()
+    }
+}
+
+/// Whether `value` can be sent as a header value: no control characters other than tabs
+fn is_field_value(value: &str) -> bool {
+    !value.chars().any(|c| c.is_control() && c != '\t')
+}
+
+fn write_stream(
+    out: &mut impl Write,
+    mut reader: Box<dyn Read + Send>,
+    length: Option<u64>,
+    chunked: bool,
+) -> io::Result<()> {
+    if let Some(length) = length {
+        // Sending more than Content-Length says would corrupt the connection
+        let copied = io::copy(&mut reader.by_ref().take(length), out)?;
+        if copied < length {
+            return Err(io::Error::new(
+                io::ErrorKind::UnexpectedEof,
+                "The body is shorter than its Content-Length",
+            ));
+        }
+        return Ok(());
+    }
+    if !chunked {
+        io::copy(&mut reader, out)?;
+        return Ok(());
+    }
+
+    let mut buffer = vec![0; CHUNK_SIZE];
+    loop {
+        let read = match reader.read(&mut buffer) {
+            Ok(0) => break,
+            Ok(read) => read,
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+            Err(e) => return Err(e),
+        };
+        write!(out, "{:X}\r\n", read)?;
+        out.write_all(&buffer[..read])?;
+        out.write_all(b"\r\n")?;
+    }
+    out.write_all(b"0\r\n\r\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::request::Limits;
+
+    fn request(text: &str) -> Request {
+        Request::read_from(&mut text.as_bytes(), &Limits::default()).unwrap()
+    }
+
+    fn written(response: Response, request: Option<&Request>) -> String {
+        let mut out = vec![];
+        response.write_to(&mut out, request).unwrap();
+        String::from_utf8(out).unwrap()
+    }
+
+    #[test]
+    fn adds_length_type_date_and_connection() {
+        let response = written(Response::html(Status::Ok, "<p>Hi</p>"), None);
+        let (head, body) = response.split_once("\r\n\r\n").unwrap();
+        let mut lines = head.split("\r\n");
+
+        assert_eq!(lines.next(), Some("HTTP/1.1 200 OK"));
+        let headers = lines.collect::<Vec<&str>>();
+        assert!(headers.contains(&"Content-Type: text/html; charset=utf-8"));
+        assert!(headers.contains(&"Content-Length: 9"));
+        assert!(headers.contains(&"Connection: close"));
+        assert!(headers
+            .iter()
+            .any(|line| line.starts_with("Date: ") && line.ends_with(" GMT")));
+        assert_eq!(body, "<p>Hi</p>");
+    }
+
+    #[test]
+    fn head_responses_have_headers_but_no_body() {
+        let head = request("HEAD / HTTP/1.1\r\nHost: a\r\n\r\n");
+        let response = written(Response::text(Status::Ok, "hello"), Some(&head));
+        assert!(response.contains("Content-Length: 5\r\n"));
+        assert!(response.ends_with("\r\n\r\n"));
+    }
+
+    #[test]
+    fn not_modified_has_no_body_or_length() {
+        let response = written(Response::new(Status::NotModified).body(b"x".to_vec()), None);
+        assert!(response.starts_with("HTTP/1.1 304 Not Modified\r\n"));
+        assert!(!response.contains("Content-Length"));
+        assert!(response.ends_with("\r\n\r\n"));
+    }
+
+    #[test]
+    fn streams_of_unknown_length_are_chunked() {
+        let data = vec![b'a'; CHUNK_SIZE + 10];
+        let response = Response::new(Status::Ok).stream(io::Cursor::new(data), None);
+        let get = request("GET / HTTP/1.1\r\nHost: a\r\n\r\n");
+        let text = written(response, Some(&get));
+        let (head, body) = text.split_once("\r\n\r\n").unwrap();
+
+        assert!(head.contains("Transfer-Encoding: chunked"));
+        assert!(!head.contains("Content-Length"));
+        let expected = format!(
+            "2000\r\n{}\r\nA\r\n{}\r\n0\r\n\r\n",
+            "a".repeat(CHUNK_SIZE),
+            "a".repeat(10)
+        );
+        assert_eq!(body, expected);
+    }
+
+    #[test]
+    fn streams_of_known_length_are_sent_as_is() {
+        let response = Response::new(Status::Ok)
+            .header("Content-Type", "image/png")
+            .stream(io::Cursor::new(vec![1, 2, 3]), Some(3));
+        let mut out = vec![];
+        response.write_to(&mut out, None).unwrap();
+        assert!(out.ends_with(b"Content-Length: 3\r\n\r\n\x01\x02\x03"));
+    }
+
+    #[test]
+    fn header_values_cannot_end_the_header() {
+        let response = Response::new(Status::Ok)
+            .header("Location", "/a")
+            .header("Location", "/a\r\nSet-Cookie: b=c");
+        assert_eq!(response.headers.get("Location"), None);
+        assert_eq!(response.headers.get("Set-Cookie"), None);
+    }
+
+    #[test]
+    fn headers_with_control_characters_are_not_written() {
+        let mut response = Response::new(Status::Ok);
+        response.headers.insert("X-Note", "a\nb");
+        let mut out = vec![];
+        let error = response.write_to(&mut out, None).unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::InvalidInput);
+        assert!(out.is_empty());
+    }
+}
@@ -0,0 +1,368 @@
+use crate::request::{percent_decode, Method, Request};
+use crate::response::{Response, Status};
+
+/// Answers a request, given the parameters its route extracted from the path
+pub type Handler = Box<dyn Fn(&Request, &Params) -> Response + Send + Sync + 'static>;
+
+/// Path parameters extracted by a route, e.g. `id` for `/users/:id`
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Params(Vec<(String, String)>);
+
+impl Params {
+    pub fn get(&self, name: &str) -> Option<&str> {
+        self.0
+            .iter()
+            .find(|(key, _)| key == name)
+            .map(|(_, value)| value.as_str())
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&str, &str)> {
+        self.0
+            .iter()
+            .map(|(name, value)| (name.as_str(), value.as_str()))
+    }
+}
+
+/// One `/`-separated part of a route pattern. The order is how specific a segment is: a
+/// literal is preferred over a parameter, and a parameter over a wildcard.
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
+enum Segment {
+    Literal(String),
+    /// `:name` matches one segment
+    Param(String),
+    /// `*name` matches the rest of the path, slashes included. Only last in a pattern.
+    Wildcard(String),
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Pattern(Vec<Segment>);
+
+impl Pattern {
+    /// Panics if `pattern` is not a valid pattern, routes are set up by the program itself
+    fn parse(pattern: &str) -> Self {
+        let Some(rest) = pattern.strip_prefix('/') else {
+            panic!("Route pattern {} does not start with /", pattern);
+        };
+        let parts = rest.split('/').collect::<Vec<&str>>();
+        let mut names = vec![];
+        let segments = parts
+            .iter()
+            .enumerate()
+            .map(|(index, part)| {
+                let (segment, name) = if let Some(name) = part.strip_prefix(':') {
+                    (Segment::Param(name.to_string()), name)
+                } else if let Some(name) = part.strip_prefix('*') {
+                    assert!(
+                        index == parts.len() - 1,
+                        "The wildcard of route pattern {} must be its last segment",
+                        pattern
+                    );
+                    (Segment::Wildcard(name.to_string()), name)
+                } else {
+                    return Segment::Literal(part.to_string());
+                };
+                assert!(
+                    !name.is_empty() && !names.contains(&name),
+                    "Route pattern {} has an unnamed or repeated parameter",
+                    pattern
+                );
+                names.push(name);
+                segment
+            })
+            .collect();
+        Self(segments)
+    }
+
+    /// The parameters of `path` if it matches. `path` is still percent-encoded: it is split
+    /// into segments before they are decoded, so an encoded `/` stays inside its segment.
+    fn matches(&self, path: &str) -> Option<Params> {
+        let mut parts = path
+            .strip_prefix('/')?
+            .split('/')
+            .map(|part| percent_decode(part, false))
+            .collect::<Option<Vec<String>>>()?
+            .into_iter();
+        let mut params = vec![];
+        for segment in &self.0 {
+            match segment {
+                Segment::Literal(literal) => {
+                    if parts.next()? != *literal {
+                        return None;
+                    }
+                }
+                Segment::Param(name) => {
+                    let value = parts.next().filter(|value| !value.is_empty())?;
+                    params.push((name.clone(), value));
+                }
+                Segment::Wildcard(name) => {
+                    let rest = parts.by_ref().collect::<Vec<String>>();
+                    if rest.is_empty() {
+                        return None;
+                    }
+                    params.push((name.clone(), rest.join("/")));
+                }
+            }
+        }
+        match parts.next() {
+            Some(_) => None,
+            None => Some(Params(params)),
+        }
+    }
+}
+
+struct Route {
+    method: Method,
+    pattern: Pattern,
+    handler: Handler,
+}
+
+/// What a request maps to
+pub enum Match<'a> {
+    Found(&'a Handler, Params),
+    /// No route has the path
+    NotFound,
+    /// Routes have the path, but for other methods: the ones listed
+    MethodNotAllowed(Vec<Method>),
+}
+
+/// Maps a method and a path to a handler.
+///
+/// Patterns are paths whose segments can be parameters: `:name` matches any one segment,
+/// and `*name`, only as the last segment, the rest of the path. When several patterns match,
+/// the most specific one wins, compared segment by segment: `/users/me` before `/users/:id`
+/// before `/users/*rest`.
+///
+/// A HEAD request is handled by the GET route of its path if there is no HEAD route, the body
+/// is left out when the response is written. `dispatch` answers a path that no route has with
+/// the `not_found` handler, or a plain 404, and a path that has routes for other methods with
+/// 405 and an `Allow` header.
+#[derive(Default)]
+pub struct Router {
+    routes: Vec<Route>,
+    not_found: Option<Handler>,
+}
+
+impl Router {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Handle `method` requests for paths matching `pattern`. A route added for the same method
+    /// and pattern as an earlier one replaces it.
+    ///
+    /// # Panics
+    ///
+    /// If `pattern` does not start with `/`, has a wildcard before its last segment, or has
+    /// an unnamed or repeated parameter.
+    pub fn route<F>(mut self, method: Method, pattern: &str, handler: F) -> Self
+    where
+        F: Fn(&Request, &Params) -> Response + Send + Sync + 'static,
+    {
+        let pattern = Pattern::parse(pattern);
+        self.routes
+            .retain(|route| route.method != method || route.pattern != pattern);
+        self.routes.push(Route {
+            method,
+            pattern,
+            handler: Box::new(handler),
+        });
+        self
+    }
+
+    pub fn get<F>(self, pattern: &str, handler: F) -> Self
+    where
+        F: Fn(&Request, &Params) -> Response + Send + Sync + 'static,
+    {
+        self.route(Method::Get, pattern, handler)
+    }
+
+    pub fn post<F>(self, pattern: &str, handler: F) -> Self
+    where
+        F: Fn(&Request, &Params) -> Response + Send + Sync + 'static,
+    {
+        self.route(Method::Post, pattern, handler)
+    }
+
+    /// Handle the requests whose path no route has, instead of a plain 404
+    pub fn not_found<F>(mut self, handler: F) -> Self
+    where
+        F: Fn(&Request, &Params) -> Response + Send + Sync + 'static,
+    {
+        self.not_found = Some(Box::new(handler));
+        self
+    }
+
+    /// The route for `method` and `path`, the path as sent, still percent-encoded
+    pub fn lookup(&self, method: Method, path: &str) -> Match<'_> {
+        let matching = self
+            .routes
+            .iter()
+            .filter_map(|route| Some((route, route.pattern.matches(path)?)))
+            .collect::<Vec<(&Route, Params)>>();
+        if matching.is_empty() {
+            return Match::NotFound;
+        }
+
+        let best = |method| {
+            matching
+                .iter()
+                .filter(move |(route, _)| route.method == method)
+                .min_by(|(a, _), (b, _)| a.pattern.0.cmp(&b.pattern.0))
+        };
+        let found = match method {
+            Method::Head => best(Method::Head).or_else(|| best(Method::Get)),
+            _ => best(method),
+        };
+        match found {
+            Some((route, params)) => Match::Found(&route.handler, params.clone()),
+            None => {
+                let mut allowed = matching
+                    .iter()
+                    .map(|(route, _)| route.method)
+                    .collect::<Vec<Method>>();
+                if allowed.contains(&Method::Get) {
+                    allowed.push(Method::Head);
+                }
+                allowed.sort_by_key(|method| Method::ALL.iter().position(|m| m == method));
+                allowed.dedup();
+                Match::MethodNotAllowed(allowed)
+            }
+        }
+    }
+
+    pub fn dispatch(&self, request: &Request) -> Response {
+        match self.lookup(request.method, request.raw_path()) {
+            Match::Found(handler, params) => handler(request, &params),
+            Match::NotFound => match &self.not_found {
+                Some(handler) => handler(request, &Params::default()),
+                None => Response::text(Status::NotFound, Status::NotFound.to_string()),
+            },
+            Match::MethodNotAllowed(allowed) => {
+                let allow = allowed
+                    .iter()
+                    .map(|method| method.as_str())
+                    .collect::<Vec<&str>>()
+                    .join(", ");
+                Response::text(
+                    Status::MethodNotAllowed,
+                    Status::MethodNotAllowed.to_string(),
+                )
+                .header("Allow", allow)
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::request::Limits;
+    use crate::response::Body;
+
+    fn request(method: &str, target: &str) -> Request {
+        let text = format!("{} {} HTTP/1.1\r\nHost: localhost\r\n\r\n", method, target);
+        Request::read_from(&mut text.as_bytes(), &Limits::default()).unwrap()
+    }
+
+    /// A router whose handlers answer with the name of their route and its parameters
+    fn router() -> Router {
+        let named = |name: &'static str| {
+            move |_: &Request, params: &Params| {
+                let mut text = name.to_string();
+                for (key, value) in params.iter() {
+                    text.push_str(&format!(" {}={}", key, value));
+                }
+                Response::text(Status::Ok, text)
+            }
+        };
+        Router::new()
+            .get("/", named("index"))
+            .get("/users/:id", named("user"))
+            .get("/users/me", named("me"))
+            .post("/users/:id", named("update"))
+            .get("/users/:id/posts/:post", named("post"))
+            .get("/static/*path", named("static"))
+    }
+
+    /// Status and body of the response
+    fn respond(router: &Router, method: &str, target: &str) -> (Status, String) {
+        let response = router.dispatch(&request(method, target));
+        let Body::Bytes(body) = response.body else {
+            panic!("Routes answer with bytes");
+        };
+        (response.status, String::from_utf8(body).unwrap())
+    }
+
+    #[test]
+    fn handlers_get_the_parameters_of_their_route() {
+        let router = router();
+        assert_eq!(respond(&router, "GET", "/").1, "index");
+        assert_eq!(respond(&router, "GET", "/users/42").1, "user id=42");
+        assert_eq!(respond(&router, "POST", "/users/42").1, "update id=42");
+        assert_eq!(
+            respond(&router, "GET", "/users/42/posts/7?draft=1").1,
+            "post id=42 post=7"
+        );
+        assert_eq!(
+            respond(&router, "GET", "/static/css/site.css").1,
+            "static path=css/site.css"
+        );
+    }
+
+    #[test]
+    fn parameters_are_decoded_after_the_path_is_split() {
+        let router = router();
+        assert_eq!(respond(&router, "GET", "/users/a%2Fb").1, "user id=a/b");
+        assert_eq!(respond(&router, "GET", "/users/a%20b").1, "user id=a b");
+        assert_eq!(
+            respond(&router, "GET", "/static/a%2Fb/c").1,
+            "static path=a/b/c"
+        );
+        assert_eq!(respond(&router, "GET", "/users%2F42").0, Status::NotFound);
+    }
+
+    #[test]
+    fn most_specific_route_wins() {
+        assert_eq!(respond(&router(), "GET", "/users/me").1, "me");
+    }
+
+    #[test]
+    fn unknown_paths_are_not_found() {
+        let router = router();
+        for target in [
+            "/nowhere",
+            "/users",
+            "/users/",
+            "/users/42/extra",
+            "/static",
+        ] {
+            assert_eq!(
+                respond(&router, "GET", target).0,
+                Status::NotFound,
+                "{}",
+                target
+            );
+        }
+
+        let router = router.not_found(|_, _| Response::text(Status::NotFound, "custom"));
+        assert_eq!(respond(&router, "GET", "/nowhere").1, "custom");
+    }
+
+    #[test]
+    fn wrong_method_lists_the_allowed_ones() {
+        let response = router().dispatch(&request("DELETE", "/users/42"));
+        assert_eq!(response.status, Status::MethodNotAllowed);
+        assert_eq!(response.headers.get("Allow"), Some("GET, HEAD, POST"));
+    }
+
+    #[test]
+    fn head_requests_use_the_get_route() {
+        assert_eq!(respond(&router(), "HEAD", "/users/42").1, "user id=42");
+    }
+
+    #[test]
+    #[should_panic(expected = "must be its last segment")]
+    fn wildcard_must_be_last() {
+        Router::new().get("/files/*path/raw", |_, _| Response::new(Status::Ok));
+    }
+}
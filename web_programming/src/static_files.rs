@@ -0,0 +1,461 @@
+use std::fs::{self, File, Metadata};
+use std::io::{Seek, SeekFrom};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use crate::date;
+use crate::request::Request;
+use crate::response::{Response, Status};
+
+/// Content types by file extension, anything else is `application/octet-stream`
+const MIME_TYPES: &[(&str, &str)] = &[
+    ("html", "text/html; charset=utf-8"),
+    ("htm", "text/html; charset=utf-8"),
+    ("css", "text/css; charset=utf-8"),
+    ("js", "text/javascript; charset=utf-8"),
+    ("mjs", "text/javascript; charset=utf-8"),
+    ("json", "application/json"),
+    ("txt", "text/plain; charset=utf-8"),
+    ("md", "text/markdown; charset=utf-8"),
+    ("csv", "text/csv; charset=utf-8"),
+    ("xml", "application/xml"),
+    ("svg", "image/svg+xml"),
+    ("png", "image/png"),
+    ("jpg", "image/jpeg"),
+    ("jpeg", "image/jpeg"),
+    ("gif", "image/gif"),
+    ("webp", "image/webp"),
+    ("avif", "image/avif"),
+    ("ico", "image/x-icon"),
+    ("woff", "font/woff"),
+    ("woff2", "font/woff2"),
+    ("ttf", "font/ttf"),
+    ("otf", "font/otf"),
+    ("pdf", "application/pdf"),
+    ("wasm", "application/wasm"),
+    ("zip", "application/zip"),
+    ("gz", "application/gzip"),
+    ("mp3", "audio/mpeg"),
+    ("wav", "audio/wav"),
+    ("mp4", "video/mp4"),
+    ("webm", "video/webm"),
+];
+
+/// Content type of a file, by its extension
+pub fn mime_type(path: &Path) -> &'static str {
+    path.extension()
+        .and_then(|extension| extension.to_str())
+        .and_then(|extension| {
+            MIME_TYPES
+                .iter()
+                .find(|(known, _)| known.eq_ignore_ascii_case(extension))
+        })
+        .map_or("application/octet-stream", |(_, mime)| mime)
+}
+
+/// Serves the files under a directory.
+///
+/// Paths with `..` segments, and files that resolve outside the directory through a symbolic
+/// link, are refused with 403. A directory is answered with its first index file that exists.
+/// Files are streamed, never read into memory, with their `Content-Type` by extension.
+///
+/// Every file is sent with an `ETag` and a `Last-Modified` date. A request whose
+/// `If-None-Match` or `If-Modified-Since` shows the client has the current file gets a 304
+/// without a body. A single `Range` of bytes is answered with 206 and that part of the file,
+/// or 416 if it lies past the end. Several ranges get the whole file.
+#[derive(Debug, Clone)]
+pub struct StaticFiles {
+    root: PathBuf,
+    index_files: Vec<String>,
+}
+
+impl StaticFiles {
+    /// Serve the files under `root`, with `index.html` as the index file of directories
+    pub fn new(root: impl Into<PathBuf>) -> Self {
+        Self {
+            root: root.into(),
+            index_files: vec!["index.html".to_string()],
+        }
+    }
+
+    /// The files served for a directory, the first one that exists wins
+    pub fn index_files(mut self, names: &[&str]) -> Self {
+        self.index_files = names.iter().map(|name| name.to_string()).collect();
+        self
+    }
+
+    /// Answer `request` with the file at `path`, relative to the root, e.g. the `*path`
+    /// parameter of a route like `/static/*path`
+    pub fn serve(&self, request: &Request, path: &str) -> Response {
+        let Some(file) = self.resolve(path) else {
+            return Response::text(Status::Forbidden, Status::Forbidden.to_string());
+        };
+        let Ok(metadata) = fs::metadata(&file) else {
+            return Response::text(Status::NotFound, Status::NotFound.to_string());
+        };
+
+        if metadata.is_dir() {
+            // Relative links in an index file only work from a path ending in a slash
+            if !request.path.ends_with('/') {
+                // Built from the target as sent, a decoded path could hold CR or LF
+                let location = match request.target.split_once('?') {
+                    Some((_, query)) => format!("{}/?{}", request.raw_path(), query),
+                    None => format!("{}/", request.raw_path()),
+                };
+                return Response::new(Status::MovedPermanently)
+                    .header("Location", percent_encode_non_ascii(&location));
+            }
+            return match self.index_file(&file) {
+                Some((index, metadata)) => send_file(request, &index, &metadata),
+                None => Response::text(Status::NotFound, Status::NotFound.to_string()),
+            };
+        }
+        send_file(request, &file, &metadata)
+    }
+
+    /// The file `path` names under the root, `None` if it names one outside of it
+    fn resolve(&self, path: &str) -> Option<PathBuf> {
+        let mut file = self.root.clone();
+        for segment in path.split('/') {
+            match segment {
+                "" | "." => {}
+                ".." => return None,
+                // A backslash or a drive letter could step out of the root on Windows
+                _ if segment.contains(['\\', ':', '\0']) => return None,
+                _ => file.push(segment),
+            }
+        }
+
+        // A symbolic link inside the root may still point outside of it
+        if let (Ok(root), Ok(resolved)) = (self.root.canonicalize(), file.canonicalize()) {
+            if !resolved.starts_with(root) {
+                return None;
+            }
+        }
+        Some(file)
+    }
+
+    fn index_file(&self, directory: &Path) -> Option<(PathBuf, Metadata)> {
+        self.index_files.iter().find_map(|name| {
+            let file = directory.join(name);
+            let metadata = fs::metadata(&file).ok().filter(Metadata::is_file)?;
+            Some((file, metadata))
+        })
+    }
+}
+
+fn send_file(request: &Request, path: &Path, metadata: &Metadata) -> Response {
+    let length = metadata.len();
+    let modified = metadata.modified().ok();
+    let etag = entity_tag(length, modified);
+    let mut response = Response::new(Status::Ok)
+        .header("Content-Type", mime_type(path))
+        .header("ETag", etag.clone())
+        .header("Accept-Ranges", "bytes");
+    if let Some(modified) = modified {
+        response = response.header("Last-Modified", date::format(modified));
+    }
+
+    if is_not_modified(request, &etag, modified) {
+        response.status = Status::NotModified;
+        return response;
+    }
+
+    let range = match request.header("Range") {
+        Some(range) if if_range_matches(request, &etag, modified) => parse_range(range, length),
+        _ => None,
+    };
+    let (start, end) = match range {
+        Some(Ok(range)) => range,
+        Some(Err(())) => {
+            return Response::text(
+                Status::RangeNotSatisfiable,
+                Status::RangeNotSatisfiable.to_string(),
+            )
+            .header("Content-Range", format!("bytes */{}", length));
+        }
+        None => (0, length),
+    };
+    if range.is_some() {
+        response.status = Status::PartialContent;
+        response = response.header(
+            "Content-Range",
+            format!("bytes {}-{}/{}", start, end - 1, length),
+        );
+    }
+
+    let file = File::open(path).and_then(|mut file| {
+        file.seek(SeekFrom::Start(start))?;
+        Ok(file)
+    });
+    match file {
+        Ok(file) => response.stream(file, Some(end - start)),
+        Err(e) => Response::text(
+            Status::InternalServerError,
+            format!("Could not read the file: {}", e),
+        ),
+    }
+}
+
+/// Escape the bytes of `text` that cannot appear as they are in a header value
+fn percent_encode_non_ascii(text: &str) -> String {
+    let mut encoded = String::with_capacity(text.len());
+    for b in text.bytes() {
+        if b.is_ascii_graphic() {
+            encoded.push(b as char);
+        } else {
+            encoded.push_str(&format!("%{:02X}", b));
+        }
+    }
+    encoded
+}
+
+/// A validator that changes whenever the file's size or modification time does
+fn entity_tag(length: u64, modified: Option<SystemTime>) -> String {
+    let modified = modified
+        .and_then(|time| time.duration_since(UNIX_EPOCH).ok())
+        .unwrap_or_default();
+    format!("\"{:x}-{:x}\"", length, modified.as_nanos())
+}
+
+/// HTTP dates have whole seconds, the file's own time has to be compared at that precision
+fn whole_seconds(time: SystemTime) -> SystemTime {
+    let seconds = time
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs();
+    UNIX_EPOCH + Duration::from_secs(seconds)
+}
+
+/// Does the client's copy match? `If-None-Match` decides if present, as it is more precise
+/// than `If-Modified-Since`.
+fn is_not_modified(request: &Request, etag: &str, modified: Option<SystemTime>) -> bool {
+    if let Some(tags) = request.header("If-None-Match") {
+        // Weak comparison: a weak tag matches the strong tag with the same value
+        return tags
+            .split(',')
+            .map(str::trim)
+            .any(|tag| tag == "*" || tag.strip_prefix("W/").unwrap_or(tag) == etag);
+    }
+    match (
+        request.header("If-Modified-Since").and_then(date::parse),
+        modified,
+    ) {
+        (Some(since), Some(modified)) => whole_seconds(modified) <= since,
+        _ => false,
+    }
+}
+
+/// A `Range` only applies if `If-Range`, when sent, still names the current file
+fn if_range_matches(request: &Request, etag: &str, modified: Option<SystemTime>) -> bool {
+    match request.header("If-Range") {
+        None => true,
+        Some(tag) if tag.starts_with('"') => tag == etag,
+        Some(time) => {
+            date::parse(time).is_some_and(|time| modified.map(whole_seconds) == Some(time))
+        }
+    }
+}
+
+/// Parse a `Range` header of a single byte range into the start and end (exclusive) of the
+/// bytes to send. `None` to ignore the header and send the whole file: it is not a byte range
+/// or asks for several. `Err` if the range starts past the end of the file.
+fn parse_range(header: &str, length: u64) -> Option<Result<(u64, u64), ()>> {
+    let range = header.trim().strip_prefix("bytes=")?.trim();
+    if range.contains(',') {
+        return None;
+    }
+    let (first, last) = range.split_once('-')?;
+    let number = |digits: &str| -> Option<Option<u64>> {
+        match digits.trim() {
+            "" => Some(None),
+            digits if digits.bytes().all(|b| b.is_ascii_digit()) => digits.parse().ok().map(Some),
+            _ => None,
+        }
+    };
+
+    let range = match (number(first)?, number(last)?) {
+        // The last `suffix` bytes
+        (None, Some(suffix)) => {
+            if suffix == 0 || length == 0 {
+                return Some(Err(()));
+            }
+            (length.saturating_sub(suffix), length)
+        }
+        (Some(start), last) => {
+            if last.is_some_and(|last| last < start) {
+                return None;
+            }
+            if start >= length {
+                return Some(Err(()));
+            }
+            let end = last.map_or(length, |last| last.saturating_add(1).min(length));
+            (start, end)
+        }
+        (None, None) => return None,
+    };
+    Some(Ok(range))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Read;
+
+    use super::*;
+    use crate::request::Limits;
+    use crate::response::Body;
+
+    /// A directory with a few files, removed when dropped
+    struct Site(PathBuf);
+
+    impl Site {
+        fn new(name: &str) -> Self {
+            let root =
+                std::env::temp_dir().join(format!("static-files-{}-{}", name, std::process::id()));
+            let _ = fs::remove_dir_all(&root);
+            fs::create_dir_all(root.join("docs")).unwrap();
+            fs::write(root.join("index.html"), "<h1>Home</h1>").unwrap();
+            fs::write(root.join("docs/index.html"), "<h1>Docs</h1>").unwrap();
+            fs::write(root.join("logo.png"), [0x89, b'P', b'N', b'G', 0, 0xff]).unwrap();
+            fs::write(root.join("digits.txt"), "0123456789").unwrap();
+            Self(root)
+        }
+    }
+
+    impl Drop for Site {
+        fn drop(&mut self) {
+            let _ = fs::remove_dir_all(&self.0);
+        }
+    }
+
+    fn get(files: &StaticFiles, path: &str, headers: &[(&str, &str)]) -> Response {
+        let mut text = format!("GET /static/{} HTTP/1.1\r\nHost: localhost\r\n", path);
+        for (name, value) in headers {
+            text.push_str(&format!("{}: {}\r\n", name, value));
+        }
+        text.push_str("\r\n");
+        let request = Request::read_from(&mut text.as_bytes(), &Limits::default()).unwrap();
+        files.serve(&request, path)
+    }
+
+    fn body(response: Response) -> Vec<u8> {
+        match response.body {
+            Body::Bytes(bytes) => bytes,
+            Body::Stream { reader, length } => {
+                let mut bytes = vec![];
+                reader
+                    .take(length.unwrap())
+                    .read_to_end(&mut bytes)
+                    .unwrap();
+                bytes
+            }
+        }
+    }
+
+    #[test]
+    fn serves_binary_files_with_their_mime_type() {
+        let site = Site::new("binary");
+        let files = StaticFiles::new(&site.0);
+        let response = get(&files, "logo.png", &[]);
+        assert_eq!(response.status, Status::Ok);
+        assert_eq!(response.headers.get("Content-Type"), Some("image/png"));
+        assert_eq!(body(response), [0x89, b'P', b'N', b'G', 0, 0xff]);
+
+        assert_eq!(get(&files, "missing.css", &[]).status, Status::NotFound);
+        assert_eq!(mime_type(Path::new("site.CSS")), "text/css; charset=utf-8");
+        assert_eq!(mime_type(Path::new("data.bin")), "application/octet-stream");
+    }
+
+    #[test]
+    fn path_traversal_is_refused() {
+        let site = Site::new("traversal");
+        let files = StaticFiles::new(site.0.join("docs"));
+        for path in ["../index.html", "a/../../index.html", "..\\index.html"] {
+            assert_eq!(get(&files, path, &[]).status, Status::Forbidden, "{}", path);
+        }
+    }
+
+    #[test]
+    fn directories_are_served_their_index_file() {
+        let site = Site::new("index");
+        let files = StaticFiles::new(&site.0);
+        assert_eq!(body(get(&files, "", &[])), b"<h1>Home</h1>");
+        assert_eq!(body(get(&files, "docs/", &[])), b"<h1>Docs</h1>");
+
+        let redirect = get(&files, "docs", &[]);
+        assert_eq!(redirect.status, Status::MovedPermanently);
+        assert_eq!(redirect.headers.get("Location"), Some("/static/docs/"));
+
+        // The path is redirected to as it was sent, not decoded
+        let name = "my docs\r\nSet-Cookie a=b";
+        fs::create_dir_all(site.0.join(name)).unwrap();
+        let text = "GET /static/my%20docs%0d%0aSet-Cookie%20a=b?v=\u{e9} HTTP/1.1\r\n\
+                    Host: localhost\r\n\r\n";
+        let request = Request::read_from(&mut text.as_bytes(), &Limits::default()).unwrap();
+        let redirect = files.serve(&request, name);
+        assert_eq!(
+            redirect.headers.get("Location"),
+            Some("/static/my%20docs%0d%0aSet-Cookie%20a=b/?v=%C3%A9")
+        );
+
+        let files = StaticFiles::new(&site.0).index_files(&["default.htm"]);
+        assert_eq!(get(&files, "docs/", &[]).status, Status::NotFound);
+    }
+
+    #[test]
+    fn ranges_are_served_as_partial_content() {
+        let site = Site::new("ranges");
+        let files = StaticFiles::new(&site.0);
+
+        let response = get(&files, "digits.txt", &[("Range", "bytes=2-4")]);
+        assert_eq!(response.status, Status::PartialContent);
+        assert_eq!(response.headers.get("Content-Range"), Some("bytes 2-4/10"));
+        assert_eq!(body(response), b"234");
+
+        assert_eq!(
+            body(get(&files, "digits.txt", &[("Range", "bytes=7-")])),
+            b"789"
+        );
+        assert_eq!(
+            body(get(&files, "digits.txt", &[("Range", "bytes=-2")])),
+            b"89"
+        );
+        assert_eq!(
+            body(get(&files, "digits.txt", &[("Range", "bytes=8-99")])),
+            b"89"
+        );
+
+        let response = get(&files, "digits.txt", &[("Range", "bytes=10-")]);
+        assert_eq!(response.status, Status::RangeNotSatisfiable);
+        assert_eq!(response.headers.get("Content-Range"), Some("bytes */10"));
+
+        // Several ranges, or a stale If-Range, get the whole file
+        let response = get(&files, "digits.txt", &[("Range", "bytes=0-1,4-5")]);
+        assert_eq!(response.status, Status::Ok);
+        let stale = [("Range", "bytes=0-1"), ("If-Range", "\"stale\"")];
+        assert_eq!(get(&files, "digits.txt", &stale).status, Status::Ok);
+    }
+
+    #[test]
+    fn unchanged_files_are_not_modified() {
+        let site = Site::new("caching");
+        let files = StaticFiles::new(&site.0);
+        let response = get(&files, "index.html", &[]);
+        let etag = response.headers.get("ETag").unwrap().to_string();
+        let modified = response.headers.get("Last-Modified").unwrap().to_string();
+
+        let response = get(&files, "index.html", &[("If-None-Match", &etag)]);
+        assert_eq!(response.status, Status::NotModified);
+        assert_eq!(response.headers.get("ETag"), Some(etag.as_str()));
+        let response = get(&files, "index.html", &[("If-Modified-Since", &modified)]);
+        assert_eq!(response.status, Status::NotModified);
+
+        let other = [
+            ("If-None-Match", "\"other\""),
+            ("If-Modified-Since", &modified),
+        ];
+        assert_eq!(get(&files, "index.html", &other).status, Status::Ok);
+        let old = [("If-Modified-Since", "Thu, 01 Jan 1970 00:00:00 GMT")];
+        assert_eq!(get(&files, "index.html", &old).status, Status::Ok);
+    }
+}
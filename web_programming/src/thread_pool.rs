@@ -0,0 +1,179 @@
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::mpsc::{self, Receiver, SyncSender, TrySendError};
+use std::sync::{Arc, Mutex};
+use std::thread::{self, JoinHandle};
+
+type Job = Box<dyn FnOnce() + Send + 'static>;
+
+/// A fixed number of worker threads running jobs from a bounded queue.
+///
+/// A burst of work can not start more threads than `workers`, or hold more than `queue` jobs
+/// waiting for a worker. When both are taken, `execute` waits for room and `try_execute` fails,
+/// so the caller can turn the work away (e.g. with a 503).
+///
+/// Dropping the pool lets the workers finish the queued jobs, then joins them.
+#[derive(Debug)]
+pub struct ThreadPool {
+    workers: Vec<Worker>,
+    sender: Option<SyncSender<Job>>,
+}
+
+impl ThreadPool {
+    /// Start `workers` threads sharing a queue of up to `queue` jobs. A queue of 0 only hands
+    /// a job over when a worker is idle.
+    ///
+    /// # Panics
+    ///
+    /// If `workers` is 0.
+    pub fn new(workers: usize, queue: usize) -> Self {
+        assert!(workers > 0, "A thread pool needs at least one worker");
+
+        let (sender, receiver) = mpsc::sync_channel(queue);
+        let receiver = Arc::new(Mutex::new(receiver));
+        let workers = (0..workers)
+            .map(|id| Worker::new(id, Arc::clone(&receiver)))
+            .collect();
+
+        Self {
+            workers,
+            sender: Some(sender),
+        }
+    }
+
+    pub fn workers(&self) -> usize {
+        self.workers.len()
+    }
+
+    /// Run `job` on a worker, waiting for room in the queue if it is full
+    pub fn execute<F>(&self, job: F)
+    where
+        F: FnOnce() + Send + 'static,
+    {
+        self.sender()
+            .send(Box::new(job))
+            .expect("Workers only stop when the pool is dropped");
+    }
+
+    /// Run `job` on a worker if the queue has room for it, otherwise drop it and return
+    /// `Saturated`
+    pub fn try_execute<F>(&self, job: F) -> Result<(), Saturated>
+    where
+        F: FnOnce() + Send + 'static,
+    {
+        match self.sender().try_send(Box::new(job)) {
+            Ok(()) => Ok(()),
+            Err(TrySendError::Full(_)) => Err(Saturated),
+            Err(TrySendError::Disconnected(_)) => {
+                unreachable!("Workers only stop when the pool is dropped")
+            }
+        }
+    }
+
+    fn sender(&self) -> &SyncSender<Job> {
+        self.sender
+            .as_ref()
+            .expect("The sender is only taken when the pool is dropped")
+    }
+}
+
+impl Drop for ThreadPool {
+    fn drop(&mut self) {
+        // Closing the queue stops every worker once it is empty
+        drop(self.sender.take());
+        for worker in &mut self.workers {
+            if let Some(thread) = worker.thread.take() {
+                thread
+                    .join()
+                    .expect("Workers catch the panics of their jobs");
+            }
+        }
+    }
+}
+
+/// Every worker and queue slot of a `ThreadPool` is taken
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Saturated;
+
+#[derive(Debug)]
+struct Worker {
+    thread: Option<JoinHandle<()>>,
+}
+
+impl Worker {
+    fn new(id: usize, receiver: Arc<Mutex<Receiver<Job>>>) -> Self {
+        let thread = thread::Builder::new()
+            .name(format!("worker-{}", id))
+            .spawn(move || loop {
+                // The lock is released as soon as a job is taken, not held while it runs
+                let job = receiver.lock().unwrap().recv();
+                match job {
+                    // A job that panics must not take its worker down with it
+                    Ok(job) => {
+                        let _ = panic::catch_unwind(AssertUnwindSafe(job));
+                    }
+                    Err(_) => break,
+                }
+            })
+            .expect("Could not start a worker thread");
+        Self {
+            thread: Some(thread),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Barrier;
+    use std::time::Duration;
+
+    use super::*;
+
+    #[test]
+    fn every_job_runs_before_the_pool_is_dropped() {
+        let done = Arc::new(AtomicUsize::new(0));
+        let pool = ThreadPool::new(3, 2);
+        for _ in 0..20 {
+            let done = Arc::clone(&done);
+            pool.execute(move || {
+                thread::sleep(Duration::from_millis(1));
+                done.fetch_add(1, Ordering::SeqCst);
+            });
+        }
+        drop(pool);
+        assert_eq!(done.load(Ordering::SeqCst), 20);
+    }
+
+    #[test]
+    fn saturated_pool_turns_jobs_away() {
+        let pool = ThreadPool::new(2, 1);
+        let started = Arc::new(Barrier::new(3));
+        let release = Arc::new(Barrier::new(3));
+        for _ in 0..2 {
+            let (started, release) = (Arc::clone(&started), Arc::clone(&release));
+            pool.execute(move || {
+                started.wait();
+                release.wait();
+            });
+        }
+        // Both workers are busy, one more job fits in the queue
+        started.wait();
+        assert_eq!(pool.try_execute(|| {}), Ok(()));
+        assert_eq!(pool.try_execute(|| {}), Err(Saturated));
+
+        release.wait();
+    }
+
+    #[test]
+    fn worker_survives_a_panicking_job() {
+        let done = Arc::new(AtomicUsize::new(0));
+        let pool = ThreadPool::new(1, 1);
+        pool.execute(|| panic!("Job failed"));
+        let counter = Arc::clone(&done);
+        pool.execute(move || {
+            counter.fetch_add(1, Ordering::SeqCst);
+        });
+        drop(pool);
+        assert_eq!(done.load(Ordering::SeqCst), 1);
+    }
+}